@@ -0,0 +1,37 @@
+#![feature(test)]
+#![allow(non_snake_case)]
+
+extern crate test;
+
+use rust_vm::codegen::bytecodeGen2;
+use rust_vm::fs::setupFs;
+use rust_vm::lexer::tokenizeSource;
+use rust_vm::parser::parseTokens;
+use rust_vm::std::bootStrapVM;
+use rust_vm::vm::VirtualMachine;
+use test::Bencher;
+
+fn bench(b: &mut Bencher, vm: &mut VirtualMachine, source: &str) {
+    let tokens = tokenizeSource(source).unwrap();
+    let ast = parseTokens(tokens).unwrap();
+    let (ops, locals, _) = bytecodeGen2(ast, &mut vm.functionReturns()).unwrap();
+
+    b.iter(|| vm.eval(ops.clone(), locals.clone()).unwrap());
+}
+
+#[bench]
+fn benchFact(b: &mut Bencher) {
+    let source = include_str!("../src/compiler/fact.vipl").replace("10_000_000", "10_000");
+    bench(b, &mut bootStrapVM(), &source);
+}
+
+#[bench]
+fn benchCounter(b: &mut Bencher) {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/common");
+    let source = include_str!("../src/compiler/counter.vipl")
+        .replace("/home/vasabi/Downloads/vm-rust", dir)
+        .replace("print(", "// print(");
+    let mut vm = bootStrapVM();
+    setupFs(&mut vm);
+    bench(b, &mut vm, &source);
+}
@@ -4,6 +4,8 @@ use std::fmt::{Display, Formatter};
 use std::ops::Index;
 
 use crate::bytecodeChecker::InvalidTypeException;
+use crate::lexer::Location;
+use crate::objects::MapKey;
 use crate::vm::{DataType, Generic, genFunName, MyStr, ObjectMeta, OpCode, VariableMetadata};
 use crate::vm::DataType::{Bool, Char, Object};
 use crate::vm::Generic::Any;
@@ -53,6 +55,17 @@ pub enum Expression {
     ArrayLiteral(Vec<Expression>),
     ArrayIndexing(Box<ArrayAccess>),
     NotExpression(Box<Expression>),
+    MapLiteral(Vec<(Expression, Expression)>),
+    Range {
+        start: Box<Expression>,
+        end: Box<Expression>,
+    },
+    // Array<T>(size, fill)
+    SizedArray {
+        typ: DataType,
+        size: Box<Expression>,
+        fill: Box<Expression>,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -61,12 +74,307 @@ pub struct ArrayAccess {
     pub index: Expression,
 }
 
+impl ArrayAccess {
+    // indexing with range produces copy of the given part
+    pub fn isSlice(
+        &self,
+        typesMapping: &HashMap<MyStr, (DataType, usize)>,
+        functionReturns: &HashMap<MyStr, Option<DataType>>,
+    ) -> Result<bool, Box<dyn Error>> {
+        Ok(self.index.toDataType(typesMapping, functionReturns, None)? == Some(DataType::range()))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct FunctionCall {
     pub name: MyStr,
     pub arguments: Vec<Expression>,
 }
 
+impl FunctionCall {
+    // map, generator and channel functions are generic over their element types so they cant be registered as natives
+    pub fn builtin(
+        &self,
+        argTypes: &[DataType],
+        typeHint: Option<&DataType>,
+    ) -> Result<Option<(Vec<OpCode>, Option<DataType>)>, Box<dyn Error>> {
+        if self.name.as_str() == "chan" && argTypes.is_empty() {
+            let hint = typeHint.ok_or("cannot infer type of channel consider adding type hint")?;
+            return match chanType(hint) {
+                None => Err(Box::new(InvalidTypeException {
+                    expected: DataType::chan(Any),
+                    actual: Some(hint.clone()),
+                })),
+                Some(t) => Ok(Some((vec![OpCode::ChanNew(t)], Some(hint.clone())))),
+            };
+        }
+
+        if let Some(t) = argTypes.first().and_then(chanType) {
+            return match (self.name.as_str(), argTypes.len()) {
+                ("send", 2) => {
+                    if argTypes[1] != t {
+                        return Err(Box::new(InvalidTypeException {
+                            expected: t,
+                            actual: Some(argTypes[1].clone()),
+                        }));
+                    }
+                    Ok(Some((vec![OpCode::ChanSend], None)))
+                }
+                ("recv", 1) => Ok(Some((vec![OpCode::ChanRecv(t.clone())], Some(t)))),
+                _ => Ok(None),
+            };
+        }
+
+        if let Some(t) = argTypes.first().and_then(taskType) {
+            return match (self.name.as_str(), argTypes.len()) {
+                ("join", 1) => Ok(Some((vec![OpCode::Join], t))),
+                _ => Ok(None),
+            };
+        }
+
+        if let Some(t) = argTypes.first().and_then(arrayType) {
+            return match (self.name.as_str(), argTypes.len()) {
+                ("push", 2) => {
+                    if argTypes[1] != t {
+                        return Err(Box::new(InvalidTypeException {
+                            expected: t,
+                            actual: Some(argTypes[1].clone()),
+                        }));
+                    }
+                    Ok(Some((vec![OpCode::ArrayPush(t)], None)))
+                }
+                _ => Ok(None),
+            };
+        }
+
+        if let Some(t) = argTypes.first().and_then(generatorType) {
+            return match (self.name.as_str(), argTypes.len()) {
+                ("next", 1) => Ok(Some((
+                    vec![OpCode::Dup, OpCode::GenResume, OpCode::Pop, OpCode::GenValue(t.clone())],
+                    Some(t),
+                ))),
+                _ => Ok(None),
+            };
+        }
+
+        let (key, _) = match argTypes.first().and_then(mapTypes) {
+            None => return Ok(None),
+            Some(v) => v,
+        };
+
+        let res = match (self.name.as_str(), argTypes.len()) {
+            ("contains", 2) => (vec![OpCode::MapContains], Some(Bool)),
+            ("remove", 2) => (vec![OpCode::MapRemove], None),
+            ("keys", 1) => (
+                vec![OpCode::MapKeys(key.clone())],
+                Some(DataType::arr(Generic::Type(key.clone()))),
+            ),
+            _ => return Ok(None),
+        };
+
+        if let Some(k) = argTypes.get(1) {
+            if *k != key {
+                return Err(Box::new(InvalidTypeException {
+                    expected: key,
+                    actual: Some(k.clone()),
+                }));
+            }
+        }
+
+        Ok(Some(res))
+    }
+
+    // spawn(f, args...) takes function name instead of value as first argument
+    pub fn isSpawn(&self) -> bool {
+        self.name.as_str() == "spawn" && matches!(self.arguments.first(), Some(Expression::Variable(_)))
+    }
+
+    pub fn spawnTarget(
+        &self,
+        argTypes: &[DataType],
+        functionReturns: &HashMap<MyStr, Option<DataType>>,
+    ) -> Result<Overload, Box<dyn Error>> {
+        let name = match self.arguments.first() {
+            Some(Expression::Variable(v)) => v,
+            _ => return Err("spawn expects function as first argument".into()),
+        };
+        resolveOverload(name, argTypes, functionReturns)
+    }
+
+    pub fn spawnArgTypes(
+        &self,
+        typesMapping: &HashMap<MyStr, (DataType, usize)>,
+        functionReturns: &HashMap<MyStr, Option<DataType>>,
+    ) -> Result<Vec<DataType>, Box<dyn Error>> {
+        let mut types = vec![];
+        for arg in &self.arguments[1..] {
+            types.push(
+                arg.toDataType(typesMapping, functionReturns, None)?
+                    .ok_or("spawn argument must have value")?,
+            );
+        }
+        Ok(types)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Overload {
+    pub encoded: MyStr,
+    // emitted right after corresponding argument is pushed
+    pub conversions: Vec<Vec<OpCode>>,
+    pub returnType: Option<DataType>,
+}
+
+// only conversions that dont lose information are applied implicitly, cost ranks candidates
+fn widening(from: &str, to: &str) -> Option<(usize, Vec<OpCode>)> {
+    match (from, to) {
+        (a, b) if a == b => Some((0, vec![])),
+        ("int", "float") => Some((1, vec![OpCode::I2F])),
+        ("char", "int") => Some((1, vec![OpCode::C2I])),
+        ("char", "float") => Some((2, vec![OpCode::C2I, OpCode::I2F])),
+        _ => None,
+    }
+}
+
+pub fn resolveOverload(
+    name: &str,
+    argTypes: &[DataType],
+    functionReturns: &HashMap<MyStr, Option<DataType>>,
+) -> Result<Overload, Box<dyn Error>> {
+    let enc = genFunName(name, argTypes);
+    if let Some(ret) = functionReturns.get(&MyStr::from(enc.clone())) {
+        return Ok(Overload {
+            encoded: MyStr::from(enc),
+            conversions: vec![vec![]; argTypes.len()],
+            returnType: ret.clone(),
+        });
+    }
+
+    // encoded names look like name(int, float)
+    let mut candidates = functionReturns
+        .iter()
+        .filter(|(k, _)| k.as_str().split('(').next() == Some(name))
+        .collect::<Vec<_>>();
+    candidates.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+
+    if candidates.is_empty() {
+        return Err(format!("function {name} is not defined").into());
+    }
+    let listed = |c: &[(&MyStr, &Option<DataType>)]| {
+        c.iter().map(|it| it.0.as_str()).collect::<Vec<_>>().join(", ")
+    };
+
+    let mut sameArity = false;
+    let mut ranked = vec![];
+
+    'candidates: for (k, ret) in &candidates {
+        let params = &k.as_str()[name.len() + 1..k.as_str().len() - 1];
+        let params = if params.is_empty() {
+            vec![]
+        } else {
+            params.split(", ").collect::<Vec<_>>()
+        };
+        if params.len() != argTypes.len() {
+            continue;
+        }
+        sameArity = true;
+
+        let mut cost = 0;
+        let mut conversions = vec![];
+        for (arg, param) in argTypes.iter().zip(params) {
+            match widening(arg.toString(), param) {
+                None => continue 'candidates,
+                Some((c, ops)) => {
+                    cost += c;
+                    conversions.push(ops)
+                }
+            }
+        }
+        ranked.push((
+            cost,
+            (*k, *ret),
+            Overload {
+                encoded: (*k).clone(),
+                conversions,
+                returnType: (*ret).clone(),
+            },
+        ));
+    }
+
+    if !sameArity {
+        return Err(format!(
+            "wrong number of arguments for {} got {}, candidates are: {}",
+            name,
+            argTypes.len(),
+            listed(&candidates)
+        )
+            .into());
+    }
+
+    let best = match ranked.iter().map(|it| it.0).min() {
+        None => {
+            return Err(format!(
+                "no overload of {} matches {}, candidates are: {}",
+                name,
+                enc,
+                listed(&candidates)
+            )
+                .into());
+        }
+        Some(v) => v,
+    };
+    ranked.retain(|it| it.0 == best);
+
+    if ranked.len() > 1 {
+        let tied = ranked.iter().map(|it| it.1).collect::<Vec<_>>();
+        return Err(format!("call to {} is ambiguous, candidates are: {}", enc, listed(&tied)).into());
+    }
+
+    Ok(ranked.pop().unwrap().2)
+}
+
+pub fn chanType(typ: &DataType) -> Option<DataType> {
+    match typ {
+        Object(o) if o.name.as_str() == "Chan" => o.generics.get(0)?.clone().ok_or(()).ok(),
+        _ => None,
+    }
+}
+
+// Some(None) is task of void function
+pub fn taskType(typ: &DataType) -> Option<Option<DataType>> {
+    match typ {
+        Object(o) if o.name.as_str() == "Task" => {
+            Some(o.generics.get(0).and_then(|it| it.clone().ok_or(()).ok()))
+        }
+        _ => None,
+    }
+}
+
+pub fn arrayType(typ: &DataType) -> Option<DataType> {
+    match typ {
+        Object(o) if o.name.as_str() == "Array" => o.generics.first()?.clone().ok_or(()).ok(),
+        _ => None,
+    }
+}
+
+pub fn generatorType(typ: &DataType) -> Option<DataType> {
+    match typ {
+        Object(o) if o.name.as_str() == "Generator" => o.generics.get(0)?.clone().ok_or(()).ok(),
+        _ => None,
+    }
+}
+
+pub fn mapTypes(typ: &DataType) -> Option<(DataType, DataType)> {
+    match typ {
+        Object(o) if o.name.as_str() == "Map" => {
+            let key = o.generics.get(0)?.clone().ok_or(()).ok()?;
+            let value = o.generics.get(1)?.clone().ok_or(()).ok()?;
+            Some((key, value))
+        }
+        _ => None,
+    }
+}
+
 impl Expression {
     pub fn toDataType(
         &self,
@@ -77,22 +385,69 @@ impl Expression {
         match self {
             Expression::ArithmeticOp {
                 left,
-                right: _,
+                right,
                 op: o,
             } => {
                 match o {
-                    Op::Gt => return Ok(Some(Bool)),
-                    Op::Less => return Ok(Some(Bool)),
-                    Op::Eq => return Ok(Some(Bool)),
-                    Op::And => return Ok(Some(Bool)),
-                    Op::Or => return Ok(Some(Bool)),
+                    Op::Gt | Op::Less | Op::Eq => {
+                        let leftType = left.toDataType(typesMapping, functionReturns, None)?;
+                        let rightType = right.toDataType(typesMapping, functionReturns, None)?;
+                        let comparable = match (&leftType, &rightType) {
+                            (Some(Object(l)), Some(Object(r))) => {
+                                l.name == r.name && (*o == Op::Eq || l.name.as_str() == "String")
+                            }
+                            (l, r) => l == r,
+                        };
+                        if !comparable {
+                            return Err(format!(
+                                "cannot compare {} with {}",
+                                leftType.as_ref().map_or("none", |it| it.toString()),
+                                rightType.as_ref().map_or("none", |it| it.toString())
+                            )
+                                .into());
+                        }
+                        return Ok(Some(Bool));
+                    }
                     _ => {}
                 }
 
-                let _leftType = left.toDataType(typesMapping, functionReturns, None)?;
-                let _rightType = left.toDataType(typesMapping, functionReturns, None)?;
+                let leftType = left.toDataType(typesMapping, functionReturns, None)?;
+                let rightType = right.toDataType(typesMapping, functionReturns, None)?;
 
-                Ok(_leftType)
+                if let Op::And | Op::Or = o {
+                    for t in [leftType, rightType] {
+                        if t != Some(Bool) {
+                            return Err(Box::new(InvalidTypeException {
+                                expected: Bool,
+                                actual: t,
+                            }));
+                        }
+                    }
+                    return Ok(Some(Bool));
+                }
+
+                if leftType == Some(Char) {
+                    return match (o, rightType) {
+                        (Op::Sub, Some(Char)) => Ok(Some(DataType::Int)),
+                        (Op::Add | Op::Sub, Some(DataType::Int)) => Ok(Some(Char)),
+                        (_, r) => Err(Box::new(InvalidTypeException {
+                            expected: DataType::Int,
+                            actual: r,
+                        })),
+                    };
+                }
+
+                if leftType != rightType {
+                    return Err(format!(
+                        "cannot apply {:?} to {} and {}",
+                        o,
+                        leftType.as_ref().map_or("none", |it| it.toString()),
+                        rightType.as_ref().map_or("none", |it| it.toString())
+                    )
+                        .into());
+                }
+
+                Ok(leftType)
             }
             Expression::IntLiteral(_) => Ok(Some(DataType::Int)),
             Expression::LongLiteral(_) => {
@@ -108,21 +463,23 @@ impl Expression {
             Expression::FunctionCall(f) => {
                 // println!("{:?}", &f.arguments);
                 // println!("{:?}", typesMapping);
-                let types = f
-                    .arguments
-                    .iter()
-                    .filter_map(|x| x.toDataType(typesMapping, functionReturns, None).ok()?)
-                    .collect::<Vec<DataType>>();
+                if f.isSpawn() {
+                    let types = f.spawnArgTypes(typesMapping, functionReturns)?;
+                    let target = f.spawnTarget(&types, functionReturns)?;
+                    return Ok(Some(DataType::task(target.returnType)));
+                }
+                let mut types = vec![];
+                for x in &f.arguments {
+                    types.push(
+                        x.toDataType(typesMapping, functionReturns, None)?
+                            .ok_or("function argument must have value")?,
+                    );
+                }
                 // println!("{:?}", &types);
-                let enc = genFunName(f.name.as_str(), &types);
-                match functionReturns.get(&MyStr::Runtime(enc.clone().into_boxed_str())) {
-                    None => {
-                        panic!();
-                        println!("{functionReturns:?}");
-                        Err(Box::new(TypeNotFound { typ: enc }))
-                    }
-                    Some(v) => Ok(v.clone()),
+                if let Some((_, ret)) = f.builtin(&types, typeHint.as_ref())? {
+                    return Ok(ret);
                 }
+                Ok(resolveOverload(f.name.as_str(), &types, functionReturns)?.returnType)
             }
             Expression::Variable(name) => {
                 match typesMapping.get(&MyStr::Runtime(name.clone().into_boxed_str())) {
@@ -177,6 +534,17 @@ impl Expression {
                     .ok_or("cannot array index none")?;
                 match e {
                     Object(o) => {
+                        if o.name.as_str() == "Map" {
+                            let (_, value) = mapTypes(&Object(o.clone()))
+                                .ok_or("map must have key and value type")?;
+                            return Ok(Some(value));
+                        }
+                        if o.name.as_str() == "Range" {
+                            return Ok(Some(DataType::Int));
+                        }
+                        if i.isSlice(typesMapping, functionReturns)? {
+                            return Ok(Some(Object(o)));
+                        }
                         if o.name.as_str() == "String" {
                             return Ok(Some(Char));
                         }
@@ -188,7 +556,7 @@ impl Expression {
                                 .ok_or("")?,
                         ))
                     }
-                    _ => panic!(),
+                    v => Err(format!("cannot index {}", v.toString()).into()),
                 }
             }
             Expression::NotExpression(i) => {
@@ -196,10 +564,79 @@ impl Expression {
 
                 match d.ok_or("not operator cant work ok none")? {
                     DataType::Bool => Ok(Some(DataType::Bool)),
-                    _ => {
-                        panic!()
+                    v => Err(Box::new(InvalidTypeException {
+                        expected: Bool,
+                        actual: Some(v),
+                    })),
+                }
+            }
+            Expression::Range { start, end } => {
+                for e in [start, end] {
+                    let t = e.toDataType(typesMapping, functionReturns, None)?;
+                    if t != Some(DataType::Int) {
+                        return Err(Box::new(InvalidTypeException {
+                            expected: DataType::Int,
+                            actual: t,
+                        }));
+                    }
+                }
+                Ok(Some(DataType::range()))
+            }
+            Expression::SizedArray { typ, size, fill } => {
+                let s = size.toDataType(typesMapping, functionReturns, None)?;
+                if s != Some(DataType::Int) {
+                    return Err(Box::new(InvalidTypeException {
+                        expected: DataType::Int,
+                        actual: s,
+                    }));
+                }
+                let f = fill.toDataType(typesMapping, functionReturns, Some(typ.clone()))?;
+                if f.as_ref() != Some(typ) {
+                    return Err(Box::new(InvalidTypeException {
+                        expected: typ.clone(),
+                        actual: f,
+                    }));
+                }
+                Ok(Some(DataType::arr(Generic::Type(typ.clone()))))
+            }
+            Expression::MapLiteral(e) => {
+                if e.is_empty() {
+                    let hint = typeHint
+                        .ok_or("cannot infer type of empty map consider adding type hint")?;
+                    return match mapTypes(&hint) {
+                        None => Err(Box::new(InvalidTypeException {
+                            expected: DataType::map(Any, Any),
+                            actual: Some(hint),
+                        })),
+                        Some(_) => Ok(Some(hint)),
+                    };
+                }
+                let (k, v) = e.get(0).ok_or("map must have least one entry")?;
+                let keyType = k
+                    .toDataType(typesMapping, functionReturns, None)?
+                    .ok_or("map key must have type")?;
+                if !MapKey::isKeyType(&keyType) {
+                    return Err(format!("{} cant be used as map key", keyType.toString()).into());
+                }
+                let valueType = v
+                    .toDataType(typesMapping, functionReturns, None)?
+                    .ok_or("map value must have type")?;
+                // every entry has to agree with the first one
+                for (k, v) in &e[1..] {
+                    for (expr, expected) in [(k, &keyType), (v, &valueType)] {
+                        let t = expr.toDataType(typesMapping, functionReturns, Some(expected.clone()))?;
+                        if t.as_ref() != Some(expected) {
+                            return Err(Box::new(InvalidTypeException {
+                                expected: expected.clone(),
+                                actual: t,
+                            }));
+                        }
                     }
                 }
+                Ok(Some(DataType::map(
+                    Generic::Type(keyType),
+                    Generic::Type(valueType),
+                )))
             }
         }
     }
@@ -220,6 +657,29 @@ pub enum Statement {
     Continue,
     Break,
     Loop(Vec<Statement>),
+    ForLoop(ForLoop),
+    Yield(Expression),
+    // position of the following statement in source
+    Location(Location),
+}
+
+// collects values yielded directly by the function body, nested function defs are not statements
+pub fn collectYields<'a>(body: &'a [Statement], out: &mut Vec<&'a Expression>) {
+    for s in body {
+        match s {
+            Statement::Yield(e) => out.push(e),
+            Statement::While(w) => collectYields(&w.body, out),
+            Statement::Loop(b) => collectYields(b, out),
+            Statement::ForLoop(f) => collectYields(&f.body, out),
+            Statement::If(i) => {
+                collectYields(&i.body, out);
+                if let Some(b) = &i.elseBody {
+                    collectYields(b, out)
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -277,6 +737,65 @@ pub struct While {
     pub body: Vec<Statement>,
 }
 
+#[derive(Debug, Clone)]
+pub struct ForLoop {
+    pub varName: String,
+    pub iterable: Expression,
+    pub body: Vec<Statement>,
+    // unique per loop, used to name hidden iterator locals
+    pub id: usize,
+}
+
+impl ForLoop {
+    pub fn iterName(&self) -> MyStr {
+        MyStr::from(format!("{}@iter", self.id))
+    }
+
+    pub fn indexName(&self) -> MyStr {
+        MyStr::from(format!("{}@index", self.id))
+    }
+
+    // type of the collection that is actually indexed, maps are iterated over their keys
+    pub fn iterType(
+        &self,
+        typesMapping: &HashMap<MyStr, (DataType, usize)>,
+        functionReturns: &HashMap<MyStr, Option<DataType>>,
+    ) -> Result<DataType, Box<dyn Error>> {
+        let t = self
+            .iterable
+            .toDataType(typesMapping, functionReturns, None)?
+            .ok_or("cannot iterate over none")?;
+
+        match mapTypes(&t) {
+            None => Ok(t),
+            Some((key, _)) => Ok(DataType::arr(Generic::Type(key))),
+        }
+    }
+
+    pub fn elementType(
+        &self,
+        typesMapping: &HashMap<MyStr, (DataType, usize)>,
+        functionReturns: &HashMap<MyStr, Option<DataType>>,
+    ) -> Result<DataType, Box<dyn Error>> {
+        match self.iterType(typesMapping, functionReturns)? {
+            Object(o) => match o.name.as_str() {
+                "String" => Ok(Char),
+                "Range" => Ok(DataType::Int),
+                "Generator" => Ok(generatorType(&Object(o.clone()))
+                    .ok_or("cannot iterate over generator of any")?),
+                "Array" => Ok(o
+                    .generics
+                    .first()
+                    .ok_or("array must have one generic parameter")?
+                    .clone()
+                    .ok_or("cannot iterate over array of any")?),
+                _ => Err(format!("cannot iterate over {}", o.name).into()),
+            },
+            v => Err(format!("cannot iterate over {}", v.toString()).into()),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct VariableCreate {
     pub name: String,
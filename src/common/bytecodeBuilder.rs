@@ -0,0 +1,128 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::ops::{Deref, DerefMut};
+
+use crate::lexer::Location;
+use crate::lineTable::LineTable;
+use crate::vm::{JmpType, MyStr, OpCode};
+
+#[derive(Debug)]
+pub struct InvalidJump {
+    pub index: usize,
+    pub target: isize,
+    pub len: usize,
+}
+
+impl Display for InvalidJump {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "jump at {} lands at {} outside of function of length {}",
+            self.index, self.target, self.len
+        )
+    }
+}
+
+impl Error for InvalidJump {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+// jump targets of the innermost loop
+#[derive(Debug, Clone, Copy)]
+pub struct LoopLabels {
+    pub next: Label,
+    pub exit: Label,
+}
+
+// emits code of single function, jumps refer to labels and are patched by finish
+#[derive(Debug, Default)]
+pub struct BytecodeBuilder {
+    ops: Vec<OpCode>,
+    labels: Vec<Option<usize>>,
+    jumps: Vec<(usize, Label)>,
+    locations: Vec<(usize, Location)>,
+}
+
+impl BytecodeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    pub fn place(&mut self, label: Label) {
+        assert!(self.labels[label.0].is_none(), "label {} placed twice", label.0);
+        self.labels[label.0] = Some(self.ops.len());
+    }
+
+    // label at the next emitted op
+    pub fn here(&mut self) -> Label {
+        let label = self.label();
+        self.place(label);
+        label
+    }
+
+    // source position of ops emitted from now on
+    pub fn mark(&mut self, location: Location) {
+        self.locations.push((self.ops.len(), location));
+    }
+
+    pub fn jump(&mut self, label: Label, jmpType: JmpType) {
+        self.jumps.push((self.ops.len(), label));
+        self.ops.push(OpCode::Jmp {
+            offset: 0,
+            jmpType,
+        });
+    }
+
+    // appends patched code to out, jump may land one past the last op which ends the code
+    pub fn finish(
+        mut self,
+        out: &mut Vec<OpCode>,
+        lines: &mut LineTable,
+        function: Option<&MyStr>,
+    ) -> Result<(), Box<dyn Error>> {
+        for (index, label) in &self.jumps {
+            let target = self.labels[label.0]
+                .ok_or_else(|| format!("jump at {index} refers to label that was never placed"))?;
+            if let OpCode::Jmp { offset, .. } = &mut self.ops[*index] {
+                *offset = target as isize - *index as isize - 1;
+            }
+        }
+
+        let len = self.ops.len();
+        for (index, op) in self.ops.iter().enumerate() {
+            if let OpCode::Jmp { offset, .. } = op {
+                let target = index as isize + 1 + offset;
+                if target < 0 || target > len as isize {
+                    return Err(Box::new(InvalidJump { index, target, len }));
+                }
+            }
+        }
+
+        let offset = out.len();
+        for (index, location) in self.locations {
+            lines.push(index + offset, location, function.cloned());
+        }
+        out.extend(self.ops);
+        Ok(())
+    }
+}
+
+impl Deref for BytecodeBuilder {
+    type Target = Vec<OpCode>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.ops
+    }
+}
+
+impl DerefMut for BytecodeBuilder {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.ops
+    }
+}
@@ -52,7 +52,7 @@ impl Display for OutOfBoundsException {
 impl Error for OutOfBoundsException {}
 
 pub fn checkFunction(
-    opCodes: &mut SeekableOpcodes,
+    opCodes: &mut SeekableOpcodes<OpCode>,
     abstractStack: &mut AbstractStack,
     vm: &mut VirtualMachine,
     checkedFunctions: &mut HashSet<Box<str>>,
@@ -236,7 +236,7 @@ impl AbstractStack {
 
 #[inline(always)]
 pub fn checkBytecode<'a>(
-    opCodes: &mut SeekableOpcodes,
+    opCodes: &mut SeekableOpcodes<OpCode>,
     abstractLocals: &mut Vec<DataType>,
     abstractStack: &mut AbstractStack,
     vm: &mut VirtualMachine,
@@ -267,6 +267,10 @@ pub fn checkBytecode<'a>(
                 abstractStack.assertPop(&Int)?;
                 abstractStack.push(Float);
             }
+            C2I => {
+                abstractStack.assertPop(&Char)?;
+                abstractStack.push(Int);
+            }
             PushInt(_v) => {
                 abstractStack.push(Int);
             }
@@ -325,13 +329,14 @@ pub fn checkBytecode<'a>(
                 JmpType::One => abstractStack.assertPop(&Int)?,
                 JmpType::Zero => abstractStack.assertPop(&Int)?,
                 JmpType::Jmp => {}
-                JmpType::Gt => {
-                    abstractStack.assertPop(&Int)?;
-                    abstractStack.assertPop(&Int)?
-                }
-                JmpType::Less => {
-                    abstractStack.assertPop(&Int)?;
-                    abstractStack.assertPop(&Int)?
+                JmpType::Lt(t)
+                | JmpType::Le(t)
+                | JmpType::Gt(t)
+                | JmpType::Ge(t)
+                | JmpType::Eq(t)
+                | JmpType::Ne(t) => {
+                    abstractStack.assertPop(t)?;
+                    abstractStack.assertPop(t)?
                 }
                 JmpType::True => abstractStack.assertPop(&Bool)?,
                 JmpType::False => abstractStack.assertPop(&Bool)?,
@@ -341,7 +346,7 @@ pub fn checkBytecode<'a>(
                     continue;
                 }
 
-                match vm.functions.get(encoded) {
+                match vm.function(encoded) {
                     None => {
                         return Err(Box::new(GenericException {
                             msg: format!("function {encoded} not found"),
@@ -361,7 +366,40 @@ pub fn checkBytecode<'a>(
                     }
                 }
             }
+            // callee returns for us, its arguments are the last thing on the stack
+            TailCall { encoded } => match vm.function(encoded) {
+                None => {
+                    return Err(Box::new(GenericException {
+                        msg: format!("function {encoded} not found"),
+                    }));
+                }
+                Some(fun) => {
+                    for x in 0..fun.argAmount {
+                        abstractStack.assertPop(&fun.varTable[fun.argAmount - x - 1].typ)?;
+                    }
+                    return Ok(());
+                }
+            },
             Return => return Ok(()),
+            Add(Char) => {
+                abstractStack.assertPop(&Int)?;
+                abstractStack.assertPop(&Char)?;
+                abstractStack.push(Char)
+            }
+            Sub(Char) => {
+                let r = abstractStack.pop()?;
+                abstractStack.assertPop(&Char)?;
+                match r {
+                    Char => abstractStack.push(Int),
+                    Int => abstractStack.push(Char),
+                    r => {
+                        return Err(Box::new(InvalidTypeException {
+                            expected: Int,
+                            actual: Some(r),
+                        }))
+                    }
+                }
+            }
             Add(v) => unsafe {
                 abstractStack.assertPop(v)?;
                 abstractStack.assertPop(v)?;
@@ -427,12 +465,24 @@ pub fn checkBytecode<'a>(
                 abstractStack.assertPop(t)?;
                 abstractStack.assertPop(&DataType::arr(Generic::Type(t.clone())))?;
             }
+            ArrayFill(t) => {
+                abstractStack.assertPop(t)?;
+                abstractStack.assertPop(&Int)?;
+                abstractStack.push(DataType::arr(Generic::Type(t.clone())))
+            }
+            ArrayPush(t) => {
+                abstractStack.assertPop(t)?;
+                abstractStack.assertPop(&DataType::arr(Generic::Type(t.clone())))?;
+            }
             ArrayLoad(t) => {
                 abstractStack.assertPop(&Int)?;
                 abstractStack.assertPop(&DataType::arr(Generic::Type(t.clone())))?;
                 abstractStack.push(t.clone())
             }
-            ArrayLength => panic!(),
+            ArrayLength => {
+                abstractStack.pop()?;
+                abstractStack.push(Int)
+            }
             Inc { typ, index } => {
                 if *index >= abstractLocals.len() {
                     return Err(Box::new(OutOfBoundsException {
@@ -495,6 +545,88 @@ pub fn checkBytecode<'a>(
                 abstractStack.assertPop(&DataType::str())?;
                 abstractStack.push(DataType::Char);
             }
+            MapNew { key, value } => abstractStack.push(DataType::map(
+                Generic::Type(key.clone()),
+                Generic::Type(value.clone()),
+            )),
+            MapStore(t) => {
+                abstractStack.pop()?;
+                abstractStack.assertPop(t)?;
+                abstractStack.pop()?;
+            }
+            MapLoad(t) => {
+                abstractStack.pop()?;
+                abstractStack.pop()?;
+                abstractStack.push(t.clone())
+            }
+            MapContains => {
+                abstractStack.pop()?;
+                abstractStack.pop()?;
+                abstractStack.push(Bool)
+            }
+            MapRemove => {
+                abstractStack.pop()?;
+                abstractStack.pop()?;
+            }
+            MapKeys(t) => {
+                abstractStack.pop()?;
+                abstractStack.push(DataType::arr(Generic::Type(t.clone())))
+            }
+            RangeNew => {
+                abstractStack.assertPop(&Int)?;
+                abstractStack.assertPop(&Int)?;
+                abstractStack.push(DataType::range())
+            }
+            ArraySlice(t) => {
+                abstractStack.assertPop(&DataType::range())?;
+                abstractStack.assertPop(&DataType::arr(Generic::Type(t.clone())))?;
+                abstractStack.push(DataType::arr(Generic::Type(t.clone())))
+            }
+            StrSlice => {
+                abstractStack.assertPop(&DataType::range())?;
+                abstractStack.assertPop(&DataType::str())?;
+                abstractStack.push(DataType::str())
+            }
+            GenNew(t) => abstractStack.push(DataType::generator(Generic::Type(t.clone()))),
+            Yield => {
+                abstractStack.pop()?;
+            }
+            GenResume => {
+                abstractStack.pop()?;
+                abstractStack.push(Bool)
+            }
+            GenValue(t) => {
+                abstractStack.pop()?;
+                abstractStack.push(t.clone())
+            }
+            Spawn { encoded, argCount } => match vm.function(encoded) {
+                None => {
+                    return Err(Box::new(GenericException {
+                        msg: format!("function {encoded} not found"),
+                    }));
+                }
+                Some(fun) => {
+                    for x in 0..*argCount {
+                        abstractStack.assertPop(&fun.varTable[*argCount - x - 1].typ)?;
+                    }
+                    abstractStack.push(DataType::task(fun.returnType.clone()))
+                }
+            },
+            Join => {
+                let t = abstractStack.pop()?;
+                if let Some(Some(ret)) = crate::ast::taskType(&t) {
+                    abstractStack.push(ret)
+                }
+            }
+            ChanNew(t) => abstractStack.push(DataType::chan(Generic::Type(t.clone()))),
+            ChanSend => {
+                abstractStack.pop()?;
+                abstractStack.pop()?;
+            }
+            ChanRecv(t) => {
+                abstractStack.pop()?;
+                abstractStack.push(t.clone())
+            }
         }
     }
 }
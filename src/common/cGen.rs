@@ -5,12 +5,15 @@ use std::io::Write;
 
 use Statement::Variable;
 
-use crate::ast::{Expression, FunctionDef, ModType, Node, Op, Statement, StructDef};
+use crate::ast::{
+    Expression, FunctionCall, FunctionDef, mapTypes, ModType, Node, Op, resolveOverload, Statement,
+    StructDef,
+};
 use crate::lexer::*;
 use crate::optimizer::evalExpr;
 use crate::parser::*;
 use crate::vm::{
-    DataType, Generic, genFunName, genFunNameMeta, JmpType, MyStr, ObjectMeta, OpCode,
+    DataType, Generic, genFunNameMeta, JmpType, MyStr, ObjectMeta, OpCode,
     VariableMetadata,
 };
 use crate::vm::DataType::{Bool, Int};
@@ -46,10 +49,39 @@ fn genExpression(
                 }
                 Some(dat) => {
                     if let DataType::Object(v) = dat {
-                        if v.name.as_str() != "String" {
+                        let isStr = v.name.as_str() == "String";
+                        let cmp = match op {
+                            Op::Eq => "==",
+                            Op::Gt => "<",
+                            Op::Less => ">",
+                            _ => "",
+                        };
+
+                        if !isStr && op == Op::Eq {
+                            // objects other than strings are compared by reference
+                            out.push('(');
+                            genExpression(*left, out, functionReturns, vTable)?;
+                            out.push_str("==");
+                            genExpression(*right, out, functionReturns, vTable)?;
+                            out.push(')');
+                            return Ok(());
+                        }
+
+                        if !isStr {
                             panic!()
                         }
 
+                        if !cmp.is_empty() {
+                            out.push_str("(vm->nativeWrapper.strCmp(vm,");
+                            genExpression(*left, out, functionReturns, vTable)?;
+                            out.push(',');
+                            genExpression(*right, out, functionReturns, vTable)?;
+                            out.push_str(")");
+                            out.push_str(cmp);
+                            out.push_str("0)");
+                            return Ok(());
+                        }
+
                         out.push_str("vm->nativeWrapper.strConcat(vm,frame,");
                         genExpression(*left, out, functionReturns, vTable)?;
                         out.push(',');
@@ -69,7 +101,7 @@ fn genExpression(
                         Op::Less => ">",
                         Op::Eq => "==",
                         Op::And => "&&",
-                        Op::Or => panic!(),
+                        Op::Or => "||",
                     };
                     out.push_str(t);
                     genExpression(*right, out, functionReturns, vTable)?;
@@ -93,36 +125,36 @@ fn genExpression(
             }
         }
         Expression::FunctionCall(e) => {
-            let fName: MyStr = genFunName(
-                e.name.as_str(),
-                &e.arguments
-                    .iter()
-                    .map(|it| {
-                        it.toDataType(vTable, functionReturns, None)
-                            .unwrap()
-                            .unwrap()
-                    })
-                    .collect::<Vec<DataType>>(),
-            )
-                .into_boxed_str()
-                .into();
-            // println!("{:?}", fName);
-            // println!("{:?}", functionReturns);
-            let ret = functionReturns.get(&fName).unwrap().clone();
+            if e.isSpawn() {
+                return Err(Box::new(NoValue {
+                    msg: "spawn is not supported in native functions".to_string(),
+                }));
+            }
+            let argTypes = e
+                .arguments
+                .iter()
+                .map(|it| it.toDataType(vTable, functionReturns, None).unwrap().unwrap())
+                .collect::<Vec<DataType>>();
+            if e.builtin(&argTypes, None)?.is_some() {
+                return genMapBuiltin(e, &argTypes, out, functionReturns, vTable);
+            }
+
+            let target = resolveOverload(e.name.as_str(), &argTypes, functionReturns)?;
+            let fName = target.encoded;
+            let ret = target.returnType;
 
             let argsLen = e.arguments.len();
 
             out.push_str("({");
 
-            for arg in e.arguments {
-                let t = match arg.toDataType(vTable, functionReturns, None)?.unwrap() {
-                    Int => "vm->nativeWrapper.pushInt(vm,",
-                    DataType::Float => "vm->nativeWrapper.pushFloat(vm,",
-                    Bool => "vm->nativeWrapper.pushBool(vm,",
-                    DataType::Char => "vm->nativeWrapper.pushChar(vm,",
-                    DataType::Object(_) => "vm->nativeWrapper.pushRef(vm,",
+            for ((arg, t), conversion) in e.arguments.into_iter().zip(argTypes).zip(target.conversions) {
+                // C converts implicitly so only push matching parameter type is needed
+                let t = match conversion.last() {
+                    Some(OpCode::I2F) => DataType::Float,
+                    Some(OpCode::C2I) => Int,
+                    _ => t,
                 };
-                out.push_str(t);
+                out.push_str(pushFun(&t));
                 genExpression(arg, out, functionReturns, vTable)?;
                 out.push_str(");");
             }
@@ -193,6 +225,11 @@ fn genExpression(
             out.push('}');
         }
         Expression::ArrayIndexing(i) => {
+            if i.isSlice(vTable, functionReturns)? {
+                return Err(Box::new(NoValue {
+                    msg: "slices are not supported in native functions".to_string(),
+                }));
+            }
             let t = i.expr.toDataType(vTable, functionReturns, None)?.unwrap();
             match t {
                 DataType::Object(o) => match o.name.as_str() {
@@ -203,6 +240,17 @@ fn genExpression(
                         genExpression(i.index, out, functionReturns, vTable)?;
                         out.push_str(")")
                     }
+                    "Map" => {
+                        let (key, value) = mapTypes(&DataType::Object(o.clone())).unwrap();
+                        out.push_str("({");
+                        out.push_str(pushFun(&key));
+                        genExpression(i.index, out, functionReturns, vTable)?;
+                        out.push_str(");vm->nativeWrapper.mapGet(vm,");
+                        genExpression(i.expr, out, functionReturns, vTable)?;
+                        out.push_str(");");
+                        out.push_str(popFun(&value));
+                        out.push_str("})")
+                    }
                     "Array" => {
                         let c = o.generics.first().unwrap();
                         let t = c.clone().ok_or("expected generic type not any")?;
@@ -228,7 +276,72 @@ fn genExpression(
             out.push('!');
             genExpression(*e, out, functionReturns, vTable)?;
         }
+        Expression::MapLiteral(_) => {
+            return Err(Box::new(NoValue {
+                msg: "map literals are not supported in native functions".to_string(),
+            }));
+        }
+        Expression::Range { .. } => {
+            return Err(Box::new(NoValue {
+                msg: "ranges are not supported in native functions".to_string(),
+            }));
+        }
+        Expression::SizedArray { .. } => {
+            return Err(Box::new(NoValue {
+                msg: "sized arrays are not supported in native functions".to_string(),
+            }));
+        }
+    }
+    Ok(())
+}
+
+fn pushFun(t: &DataType) -> &'static str {
+    match t {
+        Int => "vm->nativeWrapper.pushInt(vm,",
+        DataType::Float => "vm->nativeWrapper.pushFloat(vm,",
+        Bool => "vm->nativeWrapper.pushBool(vm,",
+        DataType::Char => "vm->nativeWrapper.pushChar(vm,",
+        DataType::Object(_) => "vm->nativeWrapper.pushRef(vm,",
     }
+}
+
+fn popFun(t: &DataType) -> &'static str {
+    match t {
+        Int => "vm->nativeWrapper.popInt(vm);",
+        DataType::Float => "vm->nativeWrapper.popFloat(vm);",
+        Bool => "vm->nativeWrapper.popBool(vm);",
+        DataType::Char => "vm->nativeWrapper.popChar(vm);",
+        DataType::Object(_) => "vm->nativeWrapper.popRef(vm,frame);",
+    }
+}
+
+fn genMapBuiltin(
+    e: FunctionCall,
+    argTypes: &[DataType],
+    out: &mut String,
+    functionReturns: &HashMap<MyStr, Option<DataType>>,
+    vTable: &HashMap<MyStr, (DataType, usize)>,
+) -> Result<(), Box<dyn Error>> {
+    let mut args = e.arguments.into_iter();
+    let map = args.next().unwrap();
+    let fun = match e.name.as_str() {
+        "contains" => "mapContains",
+        "remove" => "mapRemove",
+        n => {
+            return Err(Box::new(NoValue {
+                msg: format!("{} is not supported in native functions", n),
+            }));
+        }
+    };
+
+    out.push_str("({");
+    out.push_str(pushFun(&argTypes[1]));
+    genExpression(args.next().unwrap(), out, functionReturns, vTable)?;
+    out.push_str(");vm->nativeWrapper.");
+    out.push_str(fun);
+    out.push_str("(vm,");
+    genExpression(map, out, functionReturns, vTable)?;
+    out.push_str(");})");
     Ok(())
 }
 
@@ -254,30 +367,34 @@ fn genStatement(
 ) -> Result<(), Box<dyn Error>> {
     match statement {
         Statement::FunctionExpr(e) => {
-            let fName: MyStr = genFunName(
-                e.name.as_str(),
-                &e.arguments
-                    .iter()
-                    .map(|it| {
-                        it.toDataType(vTable, functionReturns, None)
-                            .unwrap()
-                            .unwrap()
-                    })
-                    .collect::<Vec<DataType>>(),
-            )
-                .into_boxed_str()
-                .into();
-            let ret = functionReturns.get(&fName).unwrap().clone();
-
-            for arg in e.arguments {
-                let t = match arg.toDataType(vTable, functionReturns, None)?.unwrap() {
-                    Int => "vm->nativeWrapper.pushInt(vm,",
-                    DataType::Float => "vm->nativeWrapper.pushFloat(vm,",
-                    Bool => "vm->nativeWrapper.pushBool(vm,",
-                    DataType::Char => "vm->nativeWrapper.pushChar(vm,",
-                    DataType::Object(_) => "vm->nativeWrapper.pushRef(vm,",
+            if e.isSpawn() {
+                return Err(Box::new(NoValue {
+                    msg: "spawn is not supported in native functions".to_string(),
+                }));
+            }
+            let argTypes = e
+                .arguments
+                .iter()
+                .map(|it| it.toDataType(vTable, functionReturns, None).unwrap().unwrap())
+                .collect::<Vec<DataType>>();
+            if e.builtin(&argTypes, None)?.is_some() {
+                genMapBuiltin(e, &argTypes, out, functionReturns, vTable)?;
+                out.push(';');
+                return Ok(());
+            }
+
+            let target = resolveOverload(e.name.as_str(), &argTypes, functionReturns)?;
+            let fName = target.encoded;
+            let ret = target.returnType;
+
+            for ((arg, t), conversion) in e.arguments.into_iter().zip(argTypes).zip(target.conversions) {
+                // C converts implicitly so only push matching parameter type is needed
+                let t = match conversion.last() {
+                    Some(OpCode::I2F) => DataType::Float,
+                    Some(OpCode::C2I) => Int,
+                    _ => t,
                 };
-                out.push_str(t);
+                out.push_str(pushFun(&t));
                 genExpression(arg, out, functionReturns, vTable)?;
                 out.push_str(");");
             }
@@ -412,6 +529,18 @@ fn genStatement(
             out.push(';');
         }
         Statement::ArrayAssign { left, right } => {
+            let target = left.expr.toDataType(vTable, functionReturns, None)?.unwrap();
+            if let Some((key, value)) = mapTypes(&target) {
+                out.push_str(pushFun(&key));
+                genExpression(left.index, out, functionReturns, vTable)?;
+                out.push_str(");");
+                out.push_str(pushFun(&value));
+                genExpression(right, out, functionReturns, vTable)?;
+                out.push_str(");vm->nativeWrapper.mapSet(vm,");
+                genExpression(left.expr, out, functionReturns, vTable)?;
+                out.push_str(");");
+                return Ok(());
+            }
             panic!();
             genExpression(left.expr, out, functionReturns, vTable)?;
             out.push('[');
@@ -429,6 +558,17 @@ fn genStatement(
             }
             out.push_str(" }");
         }
+        Statement::ForLoop(_) => {
+            return Err(Box::new(NoValue {
+                msg: "for loops are not supported in native functions".to_string(),
+            }));
+        }
+        Statement::Yield(_) => {
+            return Err(Box::new(NoValue {
+                msg: "generators are not supported in native functions".to_string(),
+            }));
+        }
+        Statement::Location(_) => {}
     }
     Ok(())
 }
@@ -643,6 +783,9 @@ pub fn buildLocalsTable(
         Statement::ArrayAssign { .. } => {}
         Statement::Continue => {}
         Statement::Break => {}
+        Statement::ForLoop(_) => {}
+        Statement::Yield(_) => {}
+        Statement::Location(_) => {}
     }
 
     Ok(())
@@ -6,12 +6,20 @@ use std::io::Write;
 
 use Statement::Variable;
 
-use crate::ast::{Expression, FunctionDef, ModType, Node, Op, Statement, StructDef};
+use crate::ast::{
+    collectYields, Expression, FunctionDef, generatorType, mapTypes, ModType, Node, Op,
+    resolveOverload, Statement, StructDef,
+};
+use crate::bytecodeBuilder::{BytecodeBuilder, Label, LoopLabels};
+use crate::bytecodeChecker::InvalidTypeException;
 use crate::lexer::*;
+use crate::lineTable::LineTable;
 use crate::optimizer::{evalE, evalExpr};
 use crate::parser::*;
+use crate::std::bootStrapVM;
+use crate::typeChecker::checkProgram;
 use crate::vm::{
-    DataType, evaluateBytecode, Generic, genFunName, genFunNameMeta, JmpType, MyStr, OpCode, Value,
+    DataType, evaluateBytecode, Generic, genFunNameMeta, INT_MAX, INT_MIN, JmpType, MyStr, OpCode, Value,
     VariableMetadata,
 };
 use crate::vm::DataType::{Bool, Int};
@@ -32,14 +40,14 @@ impl Error for NoValue {}
 
 pub struct ExpressionCtx<'a> {
     pub exp: &'a Expression,
-    pub ops: &'a mut Vec<OpCode>,
+    pub ops: &'a mut BytecodeBuilder,
     pub functionReturns: &'a HashMap<MyStr, Option<DataType>>,
     pub vTable: &'a HashMap<MyStr, (DataType, usize)>,
     pub typeHint: Option<DataType>,
 }
 
 pub struct PartialExprCtx<'a> {
-    pub ops: &'a mut Vec<OpCode>,
+    pub ops: &'a mut BytecodeBuilder,
     pub functionReturns: &'a HashMap<MyStr, Option<DataType>>,
     pub vTable: &'a HashMap<MyStr, (DataType, usize)>,
     pub typeHint: Option<DataType>,
@@ -73,11 +81,13 @@ impl ExpressionCtx<'_> {
 
 pub struct StatementCtx<'a> {
     pub statement: &'a Statement,
-    pub ops: &'a mut Vec<OpCode>,
+    pub ops: &'a mut BytecodeBuilder,
     pub functionReturns: &'a HashMap<MyStr, Option<DataType>>,
     pub vTable: &'a HashMap<MyStr, (DataType, usize)>,
-    pub loopContext: Option<usize>,
+    pub loopContext: Option<LoopLabels>,
     pub clearStack: bool,
+    // top level code has no frame a tail call could reuse
+    pub inFunction: bool,
 }
 
 impl ExpressionCtx<'_> {
@@ -115,10 +125,19 @@ impl StatementCtx<'_> {
             vTable: self.vTable,
             loopContext: self.loopContext,
             clearStack: self.clearStack,
+            inFunction: self.inFunction,
         }
     }
 }
 
+// literal must fit into int of vm
+fn intLiteral(s: &str) -> Result<isize, Box<dyn Error>> {
+    match s.parse::<isize>() {
+        Ok(v) if (INT_MIN..=INT_MAX).contains(&v) => Ok(v),
+        _ => Err(format!("integer literal {s} is out of range").into()),
+    }
+}
+
 fn genExpression(mut ctx: ExpressionCtx) -> Result<(), Box<dyn Error>> {
     let (e, mut r) = ctx.reduce();
     let mut d = evalE(&e);
@@ -137,6 +156,25 @@ fn genExpression(mut ctx: ExpressionCtx) -> Result<(), Box<dyn Error>> {
                     }));
                 }
                 Some(dat) => {
+                    if *op == Op::And || *op == Op::Or {
+                        // right side is evaluated only when left doesnt decide the result
+                        genExpression(r.constructCtx(&**left))?;
+                        let end = r.ops.label();
+                        r.ops.push(Dup);
+                        r.ops.jump(
+                            end,
+                            if *op == Op::And {
+                                JmpType::False
+                            } else {
+                                JmpType::True
+                            },
+                        );
+                        r.ops.push(Pop);
+                        genExpression(r.constructCtx(&**right))?;
+                        r.ops.place(end);
+                        return Ok(());
+                    }
+
                     genExpression(r.constructCtx(&**left))?;
                     genExpression(r.constructCtx(&**right))?;
                     let t = match op {
@@ -154,8 +192,7 @@ fn genExpression(mut ctx: ExpressionCtx) -> Result<(), Box<dyn Error>> {
                 }
             }
         }
-        Expression::IntLiteral(i) => r.ops.push(PushInt(i.parse::<isize>().unwrap())),
-        Expression::LongLiteral(i) => r.ops.push(PushInt(i.parse::<isize>().unwrap())),
+        Expression::IntLiteral(i) | Expression::LongLiteral(i) => r.ops.push(PushInt(intLiteral(i)?)),
         Expression::FloatLiteral(i) => r.ops.push(OpCode::PushFloat(i.parse::<f32>().unwrap())),
         Expression::DoubleLiteral(i) => r.ops.push(OpCode::PushFloat(i.parse::<f32>().unwrap())),
         Expression::StringLiteral(i) => {
@@ -164,26 +201,53 @@ fn genExpression(mut ctx: ExpressionCtx) -> Result<(), Box<dyn Error>> {
         }
         Expression::BoolLiteral(i) => r.ops.push(OpCode::PushBool(*i)),
         Expression::FunctionCall(e) => {
-            let mut argTypes = vec![];
+            // spawn takes function name as first argument
+            let spawn = e.isSpawn();
+            let args = if spawn { &e.arguments[1..] } else { &e.arguments[..] };
 
-            for arg in &e.arguments {
-                let t = arg.toDataType(r.vTable, r.functionReturns, None)?;
-                match t {
+            let mut argTypes = vec![];
+            for arg in args {
+                match arg.toDataType(r.vTable, r.functionReturns, None)? {
                     None => {
                         return Err(Box::new(NoValue {
                             msg: String::from("aahhh"),
                         }));
                     }
-                    Some(v) => {
-                        argTypes.push(v);
-                        genExpression(r.constructCtx(&arg))?;
+                    Some(v) => argTypes.push(v),
+                }
+            }
+
+            if !spawn {
+                if let Some((builtin, _)) = e.builtin(&argTypes, r.typeHint.as_ref())? {
+                    for arg in args {
+                        genExpression(r.constructCtx(arg))?;
                     }
+                    r.ops.extend(builtin);
+                    return Ok(());
                 }
             }
 
-            r.ops.push(Call {
-                encoded: MyStr::Runtime(genFunName(e.name.as_str(), &argTypes).into_boxed_str()),
-            })
+            let target = if spawn {
+                e.spawnTarget(&argTypes, r.functionReturns)?
+            } else {
+                resolveOverload(e.name.as_str(), &argTypes, r.functionReturns)?
+            };
+
+            for (arg, conversion) in args.iter().zip(target.conversions) {
+                genExpression(r.constructCtx(arg))?;
+                r.ops.extend(conversion);
+            }
+
+            if spawn {
+                r.ops.push(Spawn {
+                    encoded: target.encoded,
+                    argCount: argTypes.len(),
+                })
+            } else {
+                r.ops.push(Call {
+                    encoded: target.encoded,
+                })
+            }
         }
         Expression::Variable(v) => {
             let _res = match r.vTable.get(&MyStr::Runtime(v.clone().into_boxed_str())) {
@@ -224,11 +288,10 @@ fn genExpression(mut ctx: ExpressionCtx) -> Result<(), Box<dyn Error>> {
             // let d = i.get(0).ok_or("array must have at least one element")?.toDataType(vTable, functionReturns, None)?.ok_or("array elements must have type")?;
             r.ops.push(PushInt(i.len() as isize));
             r.ops.push(ArrayNew(e.clone()));
-            for (ind, exp) in i.iter().enumerate() {
+            for exp in i {
                 r.ops.push(Dup);
                 genExpression(r.constructCtx(exp))?;
-                r.ops.push(PushInt(ind as isize));
-                r.ops.push(ArrayStore(e.clone()));
+                r.ops.push(ArrayPush(e.clone()));
             }
         }
         Expression::ArrayIndexing(i) => {
@@ -240,10 +303,26 @@ fn genExpression(mut ctx: ExpressionCtx) -> Result<(), Box<dyn Error>> {
             match d {
                 DataType::Object(o) => {
                     // println!("{:?}", o);
+                    let isSlice = i.isSlice(r.vTable, r.functionReturns)?;
+
                     if o.name.as_str() == "String" {
                         genExpression(r.constructCtx(&i.expr))?;
                         genExpression(r.constructCtx(&i.index))?;
-                        r.ops.push(GetChar);
+                        r.ops.push(if isSlice { StrSlice } else { GetChar });
+                        return Ok(());
+                    }
+
+                    if o.name.as_str() == "Range" {
+                        genExpression(r.constructCtx(&i.expr))?;
+                        genExpression(r.constructCtx(&i.index))?;
+                        r.ops.push(ArrayLoad(Int));
+                        return Ok(());
+                    }
+
+                    if let Some((_, value)) = mapTypes(&DataType::Object(o.clone())) {
+                        genExpression(r.constructCtx(&i.expr))?;
+                        genExpression(r.constructCtx(&i.index))?;
+                        r.ops.push(MapLoad(value));
                         return Ok(());
                     }
 
@@ -252,7 +331,11 @@ fn genExpression(mut ctx: ExpressionCtx) -> Result<(), Box<dyn Error>> {
                             genExpression(r.constructCtx(&i.expr))?;
                             genExpression(r.constructCtx(&i.index))?;
 
-                            ctx.ops.push(OpCode::ArrayLoad(v.clone()));
+                            if isSlice {
+                                ctx.ops.push(OpCode::ArraySlice(v.clone()));
+                            } else {
+                                ctx.ops.push(OpCode::ArrayLoad(v.clone()));
+                            }
                         }
                         Generic::Any => panic!(),
                     }
@@ -264,6 +347,33 @@ fn genExpression(mut ctx: ExpressionCtx) -> Result<(), Box<dyn Error>> {
             genExpression(r.constructCtx(&**e))?;
             ctx.ops.push(Not)
         }
+        Expression::Range { start, end } => {
+            genExpression(r.constructCtx(start))?;
+            genExpression(r.constructCtx(end))?;
+            r.ops.push(RangeNew);
+        }
+        Expression::SizedArray { typ, size, fill } => {
+            genExpression(r.constructCtx(size))?;
+            genExpression(r.constructCtx(fill))?;
+            r.ops.push(ArrayFill(typ.clone()));
+        }
+        Expression::MapLiteral(entries) => {
+            let t = e
+                .toDataType(r.vTable, r.functionReturns, r.typeHint.clone())?
+                .ok_or("map must have type")?;
+            let (key, value) = mapTypes(&t).ok_or("map must have key and value type")?;
+
+            r.ops.push(MapNew {
+                key,
+                value: value.clone(),
+            });
+            for (k, v) in entries {
+                r.ops.push(Dup);
+                genExpression(r.constructCtx(v))?;
+                genExpression(r.constructCtx(k))?;
+                r.ops.push(MapStore(value.clone()));
+            }
+        }
     }
     Ok(())
 }
@@ -281,47 +391,48 @@ impl Display for VariableNotFound {
 
 impl Error for VariableNotFound {}
 
+// jumps to target when condition is false, comparison of numbers or chars is fused into the jump
+fn genBranchUnless(
+    ctx: &mut StatementCtx,
+    condition: &Expression,
+    target: Label,
+) -> Result<(), Box<dyn Error>> {
+    let (exp, negated) = match condition {
+        Expression::NotExpression(e) => (&**e, true),
+        e => (e, false),
+    };
+    // constant comparisons fold into literals
+    let folded = evalE(exp);
+    if let Some(Expression::ArithmeticOp { left, right, op }) = &folded {
+        let typ = left
+            .toDataType(ctx.vTable, ctx.functionReturns, None)?
+            .filter(|it| matches!(it, Int | DataType::Float | DataType::Char));
+        // Greater is emitted for `<`
+        let jumps = match (typ, op) {
+            (Some(t), Op::Gt) => Some((JmpType::Lt(t.clone()), JmpType::Ge(t))),
+            (Some(t), Op::Less) => Some((JmpType::Gt(t.clone()), JmpType::Le(t))),
+            (Some(t), Op::Eq) => Some((JmpType::Eq(t.clone()), JmpType::Ne(t))),
+            _ => None,
+        };
+        if let Some((holds, fails)) = jumps {
+            genExpression(ctx.makeExpressionCtx(left, None))?;
+            genExpression(ctx.makeExpressionCtx(right, None))?;
+            ctx.ops.jump(target, if negated { holds } else { fails });
+            return Ok(());
+        }
+    }
+    genExpression(ctx.makeExpressionCtx(condition, None))?;
+    ctx.ops.jump(target, JmpType::False);
+    Ok(())
+}
+
 fn genStatement(mut ctx: StatementCtx) -> Result<(), Box<dyn Error>> {
     match ctx.statement {
         Statement::FunctionExpr(ref e) => {
-            let mut argTypes = vec![];
-
-            for arg in &e.arguments {
-                let t = arg
-                    .toDataType(ctx.vTable, ctx.functionReturns, None)
-                    .unwrap();
-                match t {
-                    None => {
-                        return Err(Box::new(NoValue {
-                            msg: "e".to_string(),
-                        }))
-                    }
-                    Some(v) => {
-                        argTypes.push(v);
-                        genExpression(ctx.makeExpressionCtx(&arg, None))?;
-                    }
-                }
-            }
-
-            let funName = genFunName(e.name.as_str(), &argTypes).into_boxed_str();
-            let s = MyStr::from(funName);
-
-            let mut shouldPop = false;
-
-            if ctx.clearStack {
-                match ctx.functionReturns.get(&s) {
-                    None => {}
-                    Some(v) => match v {
-                        None => {}
-                        Some(_) => {
-                            shouldPop = true;
-                        }
-                    },
-                }
-            }
-
-            ctx.ops.push(Call { encoded: s });
-            if shouldPop {
+            let call = Expression::FunctionCall(e.clone());
+            let ret = call.toDataType(ctx.vTable, ctx.functionReturns, None)?;
+            genExpression(ctx.makeExpressionCtx(&call, None))?;
+            if ctx.clearStack && ret.is_some() {
                 ctx.ops.push(Pop)
             }
         }
@@ -364,68 +475,55 @@ fn genStatement(mut ctx: StatementCtx) -> Result<(), Box<dyn Error>> {
                             msg: format!("expected bool got {:?} {:?}", ve, w.exp),
                         }));
                     }
-                    let size = ctx.ops.len();
-                    genExpression(ctx.makeExpressionCtx(&w.exp, None))?;
-                    let mut bodyBuf = vec![];
+                    let labels = LoopLabels {
+                        next: ctx.ops.here(),
+                        exit: ctx.ops.label(),
+                    };
+                    genBranchUnless(&mut ctx, &w.exp, labels.exit)?;
                     for s in &w.body {
                         let mut ctx2 = ctx.copy(&s);
-                        ctx2.ops = &mut bodyBuf;
-                        ctx2.loopContext = Some(size - 2);
+                        ctx2.loopContext = Some(labels);
                         genStatement(ctx2)?;
                     }
-                    let len = bodyBuf.len();
-                    ctx.ops.push(OpCode::Jmp {
-                        offset: len as isize + 1,
-                        jmpType: JmpType::False,
-                    });
-                    ctx.ops.extend(bodyBuf);
-                    ctx.ops.push(OpCode::Jmp {
-                        offset: -(ctx.ops.len() as isize - size as isize + 1),
-                        jmpType: JmpType::Jmp,
-                    })
+                    ctx.ops.jump(labels.next, JmpType::Jmp);
+                    ctx.ops.place(labels.exit);
                 }
             }
         }
         Statement::If(flow) => {
-            let mut buf = vec![];
+            let otherwise = ctx.ops.label();
+            genBranchUnless(&mut ctx, &flow.condition, otherwise)?;
             for s in &flow.body {
-                let mut cop = ctx.copy(&s);
-                cop.ops = &mut buf;
-                genStatement(cop)?;
+                genStatement(ctx.copy(&s))?;
             }
 
-            genExpression(ctx.makeExpressionCtx(&flow.condition, None))?;
-            let mut jumpDist = buf.len() as isize;
-            if flow.elseBody.is_some() {
-                jumpDist += 1;
-            }
-            ctx.ops.push(Jmp {
-                offset: jumpDist,
-                jmpType: JmpType::False,
-            });
-            ctx.ops.extend(buf);
-
             match &flow.elseBody {
-                None => {}
+                None => ctx.ops.place(otherwise),
                 Some(els) => {
-                    buf = vec![];
+                    let end = ctx.ops.label();
+                    ctx.ops.jump(end, JmpType::Jmp);
+                    ctx.ops.place(otherwise);
                     for s in els {
-                        let mut ctx1 = ctx.copy(&s);
-                        ctx1.ops = &mut buf;
-                        genStatement(ctx1)?;
+                        genStatement(ctx.copy(&s))?;
                     }
-
-                    ctx.ops.push(OpCode::Jmp {
-                        offset: buf.len() as isize,
-                        jmpType: JmpType::Jmp,
-                    });
-                    ctx.ops.extend(buf);
+                    ctx.ops.place(end);
                 }
             }
         }
         Statement::Return(ret) => {
             genExpression(ctx.makeExpressionCtx(&ret.exp, None))?;
-            ctx.ops.push(OpCode::Return)
+            // call of script function as the returned value is tail call, builtins end with other ops
+            let tail = match ctx.ops.last() {
+                Some(Call { encoded }) if ctx.inFunction => Some(encoded.clone()),
+                _ => None,
+            };
+            match tail {
+                Some(encoded) if matches!(ret.exp, Expression::FunctionCall(_)) => {
+                    ctx.ops.pop();
+                    ctx.ops.push(TailCall { encoded })
+                }
+                _ => ctx.ops.push(OpCode::Return),
+            }
         }
         Statement::VariableMod(m) => {
             match ctx.vTable.get(&MyStr::Runtime(m.clone().varName.into_boxed_str())) {
@@ -452,48 +550,126 @@ fn genStatement(mut ctx: StatementCtx) -> Result<(), Box<dyn Error>> {
             }
         }
         Statement::ArrayAssign { left, right } => {
+            let target = left
+                .expr
+                .toDataType(ctx.vTable, ctx.functionReturns, None)?
+                .ok_or("cant index void")?;
             genExpression(ctx.makeExpressionCtx(&left.expr, None))?;
             let t = right
                 .toDataType(ctx.vTable, ctx.functionReturns, None)?
                 .ok_or("cant assign void to array")?;
             genExpression(ctx.makeExpressionCtx(&right, None))?;
             genExpression(ctx.makeExpressionCtx(&left.index, None))?;
-            ctx.ops.push(ArrayStore(t))
+            match mapTypes(&target) {
+                None => ctx.ops.push(ArrayStore(t)),
+                Some(_) => ctx.ops.push(MapStore(t)),
+            }
         }
         Statement::Continue => {
-            let index = ctx
+            let labels = ctx
                 .loopContext
                 .ok_or("continue can be only used in loops")?;
-            ctx.ops.push(Jmp {
-                offset: (index - ctx.ops.len() + 2) as isize,
-                jmpType: JmpType::Jmp,
-            })
+            ctx.ops.jump(labels.next, JmpType::Jmp)
+        }
+        Statement::Break => {
+            let labels = ctx.loopContext.ok_or("break can be only used in loops")?;
+            ctx.ops.jump(labels.exit, JmpType::Jmp)
         }
-        Statement::Break => panic!(),
         Statement::Loop(body) => {
-            let mut buf = vec![];
-            let context = ctx.ops.len();
+            let labels = LoopLabels {
+                next: ctx.ops.here(),
+                exit: ctx.ops.label(),
+            };
             for s in body {
                 let mut cop = ctx.copy(s);
-                cop.ops = &mut buf;
-                cop.loopContext = Some(context);
+                cop.loopContext = Some(labels);
                 genStatement(cop)?;
             }
-            let bufLen = buf.len() as isize;
-            ctx.ops.extend(buf);
-            ctx.ops.push(Jmp {
-                offset: -(bufLen + 1),
-                jmpType: JmpType::Jmp,
-            });
+            ctx.ops.jump(labels.next, JmpType::Jmp);
+            ctx.ops.place(labels.exit);
+        }
+        Statement::ForLoop(f) => {
+            let iterType = f.iterType(ctx.vTable, ctx.functionReturns)?;
+            let elementType = f.elementType(ctx.vTable, ctx.functionReturns)?;
+            let getLocal = |name: &MyStr| {
+                ctx.vTable
+                    .get(name)
+                    .map(|it| it.1)
+                    .ok_or_else(|| VariableNotFound { name: name.to_string() })
+            };
+            let iter = getLocal(&f.iterName())?;
+            let index = getLocal(&f.indexName())?;
+            let var = getLocal(&MyStr::from(f.varName.clone()))?;
+
+            genExpression(ctx.makeExpressionCtx(&f.iterable, None))?;
+            let iterable = f
+                .iterable
+                .toDataType(ctx.vTable, ctx.functionReturns, None)?
+                .ok_or("cannot iterate over none")?;
+            if let Some((key, _)) = mapTypes(&iterable) {
+                ctx.ops.push(MapKeys(key));
+            }
+            ctx.ops.push(SetLocal { index: iter, typ: iterType.clone() });
+            ctx.ops.push(PushInt(0));
+            ctx.ops.push(SetLocal { index, typ: Int });
+
+            let isGenerator = generatorType(&iterType).is_some();
+
+            let header = ctx.ops.here();
+            let labels = LoopLabels {
+                next: ctx.ops.label(),
+                exit: ctx.ops.label(),
+            };
+            if isGenerator {
+                ctx.ops.push(PushLocal { index: iter });
+                ctx.ops.push(GenResume);
+                ctx.ops.jump(labels.exit, JmpType::False);
+            } else {
+                ctx.ops.push(PushLocal { index });
+                ctx.ops.push(PushLocal { index: iter });
+                ctx.ops.push(ArrayLength);
+                ctx.ops.jump(labels.exit, JmpType::Ge(Int));
+            }
+
+            ctx.ops.push(PushLocal { index: iter });
+            if isGenerator {
+                ctx.ops.push(GenValue(elementType.clone()));
+            } else if iterType == DataType::str() {
+                ctx.ops.push(PushLocal { index });
+                ctx.ops.push(GetChar);
+            } else {
+                ctx.ops.push(PushLocal { index });
+                ctx.ops.push(ArrayLoad(elementType.clone()));
+            }
+            ctx.ops.push(SetLocal { index: var, typ: elementType });
+
+            for s in &f.body {
+                let mut ctx2 = ctx.copy(&s);
+                ctx2.loopContext = Some(labels);
+                genStatement(ctx2)?;
+            }
+            ctx.ops.place(labels.next);
+            if !isGenerator {
+                ctx.ops.push(Inc { typ: Int, index });
+            }
+            ctx.ops.jump(header, JmpType::Jmp);
+            ctx.ops.place(labels.exit);
         }
+        Statement::Yield(e) => {
+            genExpression(ctx.makeExpressionCtx(e, None))?;
+            ctx.ops.push(Yield)
+        }
+        Statement::Location(l) => ctx.ops.mark(*l),
     }
     Ok(())
 }
 
 fn genFunctionDef(
     fun: FunctionDef,
-    ops: &mut Vec<OpCode>,
+    out: &mut Vec<OpCode>,
     functionReturns: &HashMap<MyStr, Option<DataType>>,
+    lines: &mut LineTable,
+    location: Location,
 ) -> Result<(), Box<dyn Error>> {
     if fun.isNative {
         let c = fun.argCount;
@@ -501,18 +677,20 @@ fn genFunctionDef(
         crate::cGen::genFunctionDef(fun.clone(), &mut buf, functionReturns)?;
         let resPath = crate::gccWrapper::compile(&buf)?;
 
-        ops.push(OpCode::StrNew(MyStr::Runtime(resPath.into_boxed_str())));
-        ops.push(StrNew(MyStr::Runtime(
+        out.push(OpCode::StrNew(MyStr::Runtime(resPath.into_boxed_str())));
+        out.push(StrNew(MyStr::Runtime(
             genFunNameMeta(&fun.name, &fun.args, c).into_boxed_str(),
         )));
-        ops.push(OpCode::PushInt(fun.argCount as isize));
-        ops.push(OpCode::Call {
+        out.push(OpCode::PushInt(fun.argCount as isize));
+        out.push(OpCode::Call {
             encoded: MyStr::Runtime(Box::from("loadNative(String, String, int)")),
         });
 
         return Ok(());
     }
 
+    let mut ops = BytecodeBuilder::new();
+    ops.mark(location);
     ops.push(OpCode::FunBegin);
     ops.push(FunName {
         name: MyStr::Runtime(fun.name.clone().into_boxed_str()),
@@ -535,23 +713,54 @@ fn genFunctionDef(
         typ: idk2.into_boxed_slice(),
         argsCount: fun.argCount,
     });
+    let mut yields = vec![];
+    collectYields(&fun.body, &mut yields);
+
+    let generator = fun.returnType.as_ref().and_then(generatorType);
+    match &generator {
+        None => {
+            if !yields.is_empty() {
+                return Err(format!("function {} yields but doesnt return Generator", fun.name).into());
+            }
+        }
+        Some(t) => {
+            for y in yields {
+                let yt = y.toDataType(&idk1, functionReturns, None)?;
+                if yt.as_ref() != Some(t) {
+                    return Err(Box::new(InvalidTypeException {
+                        expected: t.clone(),
+                        actual: yt,
+                    }));
+                }
+            }
+        }
+    }
+
     ops.push(FunReturn {
         typ: fun.returnType,
     });
 
+    // calling generator function only captures its frame, body runs on resume
+    if let Some(t) = generator {
+        ops.push(GenNew(t));
+        ops.push(OpCode::Return);
+    }
+
     for statement in fun.body {
         let ctx = StatementCtx {
             statement: &statement,
-            ops,
+            ops: &mut ops,
             functionReturns,
             vTable: &idk1,
             loopContext: None,
             clearStack: true,
+            inFunction: true,
         };
         genStatement(ctx)?;
     }
     ops.push(OpCode::Return);
-    ops.push(OpCode::FunEnd);
+    ops.finish(out, lines, Some(&fun.name.into()))?;
+    out.push(OpCode::FunEnd);
     Ok(())
 }
 
@@ -617,12 +826,31 @@ pub fn buildLocalsTable(
                 buildLocalsTable(s, mainLocals, localTypes, functionReturns)?;
             }
         }
+        Statement::ForLoop(f) => {
+            let locals = [
+                (f.iterName(), f.iterType(mainLocals, functionReturns)?),
+                (f.indexName(), Int),
+                (
+                    MyStr::from(f.varName.clone()),
+                    f.elementType(mainLocals, functionReturns)?,
+                ),
+            ];
+            for (name, typ) in locals {
+                mainLocals.insert(name.clone(), (typ.clone(), localTypes.len()));
+                localTypes.push(VariableMetadata { name, typ });
+            }
+            for s in &f.body {
+                buildLocalsTable(s, mainLocals, localTypes, functionReturns)?;
+            }
+        }
         Statement::FunctionExpr(_) => {}
         Statement::VariableMod(_) => {}
         Statement::Return(_) => {}
         Statement::ArrayAssign { .. } => {}
         Statement::Continue => {}
         Statement::Break => {}
+        Statement::Yield(_) => {}
+        Statement::Location(_) => {}
     }
 
     Ok(())
@@ -635,7 +863,9 @@ pub fn complexBytecodeGen(
     mainLocals: &mut HashMap<MyStr, (DataType, usize)>,
     structs: &mut HashMap<MyStr, HashMap<String, DataType>>,
     clearStack: bool,
-) -> Result<Vec<OpCode>, Box<dyn Error>> {
+) -> Result<(Vec<OpCode>, LineTable), Box<dyn Error>> {
+    checkProgram(&operations, functionReturns, mainLocals)?;
+
     let mut inlineMain = vec![];
     let mut ops = vec![];
 
@@ -658,6 +888,11 @@ pub fn complexBytecodeGen(
                 }
             },
             Operation::Statement(v) => {
+                let mut yields = vec![];
+                collectYields(std::slice::from_ref(v), &mut yields);
+                if !yields.is_empty() {
+                    return Err("yield outside of generator function".into());
+                }
                 if let Variable(c) = v {
                     match c.init {
                         None => {
@@ -673,23 +908,36 @@ pub fn complexBytecodeGen(
                             )?;
                             mainLocals.insert(
                                 c.name.clone().into_boxed_str().into(),
-                                (t.clone().unwrap(), mainLocals.len()),
+                                (t.clone().unwrap(), localTypes.len()),
                             );
                             localTypes.push(t.unwrap());
                         }
                     }
                 }
+                if let Statement::ForLoop(_) = v {
+                    let mut metas = localTypes
+                        .iter()
+                        .map(|it| VariableMetadata::from(it.clone()))
+                        .collect::<Vec<_>>();
+                    buildLocalsTable(v, mainLocals, &mut metas, functionReturns)?;
+                    localTypes.extend(metas.into_iter().skip(localTypes.len()).map(|it| it.typ));
+                }
                 inlineMain.push(op.clone())
             }
             _ => inlineMain.push(op.clone()),
         }
     }
 
+    let mut lines = LineTable::default();
+    let mut location = Location::default();
     for op in &operations {
+        if let Operation::Statement(Statement::Location(l)) = op {
+            location = *l;
+        }
         if let Operation::Global(f) = op {
             match f {
                 Node::FunctionDef(v) => {
-                    genFunctionDef(v.clone(), &mut ops, functionReturns)?;
+                    genFunctionDef(v.clone(), &mut ops, functionReturns, &mut lines, location)?;
                 }
                 Node::StructDef(v) => {
                     genStructDef(v.clone(), &mut ops, functionReturns, structs)?;
@@ -698,23 +946,25 @@ pub fn complexBytecodeGen(
         }
     }
 
+    let mut builder = BytecodeBuilder::new();
     for op in &inlineMain {
         match op {
             Operation::Statement(s) => {
                 let ctx = StatementCtx {
                     statement: s,
-                    ops: &mut ops,
+                    ops: &mut builder,
                     functionReturns,
                     vTable: mainLocals,
                     loopContext: None,
                     clearStack,
+                    inFunction: false,
                 };
                 genStatement(ctx)?;
             }
             Operation::Expr(e) => {
                 let ctx = ExpressionCtx {
                     exp: &e,
-                    ops: &mut ops,
+                    ops: &mut builder,
                     functionReturns,
                     vTable: &mainLocals,
                     typeHint: None,
@@ -724,15 +974,16 @@ pub fn complexBytecodeGen(
             _ => {}
         }
     }
+    builder.finish(&mut ops, &mut lines, None)?;
 
-    Ok(ops)
+    Ok((ops, lines))
 }
 
 pub fn bytecodeGen(
     operations: Vec<Operation>,
-) -> Result<(Vec<OpCode>, Vec<DataType>), Box<dyn Error>> {
+) -> Result<(Vec<OpCode>, Vec<DataType>, LineTable), Box<dyn Error>> {
     let mut mainLocals = HashMap::new();
-    let mut functionReturns = HashMap::new();
+    let mut functionReturns = bootStrapVM().functionReturns();
     let mut localTypes = vec![];
     let mut structs = HashMap::new();
 
@@ -745,13 +996,13 @@ pub fn bytecodeGen(
         true,
     )?;
 
-    Ok((res, localTypes))
+    Ok((res.0, localTypes, res.1))
 }
 
 pub fn bytecodeGen2(
     operations: Vec<Operation>,
     functionReturns: &mut HashMap<MyStr, Option<DataType>>,
-) -> Result<(Vec<OpCode>, Vec<DataType>), Box<dyn Error>> {
+) -> Result<(Vec<OpCode>, Vec<DataType>, LineTable), Box<dyn Error>> {
     let mut mainLocals = HashMap::new();
     let mut localTypes = vec![];
     let mut structs = HashMap::new();
@@ -765,5 +1016,5 @@ pub fn bytecodeGen2(
         true,
     )?;
 
-    Ok((res, localTypes))
+    Ok((res.0, localTypes, res.1))
 }
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::ffi::{c_char, CStr};
 use std::fmt::{Debug, Formatter};
 use std::mem::forget;
@@ -5,14 +6,38 @@ use std::ptr;
 use std::rc::Rc;
 use std::thread::{sleep, Thread};
 
-use crate::codegen::bytecodeGen;
+use crate::codegen::bytecodeGen2;
 use crate::lexer::{lexingUnits, SourceProvider};
 use crate::objects::{Str, ViplObject};
 use crate::std::bootStrapVM;
-use crate::vm::{DataType, MyStr, OpCode, run, SeekableOpcodes, StackFrame, Value, VirtualMachine};
+use crate::vm::{
+    DataType, link, MyStr, OpCode, run, runTraced, SeekableOpcodes, StackFrame, Value,
+    VirtualMachine,
+};
+use crate::vmError::VmError;
 
 const DEBUG: bool = false;
 
+// c side cannot receive errors, first one is kept and raised when the extern function returns
+fn check<T>(pending: &mut Option<VmError>, res: Result<T, VmError>) -> Option<T> {
+    match res {
+        Ok(v) => Some(v),
+        Err(e) => {
+            pending.get_or_insert(e);
+            None
+        }
+    }
+}
+
+fn pop(vm: &mut VirtualMachine) -> Result<Value, VmError> {
+    vm.stack.pop().ok_or(VmError::StackUnderflow)
+}
+
+fn local(locals: &StackFrame, index: usize) -> Result<Value, VmError> {
+    let len = locals.localVariables.len();
+    locals.localVariables.get(index).cloned().ok_or(VmError::IndexOutOfBounds { index: index as isize, len })
+}
+
 
 #[no_mangle]
 pub extern fn createVm() -> *mut VirtualMachine {
@@ -94,7 +119,9 @@ pub extern fn evaluate(vm: &mut VirtualMachine, d: *const u8, len: usize) {
 
     // println!("{:?}", &ast);
 
-    let mut opCodes = match bytecodeGen(ast) {
+    let mut functionReturns = vm.functionReturns();
+
+    let opCodes = match bytecodeGen2(ast, &mut functionReturns) {
         Ok(v) => v,
         Err(_) => {
             println!("codegen failed");
@@ -104,14 +131,18 @@ pub extern fn evaluate(vm: &mut VirtualMachine, d: *const u8, len: usize) {
 
     println!("{:?}", &opCodes);
 
-    for _ in 0..opCodes.0.len() {
-        vm.opCodeCache.push(None)
+    vm.lines = opCodes.2.clone();
+
+    let mut code = vec![];
+    if let Err(e) = link(vm, &opCodes.0, &mut code) {
+        println!("linking failed {e}");
+        return;
     }
 
-    run(
+    let res = runTraced(
         &mut SeekableOpcodes {
             index: 0,
-            opCodes: &mut opCodes.0,
+            opCodes: &mut code,
         },
         vm,
         &mut StackFrame::new(
@@ -123,30 +154,34 @@ pub extern fn evaluate(vm: &mut VirtualMachine, d: *const u8, len: usize) {
         ),
     );
 
+    if let Err(e) = res {
+        println!("{}", vm.describeError(&e));
+        return;
+    }
+
     println!("finished");
 }
 
 #[no_mangle]
 pub extern fn test(vm: *mut VirtualMachine) {
     println!("i am here");
-    let mut ops = vec![
+    let ops = vec![
         OpCode::PushInt(69),
         OpCode::Call {
             encoded: MyStr::Static("print(int)"),
         },
     ];
 
+    let mut code = vec![];
     unsafe {
-        for _ in 0..ops.len() {
-            (*vm).opCodeCache.push(None)
-        }
+        link(&mut *vm, &ops, &mut code).unwrap();
     }
 
-    unsafe {
+    let res = unsafe {
         run(
             &mut SeekableOpcodes {
                 index: 0,
-                opCodes: &mut ops,
+                opCodes: &mut code,
             },
             &mut *vm as &mut VirtualMachine,
             &mut StackFrame {
@@ -154,8 +189,13 @@ pub extern fn test(vm: *mut VirtualMachine) {
                 localVariables: &mut [],
                 name: None,
                 objects: None,
+                pendingError: None,
             },
         )
+    };
+
+    if let Err(e) = res {
+        println!("{e}");
     }
 }
 
@@ -208,9 +248,7 @@ pub extern fn pushRef(vm: &mut VirtualMachine, v: *mut ViplObject) {
 
         Rc::increment_strong_count(v);
         let rc = Rc::from_raw(v);
-        vm.stack.push(Value::Reference {
-            instance: Some(rc),
-        });
+        vm.stack.push(Value::Reference(Some(rc)));
     }
 }
 
@@ -219,7 +257,8 @@ pub extern fn popInt(vm: &mut VirtualMachine) -> isize {
     if DEBUG {
         println!("ffi-popInt");
     }
-    vm.stack.pop().unwrap().getNum()
+    let res = pop(vm).and_then(|v| v.getNum());
+    check(&mut vm.pendingError, res).unwrap_or_default()
 }
 
 #[no_mangle]
@@ -227,7 +266,8 @@ pub extern fn popFloat(vm: &mut VirtualMachine) -> f32 {
     if DEBUG {
         println!("ffi-popFloat");
     }
-    vm.stack.pop().unwrap().getFlo()
+    let res = pop(vm).and_then(|v| v.getFlo());
+    check(&mut vm.pendingError, res).unwrap_or_default()
 }
 
 #[no_mangle]
@@ -235,7 +275,8 @@ pub extern fn popChar(vm: &mut VirtualMachine) -> u8 {
     if DEBUG {
         println!("ffi-popChar");
     }
-    vm.stack.pop().unwrap().getChar() as u8
+    let res = pop(vm).and_then(|v| v.getChar()).map(|c| c as u8);
+    check(&mut vm.pendingError, res).unwrap_or_default()
 }
 
 #[no_mangle]
@@ -243,7 +284,8 @@ pub extern fn popBool(vm: &mut VirtualMachine) -> bool {
     if DEBUG {
         println!("ffi-popBool");
     }
-    vm.stack.pop().unwrap().getBool()
+    let res = pop(vm).and_then(|v| v.getBool());
+    check(&mut vm.pendingError, res).unwrap_or_default()
 }
 
 #[no_mangle]
@@ -251,12 +293,8 @@ pub extern fn popRef(vm: &mut VirtualMachine, locals: &mut StackFrame) -> *mut V
     if DEBUG {
         println!("ffi-popRef");
     }
-    let rc = vm.stack.pop().unwrap().getReference().clone().unwrap();
-
-    locals.addObject(rc.clone());
-
-    let ptr = Rc::into_raw(rc);
-    ptr as *mut ViplObject
+    let res = pop(vm).and_then(|v| v.intoObject());
+    check(&mut vm.pendingError, res).map_or(ptr::null_mut(), |rc| locals.addObject(rc))
 }
 
 #[no_mangle]
@@ -264,7 +302,8 @@ pub extern fn getLocalsInt(vm: &mut StackFrame, index: usize) -> isize {
     if DEBUG {
         println!("ffi-getLocalsInt");
     }
-    unsafe { vm.localVariables.get(index).unwrap() }.getNum()
+    let res = local(vm, index).and_then(|v| v.getNum());
+    check(&mut vm.pendingError, res).unwrap_or_default()
 }
 
 #[no_mangle]
@@ -272,7 +311,8 @@ pub extern fn getLocalsFloat(vm: &mut StackFrame, index: usize) -> f32 {
     if DEBUG {
         println!("ffi-getLocalsFloat");
     }
-    unsafe { vm.localVariables.get(index).unwrap() }.getFlo()
+    let res = local(vm, index).and_then(|v| v.getFlo());
+    check(&mut vm.pendingError, res).unwrap_or_default()
 }
 
 #[no_mangle]
@@ -280,7 +320,8 @@ pub extern fn getLocalsChar(vm: &mut StackFrame, index: usize) -> u8 {
     if DEBUG {
         println!("ffi-getLocalsChar");
     }
-    unsafe { vm.localVariables.get(index).unwrap() }.getChar() as u8
+    let res = local(vm, index).and_then(|v| v.getChar()).map(|c| c as u8);
+    check(&mut vm.pendingError, res).unwrap_or_default()
 }
 
 #[no_mangle]
@@ -288,7 +329,8 @@ pub extern fn getLocalsBool(vm: &mut StackFrame, index: usize) -> bool {
     if DEBUG {
         println!("ffi-getLocalsBool");
     }
-    unsafe { vm.localVariables.get(index).unwrap() }.getBool()
+    let res = local(vm, index).and_then(|v| v.getBool());
+    check(&mut vm.pendingError, res).unwrap_or_default()
 }
 
 #[no_mangle]
@@ -296,93 +338,71 @@ pub extern fn getLocalsRef(locals: &mut StackFrame, index: usize) -> *mut ViplOb
     if DEBUG {
         println!("ffi-getLocalsRef");
     }
-    let rc = locals.localVariables.get(index).unwrap()
-        .getReference()
-        .clone()
-        .unwrap();
-    locals.addObject(rc.clone());
-    Rc::into_raw(rc) as *mut ViplObject
+    let res = local(locals, index).and_then(|v| v.intoObject());
+    check(&mut locals.pendingError, res).map_or(ptr::null_mut(), |rc| locals.addObject(rc))
 }
 
 #[no_mangle]
-pub extern fn stringGetChar(vm: &mut VirtualMachine, obj: &mut ViplObject, index: usize) -> u8 {
+pub extern fn stringGetChar(vm: &mut VirtualMachine, obj: &ViplObject, index: usize) -> u8 {
     if DEBUG {
         // println!("ffi-stringGetChar");
     }
 
-    *obj.getStr().string.as_bytes().get(index).unwrap() as u8
+    let res = obj.getStr().and_then(|s| {
+        let len = s.string.len();
+        s.string.as_bytes().get(index).copied().ok_or(VmError::IndexOutOfBounds { index: index as isize, len })
+    });
+    check(&mut vm.pendingError, res).unwrap_or_default()
 }
 
 #[no_mangle]
-pub extern fn arrGetInt(vm: &mut VirtualMachine, obj: &mut ViplObject, index: usize) -> isize {
+pub extern fn arrGetInt(vm: &mut VirtualMachine, obj: &ViplObject, index: usize) -> isize {
     if DEBUG {
         println!("ffi-arrGetInt");
     }
-    match obj {
-        ViplObject::Arr(a) => a.internal.get(index).unwrap().getNum(),
-        _ => panic!(),
-    }
+    let res = obj.getArr().and_then(|a| a.get(index as isize)).and_then(|v| v.getNum());
+    check(&mut vm.pendingError, res).unwrap_or_default()
 }
 
 #[no_mangle]
-pub extern fn arrGetFloat(vm: &mut VirtualMachine, obj: &mut ViplObject, index: usize) -> f32 {
+pub extern fn arrGetFloat(vm: &mut VirtualMachine, obj: &ViplObject, index: usize) -> f32 {
     if DEBUG {
         println!("ffi-arrGetFloat");
     }
-    match obj {
-        ViplObject::Arr(a) => a.internal.get(index).unwrap().getFlo(),
-        _ => panic!(),
-    }
+    let res = obj.getArr().and_then(|a| a.get(index as isize)).and_then(|v| v.getFlo());
+    check(&mut vm.pendingError, res).unwrap_or_default()
 }
 
 #[no_mangle]
-pub extern fn arrGetBool(vm: &mut VirtualMachine, obj: &mut ViplObject, index: usize) -> bool {
+pub extern fn arrGetBool(vm: &mut VirtualMachine, obj: &ViplObject, index: usize) -> bool {
     if DEBUG {
         println!("ffi-arrGetBool");
     }
-    match obj {
-        ViplObject::Arr(a) => a.internal.get(index).unwrap().getBool(),
-        _ => panic!(),
-    }
+    let res = obj.getArr().and_then(|a| a.get(index as isize)).and_then(|v| v.getBool());
+    check(&mut vm.pendingError, res).unwrap_or_default()
 }
 
 #[no_mangle]
-pub extern fn arrGetChar(vm: &mut VirtualMachine, obj: &mut ViplObject, index: usize) -> u8 {
+pub extern fn arrGetChar(vm: &mut VirtualMachine, obj: &ViplObject, index: usize) -> u8 {
     if DEBUG {
         println!("ffi-arrGetChar");
     }
-    match obj {
-        ViplObject::Arr(a) => a.internal.get(index).unwrap().getChar() as u8,
-        _ => panic!(),
-    }
+    let res = obj.getArr().and_then(|a| a.get(index as isize)).and_then(|v| v.getChar()).map(|c| c as u8);
+    check(&mut vm.pendingError, res).unwrap_or_default()
 }
 
 #[no_mangle]
 pub extern fn arrGetRef(
     vm: &mut VirtualMachine,
     locals: &mut StackFrame,
-    obj: &mut ViplObject,
+    obj: &ViplObject,
     index: usize,
 ) -> *mut ViplObject {
     if DEBUG {
         println!("ffi-arrGetRef");
     }
-    match obj {
-        ViplObject::Arr(a) => {
-            let rc = a
-                .internal
-                .get(index)
-                .unwrap()
-                .getReference()
-                .clone()
-                .unwrap();
-
-            locals.addObject(rc.clone());
-
-            Rc::into_raw(rc) as *mut ViplObject
-        }
-        _ => panic!(),
-    }
+    let res = obj.getArr().and_then(|a| a.get(index as isize)).and_then(|v| v.intoObject());
+    check(&mut vm.pendingError, res).map_or(ptr::null_mut(), |rc| locals.addObject(rc))
 }
 
 #[no_mangle]
@@ -391,7 +411,12 @@ pub extern fn call(vm: &mut VirtualMachine, s: *const c_char) {
     if DEBUG {
         println!("ffi-call: {}", name);
     }
-    vm.call(MyStr::Runtime(name.to_owned().into_boxed_str()));
+    // native has already failed, its result would be discarded anyway
+    if vm.pendingError.is_some() {
+        return;
+    }
+    let res = vm.call(MyStr::Runtime(name.to_owned().into_boxed_str()));
+    check(&mut vm.pendingError, res);
 }
 
 #[no_mangle]
@@ -400,32 +425,90 @@ pub extern fn stringNew(vm: &mut VirtualMachine, locals: &mut StackFrame, s: *co
         println!("ffi-stringNew");
     }
     let st = unsafe { CStr::from_ptr(s) }.to_str().unwrap().to_owned();
-    let rc = Rc::new(ViplObject::Str(Str { string: st }));
-
-    locals.addObject(rc.clone());
+    let rc = ViplObject::alloc(ViplObject::Str(Str { string: st }));
 
-    Rc::into_raw(rc) as *mut ViplObject
+    locals.addObject(rc)
 }
 
 #[no_mangle]
 pub extern fn strConcat(
     vm: &mut VirtualMachine,
     locals: &mut StackFrame,
-    s1: &mut ViplObject,
-    s2: &mut ViplObject,
+    s1: &ViplObject,
+    s2: &ViplObject,
 ) -> *mut ViplObject {
     if DEBUG {
         println!("ffi-strConcat");
     }
-    let mut s3 = String::new();
-    s3.push_str(&s1.getStr().string);
-    s3.push_str(&s2.getStr().string);
+    let res = s1.getStr().and_then(|a| Ok(format!("{}{}", a.string, s2.getStr()?.string)));
+    let s3 = match check(&mut vm.pendingError, res) {
+        Some(s) => s,
+        None => return ptr::null_mut(),
+    };
 
     // FIXME not sure if this is needed
-    let rc = Rc::new(ViplObject::Str(Str { string: s3 }));
-    locals.addObject(rc.clone());
+    let rc = ViplObject::alloc(ViplObject::Str(Str { string: s3 }));
+    locals.addObject(rc)
+}
 
-    Rc::into_raw(rc) as *mut ViplObject
+#[no_mangle]
+pub extern fn strCmp(vm: &mut VirtualMachine, s1: &ViplObject, s2: &ViplObject) -> isize {
+    if DEBUG {
+        println!("ffi-strCmp");
+    }
+    let res = s1.getStr().and_then(|a| Ok(a.string.cmp(&s2.getStr()?.string) as isize));
+    check(&mut vm.pendingError, res).unwrap_or_default()
+}
+
+// map functions take the key (and value) from the stack so that they dont need variant per type
+
+#[no_mangle]
+pub extern fn mapGet(vm: &mut VirtualMachine, obj: &ViplObject) {
+    if DEBUG {
+        println!("ffi-mapGet");
+    }
+    let res = pop(vm).and_then(|key| {
+        obj.getMap()?.get(&key)?.ok_or_else(|| VmError::KeyNotFound(key.valueStr()))
+    });
+    // pushing null keeps stack balanced for code that pops the result
+    let value = check(&mut vm.pendingError, res).unwrap_or_else(Value::null);
+    vm.stack.push(value)
+}
+
+#[no_mangle]
+pub extern fn mapSet(vm: &mut VirtualMachine, obj: &ViplObject) {
+    if DEBUG {
+        println!("ffi-mapSet");
+    }
+    let res = pop(vm).and_then(|value| obj.getMap()?.insert(&pop(vm)?, value));
+    check(&mut vm.pendingError, res);
+}
+
+#[no_mangle]
+pub extern fn mapContains(vm: &mut VirtualMachine, obj: &ViplObject) -> bool {
+    if DEBUG {
+        println!("ffi-mapContains");
+    }
+    let res = pop(vm).and_then(|key| obj.getMap()?.contains(&key));
+    check(&mut vm.pendingError, res).unwrap_or_default()
+}
+
+#[no_mangle]
+pub extern fn mapRemove(vm: &mut VirtualMachine, obj: &ViplObject) {
+    if DEBUG {
+        println!("ffi-mapRemove");
+    }
+    let res = pop(vm).and_then(|key| obj.getMap()?.remove(&key));
+    check(&mut vm.pendingError, res);
+}
+
+#[no_mangle]
+pub extern fn mapLen(vm: &mut VirtualMachine, obj: &ViplObject) -> isize {
+    if DEBUG {
+        println!("ffi-mapLen");
+    }
+    let res = obj.getMap().map(|m| m.len() as isize);
+    check(&mut vm.pendingError, res).unwrap_or_default()
 }
 
 #[repr(C)]
@@ -448,16 +531,24 @@ pub struct NativeWrapper {
     pub getLocalsChar: extern fn(&mut StackFrame, usize) -> u8,
     pub getLocalsRef: extern fn(&mut StackFrame, usize) -> *mut ViplObject,
 
-    pub arrGetInt: extern fn(&mut VirtualMachine, &mut ViplObject, usize) -> isize,
-    pub arrGetFloat: extern fn(&mut VirtualMachine, &mut ViplObject, usize) -> f32,
-    pub arrGetBool: extern fn(&mut VirtualMachine, &mut ViplObject, usize) -> bool,
-    pub arrGetChar: extern fn(&mut VirtualMachine, &mut ViplObject, usize) -> u8,
-    pub arrGetRef: extern fn(&mut VirtualMachine, &mut StackFrame, &mut ViplObject, usize) -> *mut ViplObject,
+    pub arrGetInt: extern fn(&mut VirtualMachine, &ViplObject, usize) -> isize,
+    pub arrGetFloat: extern fn(&mut VirtualMachine, &ViplObject, usize) -> f32,
+    pub arrGetBool: extern fn(&mut VirtualMachine, &ViplObject, usize) -> bool,
+    pub arrGetChar: extern fn(&mut VirtualMachine, &ViplObject, usize) -> u8,
+    pub arrGetRef: extern fn(&mut VirtualMachine, &mut StackFrame, &ViplObject, usize) -> *mut ViplObject,
 
     pub call: extern fn(&mut VirtualMachine, *const c_char),
     pub stringNew: extern fn(&mut VirtualMachine, &mut StackFrame, *const c_char) -> *mut ViplObject,
-    pub stringGetChar: extern fn(&mut VirtualMachine, &mut ViplObject, usize) -> u8,
-    pub strConcat: extern fn(&mut VirtualMachine, &mut StackFrame, &mut ViplObject, &mut ViplObject) -> *mut ViplObject,
+    pub stringGetChar: extern fn(&mut VirtualMachine, &ViplObject, usize) -> u8,
+    pub strConcat: extern fn(&mut VirtualMachine, &mut StackFrame, &ViplObject, &ViplObject) -> *mut ViplObject,
+
+    pub mapGet: extern fn(&mut VirtualMachine, &ViplObject),
+    pub mapSet: extern fn(&mut VirtualMachine, &ViplObject),
+    pub mapContains: extern fn(&mut VirtualMachine, &ViplObject) -> bool,
+    pub mapRemove: extern fn(&mut VirtualMachine, &ViplObject),
+    pub mapLen: extern fn(&mut VirtualMachine, &ViplObject) -> isize,
+
+    pub strCmp: extern fn(&mut VirtualMachine, &ViplObject, &ViplObject) -> isize,
 }
 
 impl Debug for NativeWrapper {
@@ -493,6 +584,12 @@ impl NativeWrapper {
             stringNew,
             stringGetChar,
             strConcat,
+            mapGet,
+            mapSet,
+            mapContains,
+            mapRemove,
+            mapLen,
+            strCmp,
         }
     }
 }
@@ -1,6 +1,7 @@
 use std::fs;
 
 use crate::vm::{DataType, Generic, MyStr, Value, VariableMetadata, VirtualMachine};
+use crate::vmError::VmError;
 
 pub fn setupFs(vm: &mut VirtualMachine) {
     vm.makeNative(
@@ -10,7 +11,7 @@ pub fn setupFs(vm: &mut VirtualMachine) {
             typ: DataType::str(),
         }]),
         |vm, locals| {
-            let path = locals.localVariables.first().unwrap().getString();
+            let path = locals.localVariables.first().unwrap().getString()?;
 
             let mut buf = vec![];
 
@@ -22,10 +23,11 @@ pub fn setupFs(vm: &mut VirtualMachine) {
                         )))
                     }
                 }
-                Err(e) => panic!("{}", e)
+                Err(e) => return Err(VmError::NativeError(e.to_string())),
             }
 
-            vm.stack.push(Value::makeArray(buf, DataType::str()))
+            vm.stack.push(Value::makeArray(buf, DataType::str()));
+            Ok(())
         },
         Some(DataType::arr(Generic::Type(DataType::str()))),
     );
@@ -37,10 +39,11 @@ pub fn setupFs(vm: &mut VirtualMachine) {
             typ: DataType::str(),
         }]),
         |vm, locals| {
-            let path = locals.localVariables.first().unwrap().getString();
+            let path = locals.localVariables.first().unwrap().getString()?;
 
             let str = fs::read_to_string(path).unwrap_or_default();
-            vm.stack.push(Value::makeString(str))
+            vm.stack.push(Value::makeString(str));
+            Ok(())
         },
         Some(DataType::str()),
     );
@@ -52,7 +55,7 @@ pub fn setupFs(vm: &mut VirtualMachine) {
             typ: DataType::str(),
         }]),
         |vm, locals| {
-            let path = locals.localVariables.first().unwrap().getString();
+            let path = locals.localVariables.first().unwrap().getString()?;
 
             let val = match fs::metadata(path) {
                 Ok(v) => {
@@ -71,7 +74,8 @@ pub fn setupFs(vm: &mut VirtualMachine) {
                 Err(_) => 0,
             };
 
-            vm.stack.push(Value::Num(val))
+            vm.stack.push(Value::Num(val));
+            Ok(())
         },
         Some(DataType::Int),
     );
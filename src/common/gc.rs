@@ -0,0 +1,174 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+
+use crate::objects::{heapResize, ViplObject};
+use crate::vm::{Value, ValueKind};
+
+// registry size that triggers first collection
+const MIN_THRESHOLD: usize = 4096;
+
+// objects are reference counted, collector frees only cycles that counting cannot
+// references from outside of the heap like operand stack, frames, globals and native handles are roots
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+    pub collections: usize,
+    // objects freed by breaking cycles
+    pub freed: usize,
+    // tracked objects that survived last collection
+    pub live: usize,
+    pub time: Duration,
+}
+
+struct Registry {
+    // objects that can hold references, dead entries are dropped by collection
+    objects: Vec<Weak<ViplObject>>,
+    threshold: usize,
+    stats: GcStats,
+}
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = RefCell::new(Registry {
+        objects: vec![],
+        threshold: MIN_THRESHOLD,
+        stats: GcStats::default(),
+    });
+}
+
+pub fn track(obj: &Rc<ViplObject>) {
+    if matches!(&**obj, ViplObject::Arr(_) | ViplObject::Map(_) | ViplObject::Gen(_) | ViplObject::Runtime(_)) {
+        REGISTRY.with(|it| it.borrow_mut().objects.push(Rc::downgrade(obj)))
+    }
+}
+
+pub fn shouldCollect() -> bool {
+    REGISTRY.with(|it| {
+        let r = it.borrow();
+        r.objects.len() >= r.threshold
+    })
+}
+
+pub fn gcStats() -> GcStats {
+    REGISTRY.with(|it| it.borrow().stats)
+}
+
+// must not run while script holds borrow of object, vm calls it only between instructions
+pub fn collect() -> usize {
+    let start = Instant::now();
+    let weak = REGISTRY.with(|it| std::mem::take(&mut it.borrow_mut().objects));
+    let objects = weak.iter().filter_map(Weak::upgrade).collect::<Vec<_>>();
+    drop(weak);
+
+    let index = objects
+        .iter()
+        .enumerate()
+        .map(|(i, it)| (Rc::as_ptr(it), i))
+        .collect::<HashMap<_, _>>();
+
+    // references not coming from tracked objects, the upgrade above is not counted
+    let mut external = objects.iter().map(|it| Rc::strong_count(it) - 1).collect::<Vec<_>>();
+    for o in &objects {
+        o.trace(&mut |child| {
+            if let Some(i) = index.get(&Rc::as_ptr(child)) {
+                external[*i] = external[*i].saturating_sub(1);
+            }
+        })
+    }
+
+    let mut reachable = vec![false; objects.len()];
+    let mut pending = (0..objects.len()).filter(|it| external[*it] > 0).collect::<Vec<_>>();
+    while let Some(i) = pending.pop() {
+        if reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        objects[i].trace(&mut |child| {
+            if let Some(c) = index.get(&Rc::as_ptr(child)) {
+                if !reachable[*c] {
+                    pending.push(*c)
+                }
+            }
+        })
+    }
+
+    // garbage is kept alive by objects until all its references are cleared
+    let mut freed = 0;
+    for (i, o) in objects.iter().enumerate() {
+        if !reachable[i] {
+            o.clearReferences();
+            freed += 1;
+        }
+    }
+
+    let survivors = objects
+        .iter()
+        .zip(&reachable)
+        .filter(|(_, r)| **r)
+        .map(|(it, _)| Rc::downgrade(it))
+        .collect::<Vec<_>>();
+    drop(objects);
+
+    REGISTRY.with(|it| {
+        let mut r = it.borrow_mut();
+        // objects allocated by destructors of garbage are kept
+        let allocated = std::mem::replace(&mut r.objects, survivors);
+        r.objects.extend(allocated);
+        r.threshold = MIN_THRESHOLD.max(r.objects.len() * 2);
+        r.stats.collections += 1;
+        r.stats.freed += freed;
+        r.stats.live = r.objects.len();
+        r.stats.time += start.elapsed();
+    });
+    freed
+}
+
+impl ViplObject {
+    // visits every object directly referenced by this one
+    pub fn trace(&self, visit: &mut dyn FnMut(&Rc<ViplObject>)) {
+        let mut value = |v: &Value| {
+            if let ValueKind::Reference(Some(rc)) = v.kind() {
+                visit(rc)
+            }
+        };
+        match self {
+            ViplObject::Arr(a) => a.internal.borrow().iter().for_each(&mut value),
+            ViplObject::Map(m) => m.internal.borrow().values().for_each(&mut value),
+            ViplObject::Gen(g) => {
+                let s = g.state.borrow();
+                s.locals.iter().chain(&s.stack).chain(&s.current).for_each(&mut value)
+            }
+            ViplObject::Runtime(r) => r.trace(&mut value),
+            ViplObject::Str(_)
+            | ViplObject::Builder(_)
+            | ViplObject::Range(_)
+            | ViplObject::Chan(_)
+            | ViplObject::Task(_) => {}
+        }
+    }
+
+    // breaks cycle the object is part of, references are dropped only after borrow ends
+    fn clearReferences(&self) {
+        let before = self.heapSize();
+        let garbage = match self {
+            ViplObject::Arr(a) => a.internal.take(),
+            ViplObject::Map(m) => m.internal.take().into_values().collect(),
+            ViplObject::Gen(g) => {
+                let mut s = g.state.borrow_mut();
+                let mut refs = std::mem::take(&mut s.locals);
+                refs.append(&mut s.stack);
+                refs.extend(s.current.take());
+                refs
+            }
+            // fields are set only by constructor so cycle always passes through another object
+            ViplObject::Runtime(_)
+            | ViplObject::Str(_)
+            | ViplObject::Builder(_)
+            | ViplObject::Range(_)
+            | ViplObject::Chan(_)
+            | ViplObject::Task(_) => vec![],
+        };
+        heapResize(before, self.heapSize());
+        drop(garbage)
+    }
+}
@@ -37,6 +37,12 @@ typedef struct NativeWrapper {
   struct ViplObject *(*stringNew)(struct VirtualMachine*, struct StackFrame*, const char*);
   uint8_t (*stringGetChar)(struct VirtualMachine*, struct ViplObject*, uintptr_t);
   struct ViplObject *(*strConcat)(struct VirtualMachine*, struct StackFrame*, struct ViplObject*, struct ViplObject*);
+  void (*mapGet)(struct VirtualMachine*, struct ViplObject*);
+  void (*mapSet)(struct VirtualMachine*, struct ViplObject*);
+  bool (*mapContains)(struct VirtualMachine*, struct ViplObject*);
+  void (*mapRemove)(struct VirtualMachine*, struct ViplObject*);
+  intptr_t (*mapLen)(struct VirtualMachine*, struct ViplObject*);
+  intptr_t (*strCmp)(struct VirtualMachine*, struct ViplObject*, struct ViplObject*);
 } NativeWrapper;
 
 typedef struct VirtualMachine {
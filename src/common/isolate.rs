@@ -0,0 +1,239 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::limits::{Budget, InterruptHandle};
+use crate::objects::{MapKey, Object, ViplObject};
+use crate::vmError::VmError;
+use crate::vm::{
+    DataType, LinkedOp, run, SeekableOpcodes, StackFrame, Value, ValueKind, VirtualMachine,
+};
+
+// owned deep copy of a value, nothing in it is shared with the isolate it came from
+// except channels which are the only way for isolates to talk to each other
+#[derive(Debug)]
+pub enum SendValue {
+    Num(isize),
+    Flo(f32),
+    Bol(bool),
+    Chr(char),
+    Null,
+    Str(String),
+    Arr(Vec<SendValue>, DataType),
+    Map(Vec<(MapKey, SendValue)>, DataType, DataType),
+    Range(isize, isize),
+    Chan(Channel),
+}
+
+impl SendValue {
+    pub fn copyFrom(value: &Value) -> Result<SendValue, VmError> {
+        Ok(match value.kind() {
+            ValueKind::Num(v) => SendValue::Num(v),
+            ValueKind::Flo(v) => SendValue::Flo(v),
+            ValueKind::Bol(v) => SendValue::Bol(v),
+            ValueKind::Chr(v) => SendValue::Chr(v),
+            ValueKind::Reference(None) => SendValue::Null,
+            ValueKind::Reference(Some(o)) => match &**o {
+                ViplObject::Str(s) => SendValue::Str(s.string.clone()),
+                ViplObject::Arr(a) => SendValue::Arr(
+                    a.internal.borrow().iter().map(SendValue::copyFrom).collect::<Result<_, _>>()?,
+                    a.typ.clone(),
+                ),
+                ViplObject::Map(m) => SendValue::Map(
+                    m.internal
+                        .borrow()
+                        .iter()
+                        .map(|(k, v)| Ok((k.clone(), SendValue::copyFrom(v)?)))
+                        .collect::<Result<_, VmError>>()?,
+                    m.keyTyp.clone(),
+                    m.valueTyp.clone(),
+                ),
+                ViplObject::Range(r) => SendValue::Range(r.start, r.end),
+                ViplObject::Chan(c) => SendValue::Chan(c.clone()),
+                v => {
+                    return Err(VmError::InvalidOperation(format!(
+                        "{} cannot be sent between isolates",
+                        v.asObj().getName()
+                    )))
+                }
+            },
+        })
+    }
+
+    pub fn intoValue(self) -> Value {
+        match self {
+            SendValue::Num(v) => Value::Num(v),
+            SendValue::Flo(v) => Value::Flo(v),
+            SendValue::Bol(v) => Value::Bol(v),
+            SendValue::Chr(v) => Value::Chr(v),
+            SendValue::Null => Value::null(),
+            SendValue::Str(s) => Value::makeString(s),
+            SendValue::Arr(a, typ) => {
+                Value::makeArray(a.into_iter().map(SendValue::intoValue).collect(), typ)
+            }
+            SendValue::Map(m, keyTyp, valueTyp) => Value::makeMap(
+                m.into_iter().map(|(k, v)| (k, v.intoValue())).collect(),
+                keyTyp,
+                valueTyp,
+            ),
+            SendValue::Range(start, end) => Value::makeRange(start, end),
+            SendValue::Chan(c) => Value::makeChannel(c),
+        }
+    }
+}
+
+// unbounded fifo shared by every isolate that holds a handle to it
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub queue: Arc<(Mutex<VecDeque<SendValue>>, Condvar)>,
+    pub typ: DataType,
+}
+
+impl Channel {
+    pub fn new(typ: DataType) -> Self {
+        Self {
+            queue: Arc::new((Mutex::new(VecDeque::new()), Condvar::new())),
+            typ,
+        }
+    }
+
+    pub fn send(&self, value: &Value) -> Result<(), VmError> {
+        let v = SendValue::copyFrom(value)?;
+        let (lock, cond) = &*self.queue;
+        lock.lock().unwrap().push_back(v);
+        cond.notify_one();
+        Ok(())
+    }
+
+    // blocks until some isolate sends a value, gives up on deadline or interrupt of receiving vm
+    pub fn recv(&self, budget: &Budget, interrupt: &InterruptHandle) -> Result<Value, VmError> {
+        let (lock, cond) = &*self.queue;
+        let v = budget.wait(interrupt, |slice| {
+            let queue = lock.lock().unwrap();
+            let (mut queue, _) = cond.wait_timeout_while(queue, slice, |it| it.is_empty()).unwrap();
+            queue.pop_front()
+        })?;
+        Ok(v.intoValue())
+    }
+}
+
+impl Object for Channel {
+    fn getName(&self) -> String {
+        String::from("Chan")
+    }
+
+    fn getFields(&self) -> &[DataType] {
+        &[]
+    }
+
+    fn setField(&mut self, _field: usize, _value: Value) {}
+
+    fn getField(&self, _field: usize) -> Option<Value> {
+        None
+    }
+}
+
+type TaskResult = Result<Option<SendValue>, VmError>;
+
+#[derive(Debug)]
+pub struct Task {
+    // taken by first join
+    pub result: RefCell<Option<Receiver<TaskResult>>>,
+}
+
+impl Task {
+    // blocks until isolate finishes, gives up on deadline or interrupt of waiting vm
+    pub fn join(&self, budget: &Budget, interrupt: &InterruptHandle) -> Result<Option<Value>, VmError> {
+        let result = self
+            .result
+            .take()
+            .ok_or_else(|| VmError::InvalidOperation(String::from("task was already joined")))?;
+        let res = budget.wait(interrupt, |slice| match result.recv_timeout(slice) {
+            Ok(v) => Some(v),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => {
+                Some(Err(VmError::InvalidOperation(String::from("spawned task panicked"))))
+            }
+        })?;
+        Ok(res?.map(SendValue::intoValue))
+    }
+}
+
+impl Object for Task {
+    fn getName(&self) -> String {
+        String::from("Task")
+    }
+
+    fn getFields(&self) -> &[DataType] {
+        &[]
+    }
+
+    fn setField(&mut self, _field: usize, _value: Value) {}
+
+    fn getField(&self, _field: usize) -> Option<Value> {
+        None
+    }
+}
+
+// runs function in fresh vm on its own thread, only the bytecode, function table and deep copied arguments are shared,
+// the isolate is bound by what is left of limits of the spawning run and stops on its interrupt
+pub fn spawn(program: &[LinkedOp], parent: &VirtualMachine, index: usize, args: Vec<SendValue>) -> Task {
+    let mut ops = program.to_vec();
+    let functions = parent.functions.clone();
+    let functionIds = parent.functionIds.clone();
+    let constantIds = parent.constantIds.clone();
+    let types = parent.types.clone();
+    let limits = parent.budget.remaining(&parent.limits);
+    let interrupt = parent.interrupt.isolate();
+
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        // program is already linked against this table
+        let mut vm = VirtualMachine::new();
+        let returns = functions[index].returnType.is_some();
+        vm.functions = functions;
+        vm.functionIds = functionIds;
+        // strings cannot cross threads so literals are allocated again at the same indexes
+        vm.constants = vec![Value::null(); constantIds.len()];
+        for (s, i) in &constantIds {
+            vm.constants[*i] = Value::makeString(s.to_string());
+        }
+        vm.constantIds = constantIds;
+        vm.types = types;
+        vm.limits = limits;
+        vm.interrupt = interrupt;
+
+        let start = ops.len();
+        ops.push(LinkedOp::Call { index });
+
+        for arg in args {
+            vm.stack.push(arg.intoValue());
+        }
+
+        let res = runIsolate(&mut vm, &mut ops, start, returns);
+        // nobody may be waiting for the result anymore
+        let _ = sender.send(res);
+    });
+
+    Task {
+        result: RefCell::new(Some(receiver)),
+    }
+}
+
+fn runIsolate(vm: &mut VirtualMachine, ops: &mut [LinkedOp], start: usize, returns: bool) -> TaskResult {
+    run(
+        &mut SeekableOpcodes {
+            index: start as isize,
+            opCodes: ops,
+        },
+        vm,
+        &mut StackFrame::new(&mut []),
+    )?;
+
+    if returns {
+        Ok(Some(SendValue::copyFrom(&vm.pop()?)?))
+    } else {
+        Ok(None)
+    }
+}
@@ -1,7 +1,7 @@
 #![allow(non_snake_case)]
 
 use std::error::Error;
-use std::fmt::Formatter;
+use std::fmt::{Display, Formatter};
 use std::ops::Index;
 
 #[derive(Debug)]
@@ -22,6 +22,7 @@ pub fn tokenize(
     mut source: SourceProvider,
 ) -> Result<Vec<Token>, Box<dyn Error>> {
     let mut buf = vec![];
+    let mut tracker = LocationTracker::default();
 
     'main: while !source.isDone() {
         for unit in lexingUnits.iter_mut() {
@@ -31,9 +32,13 @@ pub fn tokenize(
                 continue;
             }
             if unit.canParse(source.peekStr(reqSize).unwrap()) {
+                let location = tracker.advance(source.data, source.index);
                 match unit.parse(&mut source) {
                     None => {}
-                    Some(v) => buf.push(v),
+                    Some(mut v) => {
+                        v.location = location;
+                        buf.push(v)
+                    }
                 }
 
                 continue 'main;
@@ -58,6 +63,7 @@ pub fn tokenizeSource(src: &str) -> Result<Vec<Token>, Box<dyn Error>> {
     };
 
     let mut buf = vec![];
+    let mut tracker = LocationTracker::default();
 
     'main: while !source.isDone() {
         for unit in lexingUnits.iter_mut() {
@@ -67,9 +73,13 @@ pub fn tokenizeSource(src: &str) -> Result<Vec<Token>, Box<dyn Error>> {
                 continue;
             }
             if unit.canParse(source.peekStr(reqSize).unwrap()) {
+                let location = tracker.advance(source.data, source.index);
                 match unit.parse(&mut source) {
                     None => {}
-                    Some(v) => buf.push(v),
+                    Some(mut v) => {
+                        v.location = location;
+                        buf.push(v)
+                    }
                 }
 
                 continue 'main;
@@ -145,6 +155,7 @@ pub enum TokenType {
     While,
     Loop,
     For,
+    In,
     True,
     False,
     If,
@@ -152,6 +163,7 @@ pub enum TokenType {
     Continue,
     Break,
     Return,
+    Yield,
     New,
     Struct,
     Native,
@@ -167,6 +179,7 @@ pub enum TokenType {
     Equals,
     Colon,
     Comma,
+    DotDot,
 
     Eq,
     Gt,
@@ -182,10 +195,58 @@ pub enum TokenType {
     Or,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// source index only moves forward so lines can be counted incrementally
+#[derive(Default)]
+struct LocationTracker {
+    index: usize,
+    line: usize,
+    lineStart: usize,
+}
+
+impl LocationTracker {
+    fn advance(&mut self, data: &str, index: usize) -> Location {
+        for (i, c) in data.as_bytes()[self.index..index].iter().enumerate() {
+            if *c == b'\n' {
+                self.line += 1;
+                self.lineStart = self.index + i + 1;
+            }
+        }
+        self.index = index;
+        Location {
+            line: self.line + 1,
+            column: index - self.lineStart + 1,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Token {
     pub typ: TokenType,
     pub str: String,
+    pub location: Location,
+}
+
+impl Token {
+    // location is filled in by tokenizer
+    pub fn new(typ: TokenType, str: String) -> Self {
+        Self {
+            typ,
+            str,
+            location: Location::default(),
+        }
+    }
 }
 
 pub trait LexingUnit: Send + Sync {
@@ -236,7 +297,7 @@ impl LexingUnit for RangeLexingUnit {
         }
         lexer.consumeMany(self.end.len());
 
-        self.tokenType.map(|v| Token { typ: v, str: buf })
+        self.tokenType.map(|v| Token::new(v, buf))
     }
 }
 
@@ -291,6 +352,10 @@ impl LexingUnit for NumericLexingUnit {
             .map_or(false, |c| c == '.' || c == '_' || c.is_numeric())
         {
             let c = lexer.peekChar().unwrap();
+            // 0..10 is range not float
+            if lexer.peekStr(2) == Some("..") {
+                break;
+            }
             if c == '.' && encounteredDot {
                 // fixme return result from lexer
                 // panic!("number cant have more than 1 dots")
@@ -324,7 +389,7 @@ impl LexingUnit for NumericLexingUnit {
             },
         }
 
-        Some(Token { typ, str: buf })
+        Some(Token::new(typ, buf))
     }
 }
 
@@ -356,10 +421,13 @@ impl LexingUnit for IdentifierLexingUnit {
             lexer.consumeOne();
         }
 
-        Some(Token {
-            typ: TokenType::Identifier,
-            str: buf,
-        })
+        // "in" is prefix of too many identifiers (int, index) to be lexed by KeywordLexingUnit
+        let typ = match buf.as_str() {
+            "in" => TokenType::In,
+            _ => TokenType::Identifier,
+        };
+
+        Some(Token::new(typ, buf))
     }
 }
 
@@ -405,10 +473,7 @@ impl LexingUnit for KeywordLexingUnit {
         let str = source.peekStr(self.keyword.len())?.to_string();
         source.consumeMany(self.keyword.len());
 
-        Some(Token {
-            typ: self.tokenType,
-            str,
-        })
+        Some(Token::new(self.tokenType, str))
     }
 }
 
@@ -424,6 +489,7 @@ pub fn lexingUnits() -> Vec<Box<dyn LexingUnit>> {
         KeywordLexingUnit::new("for", TokenType::For),
         KeywordLexingUnit::new("loop", TokenType::Loop),
         KeywordLexingUnit::new("return", TokenType::Return),
+        KeywordLexingUnit::new("yield", TokenType::Yield),
         KeywordLexingUnit::new("break", TokenType::Break),
         KeywordLexingUnit::new("continue", TokenType::Continue),
         KeywordLexingUnit::new("false", TokenType::False),
@@ -449,6 +515,7 @@ pub fn lexingUnits() -> Vec<Box<dyn LexingUnit>> {
         KeywordLexingUnit::new("=", TokenType::Equals),
         KeywordLexingUnit::new(":", TokenType::Colon),
         KeywordLexingUnit::new(",", TokenType::Comma),
+        KeywordLexingUnit::new("..", TokenType::DotDot),
         // ops
         KeywordLexingUnit::new("+", TokenType::Plus),
         KeywordLexingUnit::new("-", TokenType::Minus),
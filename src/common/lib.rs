@@ -1,4 +1,3 @@
-#![feature(get_mut_unchecked)]
 #![feature(downcast_unchecked)]
 #![feature(type_name_of_val)]
 #![feature(trait_upcasting)]
@@ -15,18 +14,26 @@
 // static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
 pub mod ast;
+pub mod bytecodeBuilder;
 pub mod bytecodeChecker;
 pub mod cGen;
 pub mod codegen;
 pub mod ffi;
 pub mod fs;
+pub mod gc;
 pub mod gccWrapper;
+pub mod isolate;
 pub mod lexer;
+pub mod limits;
+pub mod lineTable;
+pub mod lints;
 pub mod objects;
 pub mod optimizer;
 pub mod parser;
+pub mod serialization;
 pub mod std;
+pub mod typeChecker;
 pub mod vm;
-pub mod strCaching;
+pub mod vmError;
 mod tests;
 mod rice;
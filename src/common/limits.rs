@@ -0,0 +1,181 @@
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::gc;
+use crate::objects::{heapUsage, HeapUsage};
+use crate::vmError::VmError;
+
+// instructions executed between checks of clock, interrupt, stack and heap, collection runs at the same points
+const CHECK_INTERVAL: u64 = 1024;
+
+// blocking operations wake up this often to look at deadline and interrupt
+const WAIT_SLICE: Duration = Duration::from_millis(10);
+
+// bounds of single run of script, none is unlimited
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    pub instructions: Option<u64>,
+    pub timeout: Option<Duration>,
+    // frames on call stack including top level code
+    pub callDepth: Option<usize>,
+    // objects and bytes allocated by the run that are still alive
+    pub heapObjects: Option<usize>,
+    pub heapBytes: Option<usize>,
+    pub stackSize: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Instructions,
+    Timeout,
+    CallDepth,
+    HeapObjects,
+    HeapBytes,
+    StackSize,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Limit::Instructions => "instruction",
+            Limit::Timeout => "time",
+            Limit::CallDepth => "call depth",
+            Limit::HeapObjects => "heap object",
+            Limit::HeapBytes => "heap size",
+            Limit::StackSize => "stack size",
+        };
+        write!(f, "{name}")
+    }
+}
+
+// stops script of vm from other thread, every isolate it spawned sees the interrupt as well
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle {
+    // interrupts requested so far, shared with spawned isolates
+    requested: Arc<AtomicU64>,
+    // requests already seen by script of this vm
+    seen: Arc<AtomicU64>,
+}
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.requested.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // handle for isolate spawned by this vm, it counts seen requests on its own
+    pub(crate) fn isolate(&self) -> InterruptHandle {
+        InterruptHandle {
+            requested: self.requested.clone(),
+            seen: Arc::new(AtomicU64::new(self.seen.load(Ordering::Relaxed))),
+        }
+    }
+
+    fn take(&self) -> bool {
+        let requested = self.requested.load(Ordering::Relaxed);
+        self.seen.swap(requested, Ordering::Relaxed) != requested
+    }
+}
+
+// progress of current run against limits
+#[derive(Debug, Default)]
+pub struct Budget {
+    // instructions left until next check
+    pub fuel: u64,
+    granted: u64,
+    executed: u64,
+    deadline: Option<Instant>,
+    heap: HeapUsage,
+}
+
+impl Budget {
+    pub fn start(&mut self, limits: &Limits) {
+        *self = Budget {
+            deadline: limits.timeout.map(|it| Instant::now() + it),
+            heap: heapUsage(),
+            ..Default::default()
+        }
+    }
+
+    pub fn executed(&self) -> u64 {
+        self.executed + self.granted - self.fuel
+    }
+
+    // limits of isolate spawned by current run, it gets what is left of instructions and time
+    pub fn remaining(&self, limits: &Limits) -> Limits {
+        Limits {
+            instructions: limits.instructions.map(|max| max.saturating_sub(self.executed())),
+            timeout: self.deadline.map(|it| it.saturating_duration_since(Instant::now())),
+            ..limits.clone()
+        }
+    }
+
+    // allocation of given size would not fit into heap limit, checked before large allocations
+    pub fn reserve(&self, limits: &Limits, bytes: usize) -> Result<(), VmError> {
+        let used = heapUsage().bytes.saturating_sub(self.heap.bytes);
+        if limits.heapBytes.is_some_and(|max| used.saturating_add(bytes) > max) {
+            return Err(VmError::LimitExceeded(Limit::HeapBytes));
+        }
+        Ok(())
+    }
+
+    // blocks until poll gives value, poll should not wait longer than the slice it gets
+    pub fn wait<T>(&self, interrupt: &InterruptHandle, mut poll: impl FnMut(Duration) -> Option<T>) -> Result<T, VmError> {
+        loop {
+            if interrupt.take() {
+                return Err(VmError::Interrupted);
+            }
+            let mut slice = WAIT_SLICE;
+            if let Some(deadline) = self.deadline {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(VmError::LimitExceeded(Limit::Timeout));
+                }
+                slice = slice.min(left);
+            }
+            if let Some(v) = poll(slice) {
+                return Ok(v);
+            }
+        }
+    }
+
+    // called when fuel runs out
+    pub fn refuel(&mut self, limits: &Limits, interrupt: &InterruptHandle, stackSize: usize) -> Result<(), VmError> {
+        self.executed += self.granted;
+        self.granted = 0;
+
+        if interrupt.take() {
+            return Err(VmError::Interrupted);
+        }
+        let mut grant = CHECK_INTERVAL;
+        if let Some(max) = limits.instructions {
+            if self.executed >= max {
+                return Err(VmError::LimitExceeded(Limit::Instructions));
+            }
+            grant = grant.min(max - self.executed);
+        }
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(VmError::LimitExceeded(Limit::Timeout));
+            }
+        }
+        if limits.stackSize.is_some_and(|max| stackSize > max) {
+            return Err(VmError::LimitExceeded(Limit::StackSize));
+        }
+        if gc::shouldCollect() {
+            gc::collect();
+        }
+        let heap = heapUsage();
+        if limits.heapObjects.is_some_and(|max| heap.objects.saturating_sub(self.heap.objects) > max) {
+            return Err(VmError::LimitExceeded(Limit::HeapObjects));
+        }
+        if limits.heapBytes.is_some_and(|max| heap.bytes.saturating_sub(self.heap.bytes) > max) {
+            return Err(VmError::LimitExceeded(Limit::HeapBytes));
+        }
+
+        self.granted = grant;
+        self.fuel = grant;
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use crate::lexer::Location;
+use crate::vm::MyStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineEntry {
+    // first opcode generated from this position
+    pub index: usize,
+    pub location: Location,
+    pub function: Option<MyStr>,
+}
+
+// side table of bytecode, entries are sorted by index and cover code until next entry
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineTable {
+    pub file: Option<String>,
+    pub entries: Vec<LineEntry>,
+}
+
+impl LineTable {
+    pub fn push(&mut self, index: usize, location: Location, function: Option<MyStr>) {
+        // statement that generated no code is covered by the next one
+        if let Some(last) = self.entries.last_mut() {
+            if last.index == index {
+                *last = LineEntry { index, location, function };
+                return;
+            }
+        }
+        self.entries.push(LineEntry { index, location, function });
+    }
+
+    pub fn lookup(&self, index: usize) -> Option<&LineEntry> {
+        let i = self.entries.partition_point(|it| it.index <= index);
+        self.entries.get(i.checked_sub(1)?)
+    }
+
+    // table of code appended at offset, like repl segments
+    pub fn append(&mut self, other: LineTable, offset: usize) {
+        for e in other.entries {
+            self.push(e.index + offset, e.location, e.function);
+        }
+    }
+
+    pub fn position(&self, index: usize) -> String {
+        let entry = match self.lookup(index) {
+            None => return format!("opcode {index}"),
+            Some(v) => v,
+        };
+        match &self.file {
+            None => entry.location.to_string(),
+            Some(f) => format!("{f}:{}", entry.location),
+        }
+    }
+}
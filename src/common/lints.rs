@@ -0,0 +1,470 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use crate::ast::{Expression, FunctionDef, Node, Op, Statement};
+use crate::lexer::Location;
+use crate::optimizer::evalExpr;
+use crate::parser::Operation;
+use crate::vm::{Value, ValueKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    UnusedVariable,
+    UnusedParameter,
+    UnusedFunction,
+    ShadowedName,
+    ConstantCondition,
+    AlwaysFalse,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 6] = [
+        Lint::UnusedVariable,
+        Lint::UnusedParameter,
+        Lint::UnusedFunction,
+        Lint::ShadowedName,
+        Lint::ConstantCondition,
+        Lint::AlwaysFalse,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedParameter => "unused-parameter",
+            Lint::UnusedFunction => "unused-function",
+            Lint::ShadowedName => "shadowed-name",
+            Lint::ConstantCondition => "constant-condition",
+            Lint::AlwaysFalse => "always-false",
+        }
+    }
+
+    pub fn fromName(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|it| it.name() == name)
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownLint {
+    pub name: String,
+}
+
+impl Display for UnknownLint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown lint {}", self.name)
+    }
+}
+
+impl Error for UnknownLint {}
+
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    pub disabled: HashSet<Lint>,
+}
+
+impl LintConfig {
+    // one directive per line, "allow <lint>" or "warn <lint>", # starts comment
+    pub fn parse(src: &str) -> Result<LintConfig, Box<dyn Error>> {
+        let mut config = LintConfig::default();
+
+        for line in src.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (action, name) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| format!("invalid lint directive {line}"))?;
+            let lint = Lint::fromName(name.trim()).ok_or_else(|| UnknownLint {
+                name: name.trim().to_string(),
+            })?;
+            match action {
+                "allow" => config.disabled.insert(lint),
+                "warn" => config.disabled.remove(&lint),
+                _ => return Err(format!("invalid lint directive {line}").into()),
+            };
+        }
+
+        Ok(config)
+    }
+
+    // project without config file gets every lint
+    pub fn load(path: &Path) -> Result<LintConfig, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(LintConfig::default());
+        }
+        LintConfig::parse(&std::fs::read_to_string(path)?)
+    }
+
+    // project lint settings live next to the source file
+    pub fn forSource(path: &Path) -> Result<LintConfig, Box<dyn Error>> {
+        LintConfig::load(&path.with_file_name("vipl.lints"))
+    }
+}
+
+#[derive(Debug)]
+pub struct Warning {
+    pub location: Location,
+    pub function: Option<String>,
+    pub lint: Lint,
+    pub msg: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: warning: {} [{}]", self.location, self.msg, self.lint.name())?;
+        if let Some(fun) = &self.function {
+            write!(f, " (in function {fun})")?;
+        }
+        Ok(())
+    }
+}
+
+// start of line comment, "//" inside string or char literal does not count
+fn commentStart(line: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '/') if chars.peek().is_some_and(|(_, n)| *n == '/') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+// "// allow(lint, ...)" silences lints on its own line, or on the next one when comment stands alone
+fn suppressions(source: &str) -> HashMap<usize, Vec<Lint>> {
+    let mut res: HashMap<usize, Vec<Lint>> = HashMap::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let comment = match commentStart(line) {
+            None => continue,
+            Some(c) => &line[c + 2..],
+        };
+        let args = match comment
+            .trim()
+            .strip_prefix("allow(")
+            .and_then(|it| it.split(')').next())
+        {
+            None => continue,
+            Some(a) => a,
+        };
+        let target = if line.trim_start().starts_with("//") { i + 2 } else { i + 1 };
+        res.entry(target)
+            .or_default()
+            .extend(args.split(',').filter_map(|it| Lint::fromName(it.trim())));
+    }
+
+    res
+}
+
+// loop exits on break that targets it or on any return
+fn hasExit(body: &[Statement], nested: bool) -> bool {
+    body.iter().any(|s| match s {
+        Statement::Break => !nested,
+        Statement::Return(_) => true,
+        Statement::If(i) => {
+            hasExit(&i.body, nested) || i.elseBody.as_ref().is_some_and(|b| hasExit(b, nested))
+        }
+        Statement::While(w) => hasExit(&w.body, true),
+        Statement::Loop(b) => hasExit(b, true),
+        Statement::ForLoop(f) => hasExit(&f.body, true),
+        _ => false,
+    })
+}
+
+fn isPure(e: &Expression) -> bool {
+    match e {
+        Expression::FunctionCall(_) => false,
+        Expression::ArithmeticOp { left, right, .. } => isPure(left) && isPure(right),
+        Expression::ArrayIndexing(a) => isPure(&a.expr) && isPure(&a.index),
+        Expression::NotExpression(i) => isPure(i),
+        Expression::ArrayLiteral(_)
+        | Expression::MapLiteral(_)
+        | Expression::Range { .. }
+        | Expression::SizedArray { .. } => false,
+        _ => true,
+    }
+}
+
+#[derive(Default)]
+struct Body {
+    // first assignment of every variable
+    declared: Vec<(String, Location)>,
+    reads: HashSet<String>,
+}
+
+impl Body {
+    fn isDeclared(&self, name: &str) -> bool {
+        self.declared.iter().any(|it| it.0 == name)
+    }
+}
+
+struct Linter {
+    warnings: Vec<Warning>,
+    location: Location,
+    function: Option<String>,
+    functions: HashSet<String>,
+    // (caller, callee)
+    calls: Vec<(Option<String>, String)>,
+}
+
+impl Linter {
+    fn warn(&mut self, lint: Lint, msg: String) {
+        self.warnings.push(Warning {
+            location: self.location,
+            function: self.function.clone(),
+            lint,
+            msg,
+        })
+    }
+
+    fn walkExpr(&mut self, e: &Expression, body: &mut Body) {
+        match e {
+            Expression::Variable(v) => {
+                body.reads.insert(v.clone());
+            }
+            Expression::ArithmeticOp { left, right, op } => {
+                if matches!(op, Op::Gt | Op::Less | Op::Eq) {
+                    let constant = matches!(evalExpr(e).as_ref().map(Value::kind), Some(ValueKind::Bol(false)));
+                    let sameSides = *op != Op::Eq && left == right && isPure(left);
+                    if constant || sameSides {
+                        self.warn(Lint::AlwaysFalse, "comparison is always false".to_string());
+                    }
+                }
+                self.walkExpr(left, body);
+                self.walkExpr(right, body)
+            }
+            Expression::FunctionCall(f) => {
+                let args = if f.isSpawn() {
+                    if let Expression::Variable(target) = &f.arguments[0] {
+                        self.calls.push((self.function.clone(), target.clone()));
+                    }
+                    &f.arguments[1..]
+                } else {
+                    self.calls.push((self.function.clone(), f.name.to_string()));
+                    &f.arguments[..]
+                };
+                for arg in args {
+                    self.walkExpr(arg, body)
+                }
+            }
+            Expression::ArrayLiteral(items) => {
+                for item in items {
+                    self.walkExpr(item, body)
+                }
+            }
+            Expression::ArrayIndexing(a) => {
+                self.walkExpr(&a.expr, body);
+                self.walkExpr(&a.index, body)
+            }
+            Expression::NotExpression(i) => self.walkExpr(i, body),
+            Expression::MapLiteral(entries) => {
+                for (k, v) in entries {
+                    self.walkExpr(k, body);
+                    self.walkExpr(v, body)
+                }
+            }
+            Expression::Range { start, end } => {
+                self.walkExpr(start, body);
+                self.walkExpr(end, body)
+            }
+            Expression::SizedArray { size, fill, .. } => {
+                self.walkExpr(size, body);
+                self.walkExpr(fill, body)
+            }
+            _ => {}
+        }
+    }
+
+    // constant conditions contain no variables or calls so there is nothing else to walk
+    fn walkCondition(&mut self, e: &Expression, body: &mut Body) -> Option<bool> {
+        match evalExpr(e).as_ref().map(Value::kind) {
+            Some(ValueKind::Bol(b)) => Some(b),
+            _ => {
+                self.walkExpr(e, body);
+                None
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &str, body: &mut Body) {
+        if body.isDeclared(name) {
+            return;
+        }
+        if self.functions.contains(name) {
+            self.warn(Lint::ShadowedName, format!("variable {name} shadows function {name}"));
+        }
+        body.declared.push((name.to_string(), self.location));
+    }
+
+    fn walkBody(&mut self, statements: &[Statement], body: &mut Body) {
+        for s in statements {
+            self.walkStatement(s, body)
+        }
+    }
+
+    fn walkStatement(&mut self, s: &Statement, body: &mut Body) {
+        match s {
+            Statement::Location(l) => self.location = *l,
+            Statement::FunctionExpr(f) => self.walkExpr(&Expression::FunctionCall(f.clone()), body),
+            Statement::Variable(v) => {
+                if let Some(init) = &v.init {
+                    self.walkExpr(init, body);
+                }
+                self.declare(&v.name, body);
+            }
+            // updating variable is not use of it
+            Statement::VariableMod(m) => self.walkExpr(&m.expr, body),
+            Statement::ArrayAssign { left, right } => {
+                self.walkExpr(&left.expr, body);
+                self.walkExpr(&left.index, body);
+                self.walkExpr(right, body)
+            }
+            Statement::While(w) => {
+                match self.walkCondition(&w.exp, body) {
+                    Some(false) => self.warn(
+                        Lint::ConstantCondition,
+                        "loop condition is always false".to_string(),
+                    ),
+                    Some(true) if !hasExit(&w.body, false) => {
+                        self.warn(Lint::ConstantCondition, "loop never exits".to_string())
+                    }
+                    _ => {}
+                }
+                self.walkBody(&w.body, body)
+            }
+            Statement::If(i) => {
+                if let Some(b) = self.walkCondition(&i.condition, body) {
+                    self.warn(Lint::ConstantCondition, format!("condition is always {b}"));
+                }
+                self.walkBody(&i.body, body);
+                if let Some(e) = &i.elseBody {
+                    self.walkBody(e, body)
+                }
+            }
+            Statement::Return(r) => self.walkExpr(&r.exp, body),
+            Statement::Loop(b) => {
+                if !hasExit(b, false) {
+                    self.warn(Lint::ConstantCondition, "loop never exits".to_string())
+                }
+                self.walkBody(b, body)
+            }
+            Statement::ForLoop(f) => {
+                self.walkExpr(&f.iterable, body);
+                if body.isDeclared(&f.varName) {
+                    self.warn(
+                        Lint::ShadowedName,
+                        format!("loop variable {} shadows variable {}", f.varName, f.varName),
+                    );
+                    // reusing the name is the use
+                    body.reads.insert(f.varName.clone());
+                }
+                self.declare(&f.varName, body);
+                self.walkBody(&f.body, body)
+            }
+            Statement::Yield(e) => self.walkExpr(e, body),
+            Statement::Continue | Statement::Break => {}
+        }
+    }
+
+    fn unusedVariables(&mut self, body: &Body, skip: usize) {
+        for (name, location) in &body.declared[skip..] {
+            if !body.reads.contains(name) && !name.starts_with('_') {
+                self.location = *location;
+                self.warn(Lint::UnusedVariable, format!("variable {name} is never read"));
+            }
+        }
+    }
+
+    fn lintFunction(&mut self, f: &FunctionDef) {
+        let location = self.location;
+        self.function = Some(f.name.clone());
+
+        let mut body = Body::default();
+        for arg in &f.args[..f.argCount] {
+            let name = arg.name.to_string();
+            if self.functions.contains(&name) {
+                self.warn(Lint::ShadowedName, format!("parameter {name} shadows function {name}"));
+            }
+            body.declared.push((name, location));
+        }
+
+        self.walkBody(&f.body, &mut body);
+
+        self.location = location;
+        for (name, _) in &body.declared[..f.argCount] {
+            if !body.reads.contains(name) && !name.starts_with('_') {
+                self.warn(Lint::UnusedParameter, format!("parameter {name} is never read"));
+            }
+        }
+        self.unusedVariables(&body, f.argCount);
+
+        self.function = None;
+        self.location = location;
+    }
+}
+
+// warnings never stop compilation, caller decides how to show them
+pub fn lintProgram(operations: &[Operation], source: &str, config: &LintConfig) -> Vec<Warning> {
+    let mut linter = Linter {
+        warnings: vec![],
+        location: Location::default(),
+        function: None,
+        functions: HashSet::new(),
+        calls: vec![],
+    };
+
+    let mut definitions = vec![];
+    for op in operations {
+        if let Operation::Global(Node::FunctionDef(f)) = op {
+            linter.functions.insert(f.name.clone());
+        }
+    }
+
+    let mut main = Body::default();
+
+    for op in operations {
+        match op {
+            Operation::Statement(s) => linter.walkStatement(s, &mut main),
+            Operation::Expr(e) => linter.walkExpr(e, &mut main),
+            Operation::Global(Node::FunctionDef(f)) => {
+                definitions.push((f.name.clone(), linter.location));
+                linter.lintFunction(f)
+            }
+            Operation::Global(Node::StructDef(_)) => {}
+        }
+    }
+
+    linter.unusedVariables(&main, 0);
+
+    // recursion alone does not make function used
+    for (name, location) in definitions {
+        let used = linter
+            .calls
+            .iter()
+            .any(|(caller, callee)| *callee == name && caller.as_ref() != Some(&name));
+        if !used && !name.starts_with('_') {
+            linter.location = location;
+            linter.warn(Lint::UnusedFunction, format!("function {name} is never called"));
+        }
+    }
+
+    let suppressed = suppressions(source);
+    let mut warnings = linter
+        .warnings
+        .into_iter()
+        .filter(|it| !config.disabled.contains(&it.lint))
+        .filter(|it| {
+            !suppressed
+                .get(&it.location.line)
+                .is_some_and(|l| l.contains(&it.lint))
+        })
+        .collect::<Vec<_>>();
+    warnings.sort_by_key(|it| (it.location.line, it.location.column));
+    warnings
+}
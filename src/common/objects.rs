@@ -1,15 +1,26 @@
 use std::any::Any;
 use std::any::TypeId;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::mem::size_of;
+use std::rc::Rc;
 
-use crate::vm::{DataType, Value};
+use crate::isolate::{Channel, Task};
+use crate::vm::{DataType, Value, ValueKind};
+use crate::vmError::VmError;
 
 #[derive(Debug)]
 #[repr(C)]
 pub enum ViplObject {
     Arr(Array),
     Str(Str),
+    Builder(StringBuilder),
+    Map(Map),
+    Range(Range),
+    Gen(Generator),
+    Chan(Channel),
+    Task(Task),
     Runtime(Box<dyn Object>),
 }
 
@@ -21,36 +32,137 @@ unsafe impl Send for ViplObject {
 
 }
 
+// live objects of current thread, bytes are estimate of object payloads
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HeapUsage {
+    pub objects: usize,
+    pub bytes: usize,
+}
+
+thread_local! {
+    static HEAP: Cell<HeapUsage> = Cell::new(HeapUsage::default());
+}
+
+pub fn heapUsage() -> HeapUsage {
+    HEAP.with(|it| it.get())
+}
+
+// growth of live object, mutations that change its size must report it
+pub fn heapResize(old: usize, new: usize) {
+    HEAP.with(|it| {
+        let mut usage = it.get();
+        usage.bytes = (usage.bytes + new).saturating_sub(old);
+        it.set(usage)
+    })
+}
+
+impl Drop for ViplObject {
+    fn drop(&mut self) {
+        let bytes = self.heapSize();
+        HEAP.with(|it| {
+            let mut usage = it.get();
+            usage.objects = usage.objects.saturating_sub(1);
+            usage.bytes = usage.bytes.saturating_sub(bytes);
+            it.set(usage)
+        })
+    }
+}
+
 impl ViplObject {
+    // every object is allocated here so it is counted in heap usage
     #[inline]
-    pub fn getArr(&self) -> &Array {
+    pub fn alloc(self) -> Rc<ViplObject> {
+        let bytes = self.heapSize();
+        HEAP.with(|it| {
+            let mut usage = it.get();
+            usage.objects += 1;
+            usage.bytes += bytes;
+            it.set(usage)
+        });
+        let rc = Rc::new(self);
+        crate::gc::track(&rc);
+        rc
+    }
+
+    pub fn heapSize(&self) -> usize {
+        size_of::<ViplObject>()
+            + match self {
+                ViplObject::Arr(a) => a.len() * size_of::<Value>(),
+                ViplObject::Str(s) => s.string.len(),
+                ViplObject::Builder(b) => b.buffer.borrow().len(),
+                ViplObject::Map(m) => m.internal.borrow().len() * size_of::<(MapKey, Value)>(),
+                _ => 0,
+            }
+    }
+
+    #[inline]
+    pub fn getArr(&self) -> Result<&Array, VmError> {
         match self {
-            ViplObject::Arr(v) => v,
-            _ => panic!(),
+            ViplObject::Arr(v) => Ok(v),
+            v => Err(v.typeError("Array")),
         }
     }
 
     #[inline]
-    pub fn getMutArr(&mut self) -> &mut Array {
+    pub fn getStr(&self) -> Result<&Str, VmError> {
         match self {
-            ViplObject::Arr(v) => v,
-            v => panic!("{:?}", v),
+            ViplObject::Str(v) => Ok(v),
+            v => Err(v.typeError("String")),
         }
     }
 
     #[inline]
-    pub fn getStr(&self) -> &Str {
+    pub fn getBuilder(&self) -> Result<&StringBuilder, VmError> {
         match self {
-            ViplObject::Str(v) => v,
-            v => panic!("{:?}", v),
+            ViplObject::Builder(v) => Ok(v),
+            v => Err(v.typeError("StringBuilder")),
+        }
+    }
+
+    fn typeError(&self, expected: &str) -> VmError {
+        VmError::TypeError {
+            expected: expected.to_string(),
+            got: self.asObj().getName(),
         }
     }
 
     #[inline]
-    pub fn getMutStr(&mut self) -> &mut Str {
+    pub fn getMap(&self) -> Result<&Map, VmError> {
         match self {
-            ViplObject::Str(v) => v,
-            _ => panic!(),
+            ViplObject::Map(v) => Ok(v),
+            v => Err(v.typeError("Map")),
+        }
+    }
+
+    #[inline]
+    pub fn getRange(&self) -> Result<&Range, VmError> {
+        match self {
+            ViplObject::Range(v) => Ok(v),
+            v => Err(v.typeError("Range")),
+        }
+    }
+
+    #[inline]
+    pub fn getGen(&self) -> Result<&Generator, VmError> {
+        match self {
+            ViplObject::Gen(v) => Ok(v),
+            v => Err(v.typeError("Generator")),
+        }
+    }
+
+    #[inline]
+    pub fn getChan(&self) -> Result<&Channel, VmError> {
+        match self {
+            ViplObject::Chan(v) => Ok(v),
+            v => Err(v.typeError("Chan")),
+        }
+    }
+
+    #[inline]
+    pub fn getTask(&self) -> Result<&Task, VmError> {
+        match self {
+            ViplObject::Task(v) => Ok(v),
+            v => Err(v.typeError("Task")),
         }
     }
 
@@ -59,6 +171,12 @@ impl ViplObject {
         match self {
             ViplObject::Arr(a) => a,
             ViplObject::Str(a) => a,
+            ViplObject::Builder(a) => a,
+            ViplObject::Map(a) => a,
+            ViplObject::Range(a) => a,
+            ViplObject::Gen(a) => a,
+            ViplObject::Chan(a) => a,
+            ViplObject::Task(a) => a,
             ViplObject::Runtime(v) => &**v,
         }
     }
@@ -69,6 +187,15 @@ pub trait Object: Debug + Any {
     fn getFields(&self) -> &[DataType];
     fn setField(&mut self, field: usize, value: Value);
     fn getField(&self, field: usize) -> Option<Value>;
+
+    // values the object references, objects holding values outside of fields must override it for gc
+    fn trace(&self, visit: &mut dyn FnMut(&Value)) {
+        for i in 0..self.getFields().len() {
+            if let Some(v) = self.getField(i) {
+                visit(&v)
+            }
+        }
+    }
 }
 
 impl dyn Object {
@@ -117,12 +244,26 @@ pub struct ObjectDefinition {
     pub mapping: HashMap<String, (usize, DataType)>,
 }
 
+// strings are immutable so aliases and interned literals can share them, text is built with StringBuilder
 #[derive(Debug)]
 #[repr(C)]
 pub struct Str {
     pub string: String,
 }
 
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct StringBuilder {
+    pub buffer: RefCell<String>,
+}
+
+impl StringBuilder {
+    pub fn push(&self, c: char) {
+        self.buffer.borrow_mut().push(c);
+        heapResize(0, c.len_utf8())
+    }
+}
+
 impl Into<ViplObject> for Str {
     #[inline]
     fn into(self) -> ViplObject {
@@ -130,6 +271,13 @@ impl Into<ViplObject> for Str {
     }
 }
 
+impl Into<ViplObject> for StringBuilder {
+    #[inline]
+    fn into(self) -> ViplObject {
+        ViplObject::Builder(self)
+    }
+}
+
 impl Into<ViplObject> for Array {
     #[inline]
     fn into(self) -> ViplObject {
@@ -137,6 +285,41 @@ impl Into<ViplObject> for Array {
     }
 }
 
+impl Into<ViplObject> for Map {
+    #[inline]
+    fn into(self) -> ViplObject {
+        ViplObject::Map(self)
+    }
+}
+
+impl Into<ViplObject> for Generator {
+    #[inline]
+    fn into(self) -> ViplObject {
+        ViplObject::Gen(self)
+    }
+}
+
+impl Into<ViplObject> for Channel {
+    #[inline]
+    fn into(self) -> ViplObject {
+        ViplObject::Chan(self)
+    }
+}
+
+impl Into<ViplObject> for Task {
+    #[inline]
+    fn into(self) -> ViplObject {
+        ViplObject::Task(self)
+    }
+}
+
+impl Into<ViplObject> for Range {
+    #[inline]
+    fn into(self) -> ViplObject {
+        ViplObject::Range(self)
+    }
+}
+
 /*
 impl Drop for Str {
     fn drop(&mut self) {
@@ -162,13 +345,73 @@ impl Object for Str {
     }
 }
 
+impl Object for StringBuilder {
+    fn getName(&self) -> String {
+        String::from("StringBuilder")
+    }
+
+    fn getFields(&self) -> &[DataType] {
+        &[]
+    }
+
+    fn setField(&mut self, _field: usize, _value: Value) {}
+
+    fn getField(&self, _field: usize) -> Option<Value> {
+        None
+    }
+}
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct Array {
-    pub internal: Vec<Value>,
+    // every alias sees writes, borrows are never held across instructions
+    pub internal: RefCell<Vec<Value>>,
     pub typ: DataType,
 }
 
+impl Array {
+    pub fn new(internal: Vec<Value>, typ: DataType) -> Array {
+        Array {
+            internal: RefCell::new(internal),
+            typ,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.internal.borrow().len()
+    }
+
+    #[inline]
+    pub fn get(&self, index: isize) -> Result<Value, VmError> {
+        let a = self.internal.borrow();
+        match a.get(index as usize) {
+            Some(v) if index >= 0 => Ok(v.clone()),
+            _ => Err(VmError::IndexOutOfBounds { index, len: a.len() }),
+        }
+    }
+
+    #[inline]
+    pub fn set(&self, index: isize, value: Value) -> Result<(), VmError> {
+        let mut a = self.internal.borrow_mut();
+        let len = a.len();
+        let old = match a.get_mut(index as usize) {
+            Some(it) if index >= 0 => std::mem::replace(it, value),
+            _ => return Err(VmError::IndexOutOfBounds { index, len }),
+        };
+        // old value can free objects, it must not happen while array is borrowed
+        drop(a);
+        drop(old);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn push(&self, value: Value) {
+        self.internal.borrow_mut().push(value);
+        heapResize(0, size_of::<Value>())
+    }
+}
+
 impl Object for Array {
     fn getName(&self) -> String {
         String::from("Array")
@@ -183,4 +426,191 @@ impl Object for Array {
     fn getField(&self, _field: usize) -> Option<Value> {
         None
     }
-}
\ No newline at end of file
+}
+// only value types with well defined equality can be used as keys
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Int(isize),
+    Char(char),
+    Bool(bool),
+    Str(String),
+}
+
+impl MapKey {
+    pub fn isKeyType(typ: &DataType) -> bool {
+        match typ {
+            DataType::Int | DataType::Char | DataType::Bool => true,
+            DataType::Object(o) => o.name.as_str() == "String",
+            DataType::Float => false,
+        }
+    }
+
+    #[inline]
+    pub fn fromValue(value: &Value) -> Result<MapKey, VmError> {
+        Ok(match value.kind() {
+            ValueKind::Num(v) => MapKey::Int(v),
+            ValueKind::Chr(v) => MapKey::Char(v),
+            ValueKind::Bol(v) => MapKey::Bool(v),
+            ValueKind::Reference(_) => MapKey::Str(value.getString()?.clone()),
+            ValueKind::Flo(_) => return Err(VmError::typeError("map key", value)),
+        })
+    }
+
+    #[inline]
+    pub fn toValue(&self) -> Value {
+        match self {
+            MapKey::Int(v) => Value::Num(*v),
+            MapKey::Char(v) => Value::Chr(*v),
+            MapKey::Bool(v) => Value::Bol(*v),
+            MapKey::Str(v) => Value::makeString(v.clone()),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct Map {
+    pub internal: RefCell<HashMap<MapKey, Value>>,
+    pub keyTyp: DataType,
+    pub valueTyp: DataType,
+}
+
+impl Map {
+    #[inline]
+    pub fn get(&self, key: &Value) -> Result<Option<Value>, VmError> {
+        Ok(self.internal.borrow().get(&MapKey::fromValue(key)?).cloned())
+    }
+
+    #[inline]
+    pub fn insert(&self, key: &Value, value: Value) -> Result<(), VmError> {
+        let key = MapKey::fromValue(key)?;
+        let old = self.internal.borrow_mut().insert(key, value);
+        if old.is_none() {
+            heapResize(0, size_of::<(MapKey, Value)>());
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn contains(&self, key: &Value) -> Result<bool, VmError> {
+        Ok(self.internal.borrow().contains_key(&MapKey::fromValue(key)?))
+    }
+
+    #[inline]
+    pub fn remove(&self, key: &Value) -> Result<Option<Value>, VmError> {
+        let key = MapKey::fromValue(key)?;
+        let res = self.internal.borrow_mut().remove(&key);
+        if res.is_some() {
+            heapResize(size_of::<(MapKey, Value)>(), 0);
+        }
+        Ok(res)
+    }
+
+    pub fn len(&self) -> usize {
+        self.internal.borrow().len()
+    }
+
+    // iteration order is unspecified
+    pub fn keys(&self) -> Vec<Value> {
+        self.internal.borrow().keys().map(|it| it.toValue()).collect()
+    }
+}
+
+impl Object for Map {
+    fn getName(&self) -> String {
+        String::from("Map")
+    }
+
+    fn getFields(&self) -> &[DataType] {
+        &[]
+    }
+
+    fn setField(&mut self, _field: usize, _value: Value) {}
+
+    fn getField(&self, _field: usize) -> Option<Value> {
+        None
+    }
+}
+
+// half open range start..end, iterating or indexing yields ints
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct Range {
+    pub start: isize,
+    pub end: isize,
+}
+
+impl Range {
+    pub fn len(&self) -> usize {
+        (self.end - self.start).max(0) as usize
+    }
+
+    pub fn get(&self, index: isize) -> Result<isize, VmError> {
+        if index < 0 || index as usize >= self.len() {
+            return Err(VmError::IndexOutOfBounds { index, len: self.len() });
+        }
+        Ok(self.start + index)
+    }
+
+    // slices always copy so that they dont observe later writes to the source
+    pub fn checkSlice(&self, len: usize) -> Result<(usize, usize), VmError> {
+        if self.start < 0 || self.start > self.end {
+            return Err(VmError::IndexOutOfBounds { index: self.start, len });
+        }
+        if self.end as usize > len {
+            return Err(VmError::IndexOutOfBounds { index: self.end, len });
+        }
+        Ok((self.start as usize, self.end as usize))
+    }
+}
+
+impl Object for Range {
+    fn getName(&self) -> String {
+        String::from("Range")
+    }
+
+    fn getFields(&self) -> &[DataType] {
+        &[]
+    }
+
+    fn setField(&mut self, _field: usize, _value: Value) {}
+
+    fn getField(&self, _field: usize) -> Option<Value> {
+        None
+    }
+}
+
+// suspended generator frame, locals and operand stack segment are saved on every yield
+#[derive(Debug)]
+#[repr(C)]
+pub struct Generator {
+    // function whose code the generator runs
+    pub function: Option<usize>,
+    pub state: RefCell<GeneratorState>,
+    pub typ: DataType,
+}
+
+#[derive(Debug, Default)]
+pub struct GeneratorState {
+    pub locals: Vec<Value>,
+    pub stack: Vec<Value>,
+    pub index: isize,
+    pub current: Option<Value>,
+    pub done: bool,
+}
+
+impl Object for Generator {
+    fn getName(&self) -> String {
+        String::from("Generator")
+    }
+
+    fn getFields(&self) -> &[DataType] {
+        &[]
+    }
+
+    fn setField(&mut self, _field: usize, _value: Value) {}
+
+    fn getField(&self, _field: usize) -> Option<Value> {
+        None
+    }
+}
@@ -1,31 +1,51 @@
 use crate::ast::{Expression, Op};
-use crate::vm::Value;
+use crate::vm::{Value, ValueKind};
+use crate::vmError::VmError;
+
+fn apply(l: &mut Value, r: &Value, op: &Op) -> Result<(), VmError> {
+    match op {
+        Op::Add => l.add(r, &l.toDataType()),
+        Op::Sub => l.sub(r, &l.toDataType()),
+        Op::Mul => l.mul(r, &l.toDataType()),
+        Op::Div => l.div(r, &l.toDataType()),
+        Op::Gt => l.refGt(r, &l.toDataType()),
+        Op::Less => l.refLess(r, &l.toDataType()),
+        Op::Eq => l.refEq(r, &l.toDataType()),
+        Op::And => l.and(r),
+        Op::Or => l.or(r),
+    }
+}
 
 pub fn evalE(exp: &Expression) -> Option<Expression> {
     match exp {
         Expression::ArithmeticOp { left, right, op } => {
             let l = evalExpr(left);
+
+            // constant left side of && or || either decides the result or yields the right side
+            if let Some(ValueKind::Bol(b)) = l.as_ref().map(Value::kind) {
+                if (*op == Op::And && !b) || (*op == Op::Or && b) {
+                    return Some(Expression::BoolLiteral(b));
+                }
+                if *op == Op::And || *op == Op::Or {
+                    return Some(evalE(right).unwrap_or(*right.clone()));
+                }
+            }
+
             let r = evalExpr(right);
 
-            if let Some(mut a) = l && let Some(b) = r {
-                match op {
-                    Op::Add => a.add(&b, &a.toDataType()),
-                    Op::Sub => a.sub(&b, &a.toDataType()),
-                    Op::Mul => a.mul(&b, &a.toDataType()),
-                    Op::Div => a.div(&b, &a.toDataType()),
-                    Op::Gt => a.refGt(&b, &a.toDataType()),
-                    Op::Less => a.refLess(&b, &a.toDataType()),
-                    Op::Eq => a.refEq(&b, &a.toDataType()),
-                    Op::And => a.and(&b),
-                    Op::Or => a.or(&b)
-                };
-                Some(a.into())
-            } else {
-                Some(Expression::ArithmeticOp {
+            // failing operation like division by zero is left for runtime
+            let folded = match (l, r) {
+                (Some(mut a), Some(b)) => apply(&mut a, &b, op).ok().map(|_| a),
+                _ => None,
+            };
+
+            match folded {
+                Some(a) => Some(a.into()),
+                None => Some(Expression::ArithmeticOp {
                     left: Box::new(evalE(left).unwrap_or(*left.clone())),
                     right: Box::new(evalE(right).unwrap_or(*right.clone())),
                     op: op.clone(),
-                })
+                }),
             }
         }
         Expression::IntLiteral(n) => Some(exp.clone()),
@@ -40,6 +60,9 @@ pub fn evalE(exp: &Expression) -> Option<Expression> {
         Expression::Variable(_) => None,
         Expression::ArrayLiteral(_) => None,
         Expression::ArrayIndexing(_) => None,
+        Expression::MapLiteral(_) => None,
+        Expression::Range { .. } => None,
+        Expression::SizedArray { .. } => None,
     }
 }
 
@@ -47,23 +70,20 @@ pub fn evalExpr(exp: &Expression) -> Option<Value> {
     match exp {
         Expression::ArithmeticOp { left, right, op } => {
             let mut l = evalExpr(left)?;
+
+            if let ValueKind::Bol(b) = l.kind() {
+                if (*op == Op::And && !b) || (*op == Op::Or && b) {
+                    return Some(l);
+                }
+            }
+
             let r = evalExpr(right)?;
 
-            match op {
-                Op::Add => l.add(&r, &l.toDataType()),
-                Op::Sub => l.sub(&r, &l.toDataType()),
-                Op::Mul => l.mul(&r, &l.toDataType()),
-                Op::Div => l.div(&r, &l.toDataType()),
-                Op::Gt => l.refGt(&r, &l.toDataType()),
-                Op::Less => l.refLess(&r, &l.toDataType()),
-                Op::Eq => l.refEq(&r, &l.toDataType()),
-                Op::And => l.and(&r),
-                Op::Or => l.or(&r),
-            };
+            apply(&mut l, &r, op).ok()?;
 
             Some(l)
         }
-        Expression::IntLiteral(n) => Some(Value::Num(n.parse::<isize>().unwrap())),
+        Expression::IntLiteral(n) => Value::checkedNum(n.parse::<isize>().ok()?).ok(),
         Expression::LongLiteral(_) => None,
         Expression::FloatLiteral(f) => Some(Value::Flo(f.parse::<f32>().unwrap())),
         Expression::DoubleLiteral(_d) => None,
@@ -74,6 +94,9 @@ pub fn evalExpr(exp: &Expression) -> Option<Value> {
         Expression::CharLiteral(c) => Some(Value::Chr(*c)),
         Expression::ArrayLiteral(_) => None,
         Expression::ArrayIndexing(_) => None,
-        Expression::NotExpression(_) => Some(Value::Bol(false)),
+        Expression::NotExpression(e) => Some(Value::Bol(!evalExpr(e)?.getBool().ok()?)),
+        Expression::MapLiteral(_) => None,
+        Expression::Range { .. } => None,
+        Expression::SizedArray { .. } => None,
     }
 }
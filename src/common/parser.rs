@@ -2,18 +2,19 @@ use core::fmt;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::usize;
 
 use crate::ast;
 use crate::ast::{
-    ArrayAccess, Expression, FunctionCall, ModType, Node, Op, Statement, StructDef, VariableCreate,
-    VariableMod, While,
+    ArrayAccess, Expression, ForLoop, FunctionCall, ModType, Node, Op, Statement, StructDef,
+    VariableCreate, VariableMod, While,
 };
 use crate::ast::Expression::IntLiteral;
-use crate::lexer::{LexingUnit, Token, TokenType};
+use crate::lexer::{LexingUnit, Location, Token, TokenType};
 use crate::lexer::TokenType::{
-    CCB, CharLiteral, Colon, Comma, Continue, CRB, CSB, Equals, Identifier, Loop, Minus,
-    Native, Not, OCB, ORB, OSB, Return, StringLiteral, Struct,
+    CCB, CharLiteral, Colon, Comma, Continue, CRB, CSB, DotDot, Equals, For, Identifier, In, Loop,
+    Minus, Native, Not, OCB, ORB, OSB, Return, StringLiteral, Struct,
 };
 use crate::parser::ParsingUnitSearchType::{Ahead, Around, Back};
 use crate::vm::{DataType, Generic, MyStr, ObjectMeta, VariableMetadata};
@@ -188,6 +189,7 @@ pub fn parseTokens(toks: Vec<Token>) -> Result<Vec<Operation>, Box<dyn Error>> {
 
             if canParse && unit.canParse(&tokens) {
                 // println!("sus parse");
+                buf.push(Operation::Statement(Statement::Location(tokens.location())));
                 let res = unit.parse(&mut tokens, None, parsingUnits)?;
                 buf.push(res);
                 continue 'main;
@@ -325,6 +327,12 @@ impl TokenProvider {
     pub fn isDone(&self) -> bool {
         self.index >= self.tokens.len()
     }
+
+    pub fn location(&self) -> Location {
+        self.peekOne()
+            .or(self.tokens.last())
+            .map_or(Location::default(), |it| it.location)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -569,6 +577,62 @@ impl ParsingUnit for CallParsingUnit {
     fn setPriority(&mut self, _priority: usize) {}
 }
 
+pub struct SizedArrayParsingUnit;
+
+impl ParsingUnit for SizedArrayParsingUnit {
+    fn getType(&self) -> ParsingUnitSearchType {
+        Ahead
+    }
+
+    fn canParse(&self, tokenProvider: &TokenProvider) -> bool {
+        tokenProvider.peekOne().is_some_and(|it| it.typ == Identifier && it.str == "Array")
+            && tokenProvider.isPeekIndexType(TokenType::Gt, 1)
+    }
+
+    fn parse(
+        &self,
+        tokens: &mut TokenProvider,
+        _previous: Option<Operation>,
+        parser: &[Box<dyn ParsingUnit>],
+    ) -> Result<Operation, Box<dyn Error>> {
+        let typ = match parseDataType(tokens)? {
+            DataType::Object(o) => match o.generics.first() {
+                Some(Generic::Type(t)) => t.clone(),
+                _ => return Err("array must have element type".into()),
+            },
+            _ => unreachable!(),
+        };
+        tokens.getAssert(ORB)?;
+        let mut args = vec![];
+        for i in 0..2 {
+            if i != 0 {
+                tokens.getAssert(TokenType::Comma)?;
+            }
+            let res = parseOne(tokens, Ahead, parser, None)?;
+            let op = match getParsingUnit(tokens, Around, parser) {
+                None => res,
+                Some(p) => p.parse(tokens, Some(res), parser)?,
+            };
+            args.push(op.asExpr()?);
+        }
+        tokens.getAssert(TokenType::CRB)?;
+
+        let fill = args.pop().unwrap();
+        let size = args.pop().unwrap();
+        Ok(Operation::Expr(Expression::SizedArray {
+            typ,
+            size: Box::new(size),
+            fill: Box::new(fill),
+        }))
+    }
+
+    fn getPriority(&self) -> usize {
+        usize::MAX
+    }
+
+    fn setPriority(&mut self, _priority: usize) {}
+}
+
 pub struct ArithmeticParsingUnit {
     pub op: Op,
     pub typ: TokenType,
@@ -632,6 +696,64 @@ impl ParsingUnit for ArithmeticParsingUnit {
     }
 }
 
+pub struct RangeParsingUnit {
+    pub priority: usize,
+}
+
+impl ParsingUnit for RangeParsingUnit {
+    fn getType(&self) -> ParsingUnitSearchType {
+        Around
+    }
+
+    fn canParse(&self, tokenProvider: &TokenProvider) -> bool {
+        tokenProvider.isPeekType(DotDot)
+    }
+
+    fn parse(
+        &self,
+        tokens: &mut TokenProvider,
+        previous: Option<Operation>,
+        parser: &[Box<dyn ParsingUnit>],
+    ) -> Result<Operation, Box<dyn Error>> {
+        tokens.getAssert(DotDot)?;
+        let start = previous.ok_or("range must have start")?.asExpr()?;
+        let res = parseOne(tokens, Ahead, parser, None)?;
+        let par = getParsingUnit(tokens, Around, parser);
+
+        match par {
+            None => Ok(Operation::Expr(Expression::Range {
+                start: Box::new(start),
+                end: Box::new(res.asExpr()?),
+            })),
+            Some(p) => {
+                if self.priority < p.getPriority() {
+                    Ok(p.parse(
+                        tokens,
+                        Some(Operation::Expr(Expression::Range {
+                            start: Box::new(start),
+                            end: Box::new(res.asExpr()?),
+                        })),
+                        parser,
+                    )?)
+                } else {
+                    Ok(Operation::Expr(Expression::Range {
+                        start: Box::new(start),
+                        end: Box::new(p.parse(tokens, Some(res), parser)?.asExpr()?),
+                    }))
+                }
+            }
+        }
+    }
+
+    fn getPriority(&self) -> usize {
+        self.priority
+    }
+
+    fn setPriority(&mut self, priority: usize) {
+        self.priority = priority
+    }
+}
+
 pub struct NumericParsingUnit;
 
 impl ParsingUnit for NumericParsingUnit {
@@ -811,6 +933,35 @@ impl ParsingUnit for ReturnParsingUnit {
     fn setPriority(&mut self, _priority: usize) {}
 }
 
+pub struct YieldParsingUnit;
+
+impl ParsingUnit for YieldParsingUnit {
+    fn getType(&self) -> ParsingUnitSearchType {
+        Ahead
+    }
+
+    fn canParse(&self, tokenProvider: &TokenProvider) -> bool {
+        tokenProvider.isPeekType(TokenType::Yield)
+    }
+
+    fn parse(
+        &self,
+        tokenProvider: &mut TokenProvider,
+        _previous: Option<Operation>,
+        parser: &[Box<dyn ParsingUnit>],
+    ) -> Result<Operation, Box<dyn Error>> {
+        tokenProvider.getAssert(TokenType::Yield)?;
+        let exp = parseExpr(tokenProvider, parser)?;
+        Ok(Operation::Statement(Statement::Yield(exp)))
+    }
+
+    fn getPriority(&self) -> usize {
+        usize::MAX
+    }
+
+    fn setPriority(&mut self, _priority: usize) {}
+}
+
 pub struct WhileParsingUnit;
 
 impl ParsingUnit for WhileParsingUnit {
@@ -858,6 +1009,7 @@ fn parseBody(
     tokenProvider.getAssert(TokenType::OCB)?;
 
     while !tokenProvider.isPeekType(CCB) {
+        statements.push(Statement::Location(tokenProvider.location()));
         statements.push(parseOne(tokenProvider, Ahead, parser, None)?.asStatement()?);
     }
 
@@ -1129,6 +1281,9 @@ pub fn parseDataType(tokens: &mut TokenProvider) -> Result<DataType, Box<dyn Err
         tokens.getAssert(TokenType::Gt)?;
         while !tokens.isPeekType(TokenType::Less) {
             generics.push(Generic::Type(parseDataType(tokens)?));
+            if tokens.isPeekType(Comma) {
+                tokens.getAssert(Comma)?;
+            }
         }
         tokens.getAssert(TokenType::Less)?;
     }
@@ -1213,6 +1368,48 @@ impl ParsingUnit for ArrayIndexingParsingUnit {
     fn setPriority(&mut self, _priority: usize) {}
 }
 
+// parses `name[index]` as a whole so that it binds tighter than arithmetic
+// and can be used as an assignment target in statement position
+struct IndexParsingUnit;
+
+impl ParsingUnit for IndexParsingUnit {
+    fn getType(&self) -> ParsingUnitSearchType {
+        Ahead
+    }
+
+    fn canParse(&self, tokenProvider: &TokenProvider) -> bool {
+        tokenProvider.isPeekType(Identifier) && tokenProvider.isPeekIndexType(OSB, 1)
+    }
+
+    fn parse(
+        &self,
+        tokenProvider: &mut TokenProvider,
+        _previous: Option<Operation>,
+        parser: &[Box<dyn ParsingUnit>],
+    ) -> Result<Operation, Box<dyn Error>> {
+        let mut expr = Expression::Variable(tokenProvider.getIdentifier()?);
+
+        while tokenProvider.isPeekType(OSB) {
+            tokenProvider.getAssert(OSB)?;
+            let index = parseExpr(tokenProvider, parser)?;
+            tokenProvider.getAssert(CSB)?;
+            expr = Expression::ArrayIndexing(Box::new(ArrayAccess { expr, index }));
+        }
+
+        if !tokenProvider.isPeekType(Equals) {
+            return Ok(Operation::Expr(expr));
+        }
+
+        ArrayAssignParsingUnit.parse(tokenProvider, Some(Operation::Expr(expr)), parser)
+    }
+
+    fn getPriority(&self) -> usize {
+        usize::MAX
+    }
+
+    fn setPriority(&mut self, _priority: usize) {}
+}
+
 struct ArrayAssignParsingUnit;
 
 impl ParsingUnit for ArrayAssignParsingUnit {
@@ -1348,6 +1545,88 @@ impl ParsingUnit for LoopParsingUnit {
     }
 }
 
+static FOR_LOOP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+struct ForParsingUnit;
+
+impl ParsingUnit for ForParsingUnit {
+    fn getType(&self) -> ParsingUnitSearchType {
+        Ahead
+    }
+
+    fn canParse(&self, tokenProvider: &TokenProvider) -> bool {
+        tokenProvider.isPeekType(For)
+    }
+
+    fn parse(
+        &self,
+        tokenProvider: &mut TokenProvider,
+        _previous: Option<Operation>,
+        parser: &[Box<dyn ParsingUnit>],
+    ) -> Result<Operation, Box<dyn Error>> {
+        tokenProvider.getAssert(For)?;
+        let varName = tokenProvider.getIdentifier()?;
+        tokenProvider.getAssert(In)?;
+        let iterable = parseExpr(tokenProvider, parser)?;
+        let body = parseBody(tokenProvider, parser)?;
+
+        Ok(Operation::Statement(Statement::ForLoop(ForLoop {
+            varName,
+            iterable,
+            body,
+            id: FOR_LOOP_COUNTER.fetch_add(1, Ordering::Relaxed),
+        })))
+    }
+
+    fn getPriority(&self) -> usize {
+        usize::MAX
+    }
+
+    fn setPriority(&mut self, _priority: usize) {}
+}
+
+struct MapLiteralParsingUnit;
+
+impl ParsingUnit for MapLiteralParsingUnit {
+    fn getType(&self) -> ParsingUnitSearchType {
+        Ahead
+    }
+
+    fn canParse(&self, tokenProvider: &TokenProvider) -> bool {
+        tokenProvider.isPeekType(OCB)
+    }
+
+    fn parse(
+        &self,
+        tokenProvider: &mut TokenProvider,
+        _previous: Option<Operation>,
+        parser: &[Box<dyn ParsingUnit>],
+    ) -> Result<Operation, Box<dyn Error>> {
+        tokenProvider.getAssert(OCB)?;
+
+        let mut buf = vec![];
+
+        while !tokenProvider.isPeekType(CCB) {
+            let key = parseExpr(tokenProvider, parser)?;
+            tokenProvider.getAssert(Colon)?;
+            let value = parseExpr(tokenProvider, parser)?;
+            buf.push((key, value));
+            if tokenProvider.isPeekType(Comma) {
+                tokenProvider.getAssert(Comma)?;
+            }
+        }
+        tokenProvider.getAssert(CCB)?;
+
+        Ok(Operation::Expr(Expression::MapLiteral(buf)))
+    }
+
+    fn getPriority(&self) -> usize {
+        usize::MAX
+    }
+
+    fn setPriority(&mut self, _priority: usize) {}
+}
+
 struct NotParsingUnit;
 
 impl ParsingUnit for NotParsingUnit {
@@ -1439,14 +1718,18 @@ pub fn parsingUnits() -> Vec<Box<dyn ParsingUnit>> {
         Box::new(VarModParsingUnit),
         Box::new(WhileParsingUnit),
         Box::new(LoopParsingUnit),
+        Box::new(ForParsingUnit),
         Box::new(FunctionParsingUnit),
         Box::new(StatementVarParsingUnit),
+        Box::new(IndexParsingUnit),
         Box::new(NumericParsingUnit),
         Box::new(CharParsingUnit),
         Box::new(ArrayIndexingParsingUnit),
         Box::new(StringParsingUnit),
         Box::new(ArrayLiteralParsingUnit),
+        Box::new(MapLiteralParsingUnit),
         Box::new(ArrayAssignParsingUnit),
+        Box::new(SizedArrayParsingUnit),
         Box::new(CallParsingUnit),
         Box::new(BreakParsingUnit),
         Box::new(NotParsingUnit),
@@ -1471,36 +1754,38 @@ pub fn parsingUnits() -> Vec<Box<dyn ParsingUnit>> {
             typ: TokenType::Minus,
             priority: 3,
         }),
+        Box::new(RangeParsingUnit { priority: 4 }),
         Box::new(ArithmeticParsingUnit {
             op: Op::Eq,
             typ: TokenType::Eq,
-            priority: 4,
+            priority: 5,
         }),
         Box::new(ArithmeticParsingUnit {
             op: Op::Less,
             typ: TokenType::Less,
-            priority: 5,
+            priority: 6,
         }),
         Box::new(ArithmeticParsingUnit {
             op: Op::Gt,
             typ: TokenType::Gt,
-            priority: 6,
+            priority: 7,
         }),
         Box::new(ArithmeticParsingUnit {
             op: Op::And,
             typ: TokenType::And,
-            priority: 7,
+            priority: 8,
         }),
         Box::new(ArithmeticParsingUnit {
             op: Op::Or,
             typ: TokenType::Or,
-            priority: 8,
+            priority: 9,
         }),
         Box::new(BracketsParsingUnit),
         Box::new(VariableParsingUnit),
         Box::new(IfParsingUnit),
         Box::new(BoolParsingUnit),
         Box::new(ReturnParsingUnit),
+        Box::new(YieldParsingUnit),
         Box::new(StructParsingUnit),
     ]
 }
@@ -1,425 +1,788 @@
-use std::mem::transmute;
-
-use crate::vm::{DataType, JmpType, MyStr, OpCode, RawDataType, RawOpCode, VariableMetadata};
-use crate::vm::OpCode::*;
-
-impl DataType {
-    pub fn toBytes(&self, bytes: &mut Vec<u8>) {
-        let opId: [u8; 48] = unsafe { transmute((*self).clone()) };
-        bytes.push(opId[0]);
-        match self {
-            Int => {}
-            Float => {}
-            Bool => {}
-            Object(x) => {
-                let bs = x.name.to_string().escape_default().to_string();
-                bytes.extend(bs.len().to_ne_bytes());
-                bytes.extend(bs.as_bytes())
-            }
-            Char => {}
-        }
-    }
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::lexer::Location;
+use crate::lineTable::{LineEntry, LineTable};
+use crate::vm::{DataType, Generic, JmpType, MyStr, ObjectMeta, OpCode, RawOpCode, VariableMetadata};
+
+const MAGIC: &[u8; 4] = b"VIPL";
+const VERSION: u8 = 4;
+
+// every instruction is 8 bytes, operands that dont fit live in constant pool and arg is their index
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+pub struct Instruction {
+    pub code: RawOpCode,
+    pub flag: u8,
+    pub small: u16,
+    pub arg: u32,
 }
 
-impl VariableMetadata {
-    pub fn toBytes(&self, bytes: &mut Vec<u8>) {
-        let bs = self.name.to_string().escape_default().to_string();
-        bytes.extend(bs.len().to_ne_bytes());
-        bytes.extend(bs.as_bytes());
-        self.typ.toBytes(bytes);
+impl Instruction {
+    fn new(code: RawOpCode) -> Self {
+        Self {
+            code,
+            flag: 0,
+            small: 0,
+            arg: 0,
+        }
     }
-}
 
-impl JmpType {
-    pub fn toBytes(&self, bytes: &mut Vec<u8>) {
-        let opId: [u8; 1] = unsafe { transmute((*self).clone()) };
-        bytes.push(opId[0]);
+    fn arg(code: RawOpCode, arg: u32) -> Self {
+        Self {
+            code,
+            flag: 0,
+            small: 0,
+            arg,
+        }
     }
-}
 
-pub fn serialize(ops: &[OpCode]) -> Vec<u8> {
-    let mut buf = vec![];
-
-    for op in ops {
-        let opId: [u8; 48] = unsafe { std::mem::transmute((*op).clone()) };
-        buf.push(opId[0]);
-
-        match op {
-            FunBegin => {}
-            Or => {}
-            And => {}
-            Not => {}
-            FunEnd => {}
-            F2I => {}
-            I2F => {}
-            Pop => {}
-            Dup => {}
-            ClassBegin => {}
-            ClassEnd => {}
-            ArrayLength => {}
-            Return => {}
-
-            PushInt(i) => {
-                let data = (*i).to_ne_bytes();
-                buf.extend_from_slice(&data);
-            }
-            PushFloat(i) => {
-                let data = (*i).to_ne_bytes();
-                buf.extend_from_slice(&data);
-            }
-            PushBool(i) => {
-                buf.push(*i as u8);
-            }
-            Div(t) | Mul(t) | Sub(t) | Add(t) | Equals(t) | Greater(t) | Less(t) | ArrayNew(t)
-            | ArrayStore(t) | ArrayLoad(t) => t.toBytes(&mut buf),
-            FunReturn { typ } => match typ {
-                None => {
-                    buf.push(0);
-                }
-                Some(v) => {
-                    buf.push(1);
-                    v.toBytes(&mut buf)
-                }
-            },
-            ClassName { name } | New { name } | FunName { name } => {
-                let bs = name.to_string().escape_default().to_string();
-                buf.extend(bs.len().to_ne_bytes());
-                buf.extend(bs.as_bytes());
-            }
-            ClassField { name, typ } | GetField { name, typ } | SetField { name, typ } => {
-                let bs = name.to_string().escape_default().to_string();
-                buf.extend(bs.len().to_ne_bytes());
-                buf.extend(bs.as_bytes());
-                typ.toBytes(&mut buf);
-            }
-            Inc { typ, index } | Dec { typ, index } | SetLocal { index, typ } => {
-                typ.toBytes(&mut buf);
-                buf.extend(index.to_ne_bytes());
-            }
-            PushLocal { index } => {
-                buf.extend(index.to_ne_bytes());
-            }
-            Jmp { offset, jmpType } => {
-                buf.extend(offset.to_ne_bytes());
-                jmpType.toBytes(&mut buf);
-            }
-            Call { encoded } => {
-                let be = encoded.to_string().escape_default().to_string();
-                buf.extend(be.len().to_ne_bytes());
-                buf.extend(be.as_bytes());
-            }
-            LocalVarTable { typ, argsCount } => {
-                buf.extend(typ.len().to_ne_bytes());
-                for t in &**typ {
-                    t.toBytes(&mut buf);
-                }
-                buf.extend(argsCount.to_ne_bytes());
-            }
-            PushChar(_) => panic!(),
-            StrNew(_) => {}
-            GetChar => {}
+    fn args(code: RawOpCode, arg: u32, small: u16) -> Self {
+        Self {
+            code,
+            flag: 0,
+            small,
+            arg,
         }
     }
-    buf
 }
 
-pub fn getStr(bytes: &[u8], index: usize) -> (String, usize) {
-    let d = [
-        bytes[index],
-        bytes[index + 1],
-        bytes[index + 2],
-        bytes[index + 3],
-        bytes[index + 4],
-        bytes[index + 5],
-        bytes[index + 6],
-        bytes[index + 7],
-    ];
+// per module tables for everything that used to be embedded in opcodes
+#[derive(Debug, Clone, Default)]
+pub struct ConstantPool {
+    pub strings: Vec<MyStr>,
+    pub functions: Vec<MyStr>,
+    pub types: Vec<DataType>,
+    pub ints: Vec<isize>,
+    pub locals: Vec<Box<[VariableMetadata]>>,
+    stringIds: HashMap<MyStr, u32>,
+    functionIds: HashMap<MyStr, u32>,
+}
 
-    let mut consumed = d.len();
+fn narrow<T: TryFrom<usize>>(value: usize, what: &str) -> Result<T, Box<dyn Error>> {
+    T::try_from(value).map_err(|_| format!("too many {what} in module").into())
+}
 
-    let n: usize = unsafe { transmute(d) };
-    consumed += n;
+impl ConstantPool {
+    pub fn addString(&mut self, s: &MyStr) -> Result<u32, Box<dyn Error>> {
+        if let Some(id) = self.stringIds.get(s) {
+            return Ok(*id);
+        }
+        let id = narrow(self.strings.len(), "strings")?;
+        self.strings.push(s.clone());
+        self.stringIds.insert(s.clone(), id);
+        Ok(id)
+    }
 
-    let mut buf = vec![];
+    pub fn addFunction(&mut self, encoded: &MyStr) -> Result<u32, Box<dyn Error>> {
+        if let Some(id) = self.functionIds.get(encoded) {
+            return Ok(*id);
+        }
+        let id = narrow(self.functions.len(), "functions")?;
+        self.functions.push(encoded.clone());
+        self.functionIds.insert(encoded.clone(), id);
+        Ok(id)
+    }
 
-    for i in 0..n {
-        buf.push(bytes[index + d.len() + i])
+    // types are compared structurally, modules rarely have more than few dozen of them
+    pub fn addType(&mut self, t: &DataType) -> Result<u16, Box<dyn Error>> {
+        if let Some(id) = self.types.iter().position(|it| it == t) {
+            return Ok(id as u16);
+        }
+        let id = narrow(self.types.len(), "types")?;
+        self.types.push(t.clone());
+        Ok(id)
     }
-    (String::from_utf8_lossy(&buf).to_string(), consumed)
-}
 
-pub fn getOp(bytes: &[u8], index: usize) -> (RawOpCode, usize) {
-    let d = [
-        bytes[index],
-        bytes[index + 1],
-        bytes[index + 2],
-        bytes[index + 3]
-    ];
+    pub fn addInt(&mut self, i: isize) -> Result<u32, Box<dyn Error>> {
+        let id = narrow(self.ints.len(), "integer constants")?;
+        self.ints.push(i);
+        Ok(id)
+    }
 
-    let consumed = d.len();
+    pub fn addLocals(&mut self, locals: &[VariableMetadata]) -> Result<u32, Box<dyn Error>> {
+        let id = narrow(self.locals.len(), "functions")?;
+        self.locals.push(locals.into());
+        Ok(id)
+    }
 
-    let n: RawOpCode = unsafe { transmute(d) };
+    fn typ(&self, id: impl Into<u32>) -> DataType {
+        self.types[id.into() as usize].clone()
+    }
 
-    (n, consumed)
+    fn string(&self, id: u32) -> MyStr {
+        self.strings[id as usize].clone()
+    }
 }
 
-pub fn getSize(bytes: &[u8], index: usize) -> (usize, usize) {
-    let d = [
-        bytes[index],
-        bytes[index + 1],
-        bytes[index + 2],
-        bytes[index + 3],
-        bytes[index + 4],
-        bytes[index + 5],
-        bytes[index + 6],
-        bytes[index + 7],
-    ];
-
-    let consumed = d.len();
+// compare kinds keep their operand type in small
+fn jmpTypeByte(t: &JmpType) -> (u8, Option<&DataType>) {
+    match t {
+        JmpType::One => (0, None),
+        JmpType::Zero => (1, None),
+        JmpType::Jmp => (2, None),
+        JmpType::Lt(t) => (3, Some(t)),
+        JmpType::Le(t) => (4, Some(t)),
+        JmpType::Gt(t) => (5, Some(t)),
+        JmpType::Ge(t) => (6, Some(t)),
+        JmpType::Eq(t) => (7, Some(t)),
+        JmpType::Ne(t) => (8, Some(t)),
+        JmpType::True => (9, None),
+        JmpType::False => (10, None),
+    }
+}
 
-    let n: usize = unsafe { transmute(d) };
+fn jmpTypeFromByte(b: u8, t: impl FnOnce() -> DataType) -> Result<JmpType, Box<dyn Error>> {
+    Ok(match b {
+        0 => JmpType::One,
+        1 => JmpType::Zero,
+        2 => JmpType::Jmp,
+        3 => JmpType::Lt(t()),
+        4 => JmpType::Le(t()),
+        5 => JmpType::Gt(t()),
+        6 => JmpType::Ge(t()),
+        7 => JmpType::Eq(t()),
+        8 => JmpType::Ne(t()),
+        9 => JmpType::True,
+        10 => JmpType::False,
+        _ => return Err(format!("invalid jump type {b}").into()),
+    })
+}
 
-    (n, consumed)
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    pub code: Vec<Instruction>,
+    pub pool: ConstantPool,
+    pub lines: LineTable,
 }
 
-pub fn getFloat(bytes: &[u8], index: usize) -> (f32, usize) {
-    let d = [
-        bytes[index],
-        bytes[index + 1],
-        bytes[index + 2],
-        bytes[index + 3],
-    ];
+impl Module {
+    pub fn compile(ops: &[OpCode], lines: &LineTable) -> Result<Module, Box<dyn Error>> {
+        let mut module = Module::default();
+        for e in &lines.entries {
+            if let Some(f) = &e.function {
+                module.pool.addString(f)?;
+            }
+        }
+        module.lines = lines.clone();
+
+        for op in ops {
+            let pool = &mut module.pool;
+            let ins = match op {
+                OpCode::FunBegin => Instruction::new(RawOpCode::FunBegin),
+                OpCode::FunName { name } => Instruction::arg(RawOpCode::FunName, pool.addString(name)?),
+                OpCode::FunReturn { typ } => match typ {
+                    None => Instruction::new(RawOpCode::FunReturn),
+                    Some(t) => Instruction {
+                        flag: 1,
+                        ..Instruction::arg(RawOpCode::FunReturn, pool.addType(t)? as u32)
+                    },
+                },
+                OpCode::LocalVarTable { typ, argsCount } => Instruction::args(
+                    RawOpCode::LocalVarTable,
+                    pool.addLocals(typ)?,
+                    narrow(*argsCount, "arguments")?,
+                ),
+                OpCode::FunEnd => Instruction::new(RawOpCode::FunEnd),
+                OpCode::F2I => Instruction::new(RawOpCode::F2I),
+                OpCode::I2F => Instruction::new(RawOpCode::I2F),
+                OpCode::C2I => Instruction::new(RawOpCode::C2I),
+                OpCode::PushInt(v) => match i32::try_from(*v) {
+                    Ok(small) => Instruction::arg(RawOpCode::PushInt, small as u32),
+                    Err(_) => Instruction {
+                        flag: 1,
+                        ..Instruction::arg(RawOpCode::PushInt, pool.addInt(*v)?)
+                    },
+                },
+                OpCode::PushFloat(v) => Instruction::arg(RawOpCode::PushFloat, v.to_bits()),
+                OpCode::PushBool(v) => Instruction::arg(RawOpCode::PushBool, *v as u32),
+                OpCode::PushChar(v) => Instruction::arg(RawOpCode::PushChar, *v as u32),
+                OpCode::Pop => Instruction::new(RawOpCode::Pop),
+                OpCode::Dup => Instruction::new(RawOpCode::Dup),
+                OpCode::PushLocal { index } => {
+                    Instruction::arg(RawOpCode::PushLocal, narrow(*index, "locals")?)
+                }
+                OpCode::SetLocal { index, typ } => Instruction::args(
+                    RawOpCode::SetLocal,
+                    narrow(*index, "locals")?,
+                    pool.addType(typ)?,
+                ),
+                OpCode::Jmp { offset, jmpType } => {
+                    let (flag, t) = jmpTypeByte(jmpType);
+                    Instruction {
+                        flag,
+                        ..Instruction::args(
+                            RawOpCode::Jmp,
+                            i32::try_from(*offset).map_err(|_| "jump is too far")? as u32,
+                            t.map_or(Ok(0), |t| pool.addType(t))?,
+                        )
+                    }
+                }
+                OpCode::Call { encoded } => Instruction::arg(RawOpCode::Call, pool.addFunction(encoded)?),
+                OpCode::Return => Instruction::new(RawOpCode::Return),
+                OpCode::Add(t) => Instruction::arg(RawOpCode::Add, pool.addType(t)? as u32),
+                OpCode::Sub(t) => Instruction::arg(RawOpCode::Sub, pool.addType(t)? as u32),
+                OpCode::Div(t) => Instruction::arg(RawOpCode::Div, pool.addType(t)? as u32),
+                OpCode::Mul(t) => Instruction::arg(RawOpCode::Mul, pool.addType(t)? as u32),
+                OpCode::Equals(t) => Instruction::arg(RawOpCode::Equals, pool.addType(t)? as u32),
+                OpCode::Greater(t) => Instruction::arg(RawOpCode::Greater, pool.addType(t)? as u32),
+                OpCode::Less(t) => Instruction::arg(RawOpCode::Less, pool.addType(t)? as u32),
+                OpCode::Or => Instruction::new(RawOpCode::Or),
+                OpCode::And => Instruction::new(RawOpCode::And),
+                OpCode::Not => Instruction::new(RawOpCode::Not),
+                OpCode::ClassBegin => Instruction::new(RawOpCode::ClassBegin),
+                OpCode::ClassName { name } => Instruction::arg(RawOpCode::ClassName, pool.addString(name)?),
+                OpCode::ClassField { name, typ } => {
+                    Instruction::args(RawOpCode::ClassField, pool.addString(name)?, pool.addType(typ)?)
+                }
+                OpCode::ClassEnd => Instruction::new(RawOpCode::ClassEnd),
+                OpCode::New { name } => Instruction::arg(RawOpCode::New, pool.addString(name)?),
+                OpCode::GetField { name, typ } => {
+                    Instruction::args(RawOpCode::GetField, pool.addString(name)?, pool.addType(typ)?)
+                }
+                OpCode::SetField { name, typ } => {
+                    Instruction::args(RawOpCode::SetField, pool.addString(name)?, pool.addType(typ)?)
+                }
+                OpCode::ArrayNew(t) => Instruction::arg(RawOpCode::ArrayNew, pool.addType(t)? as u32),
+                OpCode::ArrayStore(t) => Instruction::arg(RawOpCode::ArrayStore, pool.addType(t)? as u32),
+                OpCode::ArrayLoad(t) => Instruction::arg(RawOpCode::ArrayLoad, pool.addType(t)? as u32),
+                OpCode::ArrayLength => Instruction::new(RawOpCode::ArrayLength),
+                OpCode::ArrayFill(t) => Instruction::arg(RawOpCode::ArrayFill, pool.addType(t)? as u32),
+                OpCode::ArrayPush(t) => Instruction::arg(RawOpCode::ArrayPush, pool.addType(t)? as u32),
+                OpCode::Inc { typ, index } => {
+                    Instruction::args(RawOpCode::Inc, narrow(*index, "locals")?, pool.addType(typ)?)
+                }
+                OpCode::Dec { typ, index } => {
+                    Instruction::args(RawOpCode::Dec, narrow(*index, "locals")?, pool.addType(typ)?)
+                }
+                OpCode::StrNew(s) => Instruction::arg(RawOpCode::StrNew, pool.addString(s)?),
+                OpCode::GetChar => Instruction::new(RawOpCode::GetChar),
+                OpCode::MapNew { key, value } => Instruction::args(
+                    RawOpCode::MapNew,
+                    pool.addType(key)? as u32,
+                    pool.addType(value)?,
+                ),
+                OpCode::MapStore(t) => Instruction::arg(RawOpCode::MapStore, pool.addType(t)? as u32),
+                OpCode::MapLoad(t) => Instruction::arg(RawOpCode::MapLoad, pool.addType(t)? as u32),
+                OpCode::MapContains => Instruction::new(RawOpCode::MapContains),
+                OpCode::MapRemove => Instruction::new(RawOpCode::MapRemove),
+                OpCode::MapKeys(t) => Instruction::arg(RawOpCode::MapKeys, pool.addType(t)? as u32),
+                OpCode::RangeNew => Instruction::new(RawOpCode::RangeNew),
+                OpCode::ArraySlice(t) => Instruction::arg(RawOpCode::ArraySlice, pool.addType(t)? as u32),
+                OpCode::StrSlice => Instruction::new(RawOpCode::StrSlice),
+                OpCode::GenNew(t) => Instruction::arg(RawOpCode::GenNew, pool.addType(t)? as u32),
+                OpCode::Yield => Instruction::new(RawOpCode::Yield),
+                OpCode::GenResume => Instruction::new(RawOpCode::GenResume),
+                OpCode::GenValue(t) => Instruction::arg(RawOpCode::GenValue, pool.addType(t)? as u32),
+                OpCode::Spawn { encoded, argCount } => Instruction::args(
+                    RawOpCode::Spawn,
+                    pool.addFunction(encoded)?,
+                    narrow(*argCount, "arguments")?,
+                ),
+                OpCode::Join => Instruction::new(RawOpCode::Join),
+                OpCode::ChanNew(t) => Instruction::arg(RawOpCode::ChanNew, pool.addType(t)? as u32),
+                OpCode::ChanSend => Instruction::new(RawOpCode::ChanSend),
+                OpCode::ChanRecv(t) => Instruction::arg(RawOpCode::ChanRecv, pool.addType(t)? as u32),
+                OpCode::TailCall { encoded } => {
+                    Instruction::arg(RawOpCode::TailCall, pool.addFunction(encoded)?)
+                }
+            };
+            module.code.push(ins);
+        }
 
-    let consumed = d.len();
+        Ok(module)
+    }
 
-    let n: f32 = unsafe { transmute(d) };
+    // expands module back into opcodes the vm runs
+    pub fn decode(&self) -> Result<Vec<OpCode>, Box<dyn Error>> {
+        let pool = &self.pool;
+        let mut ops = Vec::with_capacity(self.code.len());
+
+        for ins in &self.code {
+            let arg = ins.arg;
+            let op = match ins.code {
+                RawOpCode::FunBegin => OpCode::FunBegin,
+                RawOpCode::FunName => OpCode::FunName { name: pool.string(arg) },
+                RawOpCode::FunReturn => OpCode::FunReturn {
+                    typ: if ins.flag == 1 { Some(pool.typ(arg)) } else { None },
+                },
+                RawOpCode::LocalVarTable => OpCode::LocalVarTable {
+                    typ: pool.locals[arg as usize].clone(),
+                    argsCount: ins.small as usize,
+                },
+                RawOpCode::FunEnd => OpCode::FunEnd,
+                RawOpCode::F2I => OpCode::F2I,
+                RawOpCode::I2F => OpCode::I2F,
+                RawOpCode::C2I => OpCode::C2I,
+                RawOpCode::PushInt => {
+                    if ins.flag == 1 {
+                        OpCode::PushInt(pool.ints[arg as usize])
+                    } else {
+                        OpCode::PushInt(arg as i32 as isize)
+                    }
+                }
+                RawOpCode::PushFloat => OpCode::PushFloat(f32::from_bits(arg)),
+                RawOpCode::PushBool => OpCode::PushBool(arg != 0),
+                RawOpCode::PushChar => {
+                    OpCode::PushChar(char::from_u32(arg).ok_or("invalid char constant")?)
+                }
+                RawOpCode::Pop => OpCode::Pop,
+                RawOpCode::Dup => OpCode::Dup,
+                RawOpCode::PushLocal => OpCode::PushLocal { index: arg as usize },
+                RawOpCode::SetLocal => OpCode::SetLocal {
+                    index: arg as usize,
+                    typ: pool.typ(ins.small),
+                },
+                RawOpCode::Jmp => OpCode::Jmp {
+                    offset: arg as i32 as isize,
+                    jmpType: jmpTypeFromByte(ins.flag, || pool.typ(ins.small))?,
+                },
+                RawOpCode::Call => OpCode::Call {
+                    encoded: pool.functions[arg as usize].clone(),
+                },
+                RawOpCode::Return => OpCode::Return,
+                RawOpCode::Add => OpCode::Add(pool.typ(arg)),
+                RawOpCode::Sub => OpCode::Sub(pool.typ(arg)),
+                RawOpCode::Div => OpCode::Div(pool.typ(arg)),
+                RawOpCode::Mul => OpCode::Mul(pool.typ(arg)),
+                RawOpCode::Equals => OpCode::Equals(pool.typ(arg)),
+                RawOpCode::Greater => OpCode::Greater(pool.typ(arg)),
+                RawOpCode::Less => OpCode::Less(pool.typ(arg)),
+                RawOpCode::Or => OpCode::Or,
+                RawOpCode::And => OpCode::And,
+                RawOpCode::Not => OpCode::Not,
+                RawOpCode::ClassBegin => OpCode::ClassBegin,
+                RawOpCode::ClassName => OpCode::ClassName { name: pool.string(arg) },
+                RawOpCode::ClassField => OpCode::ClassField {
+                    name: pool.string(arg),
+                    typ: pool.typ(ins.small),
+                },
+                RawOpCode::ClassEnd => OpCode::ClassEnd,
+                RawOpCode::New => OpCode::New { name: pool.string(arg) },
+                RawOpCode::GetField => OpCode::GetField {
+                    name: pool.string(arg),
+                    typ: pool.typ(ins.small),
+                },
+                RawOpCode::SetField => OpCode::SetField {
+                    name: pool.string(arg),
+                    typ: pool.typ(ins.small),
+                },
+                RawOpCode::ArrayNew => OpCode::ArrayNew(pool.typ(arg)),
+                RawOpCode::ArrayStore => OpCode::ArrayStore(pool.typ(arg)),
+                RawOpCode::ArrayLoad => OpCode::ArrayLoad(pool.typ(arg)),
+                RawOpCode::ArrayLength => OpCode::ArrayLength,
+                RawOpCode::ArrayFill => OpCode::ArrayFill(pool.typ(arg)),
+                RawOpCode::ArrayPush => OpCode::ArrayPush(pool.typ(arg)),
+                RawOpCode::Inc => OpCode::Inc {
+                    typ: pool.typ(ins.small),
+                    index: arg as usize,
+                },
+                RawOpCode::Dec => OpCode::Dec {
+                    typ: pool.typ(ins.small),
+                    index: arg as usize,
+                },
+                RawOpCode::StrNew => OpCode::StrNew(pool.string(arg)),
+                RawOpCode::GetChar => OpCode::GetChar,
+                RawOpCode::MapNew => OpCode::MapNew {
+                    key: pool.typ(arg),
+                    value: pool.typ(ins.small),
+                },
+                RawOpCode::MapStore => OpCode::MapStore(pool.typ(arg)),
+                RawOpCode::MapLoad => OpCode::MapLoad(pool.typ(arg)),
+                RawOpCode::MapContains => OpCode::MapContains,
+                RawOpCode::MapRemove => OpCode::MapRemove,
+                RawOpCode::MapKeys => OpCode::MapKeys(pool.typ(arg)),
+                RawOpCode::RangeNew => OpCode::RangeNew,
+                RawOpCode::ArraySlice => OpCode::ArraySlice(pool.typ(arg)),
+                RawOpCode::StrSlice => OpCode::StrSlice,
+                RawOpCode::GenNew => OpCode::GenNew(pool.typ(arg)),
+                RawOpCode::Yield => OpCode::Yield,
+                RawOpCode::GenResume => OpCode::GenResume,
+                RawOpCode::GenValue => OpCode::GenValue(pool.typ(arg)),
+                RawOpCode::Spawn => OpCode::Spawn {
+                    encoded: pool.functions[arg as usize].clone(),
+                    argCount: ins.small as usize,
+                },
+                RawOpCode::Join => OpCode::Join,
+                RawOpCode::ChanNew => OpCode::ChanNew(pool.typ(arg)),
+                RawOpCode::ChanSend => OpCode::ChanSend,
+                RawOpCode::ChanRecv => OpCode::ChanRecv(pool.typ(arg)),
+                RawOpCode::TailCall => OpCode::TailCall {
+                    encoded: pool.functions[arg as usize].clone(),
+                },
+            };
+            ops.push(op);
+        }
 
-    (n, consumed)
-}
+        Ok(ops)
+    }
 
-pub fn getType(bytes: &[u8], index: usize) -> (DataType, usize) {
-    let d = [bytes[index]];
+    pub fn toBytes(&self) -> Vec<u8> {
+        let mut w = Writer { bytes: vec![] };
+        w.bytes.extend_from_slice(MAGIC);
+        w.bytes.push(VERSION);
 
-    let mut consumed = d.len();
+        w.len(self.pool.strings.len());
+        for s in &self.pool.strings {
+            w.str(s.as_str());
+        }
+        w.len(self.pool.functions.len());
+        for s in &self.pool.functions {
+            w.str(s.as_str());
+        }
+        w.len(self.pool.types.len());
+        for t in &self.pool.types {
+            w.typ(t);
+        }
+        w.len(self.pool.ints.len());
+        for i in &self.pool.ints {
+            w.bytes.extend((*i as i64).to_le_bytes());
+        }
+        w.len(self.pool.locals.len());
+        for locals in &self.pool.locals {
+            w.len(locals.len());
+            for l in locals.iter() {
+                w.str(l.name.as_str());
+                w.typ(&l.typ);
+            }
+        }
 
-    let n: RawDataType = unsafe { transmute(d) };
+        w.len(self.code.len());
+        for ins in &self.code {
+            w.bytes.push(ins.code as u8);
+            w.bytes.push(ins.flag);
+            w.bytes.extend(ins.small.to_le_bytes());
+            w.bytes.extend(ins.arg.to_le_bytes());
+        }
 
+        match &self.lines.file {
+            None => w.bytes.push(0),
+            Some(f) => {
+                w.bytes.push(1);
+                w.str(f);
+            }
+        }
+        w.len(self.lines.entries.len());
+        for e in &self.lines.entries {
+            w.len(e.index);
+            w.len(e.location.line);
+            w.len(e.location.column);
+            // 0 is main code, otherwise string id + 1
+            w.len(e.function.as_ref().map_or(0, |it| self.pool.stringIds[it] as usize + 1));
+        }
 
-    let t = match n {
-        RawDataType::Int => DataType::Int,
-        RawDataType::Float => DataType::Float,
-        RawDataType::Bool => DataType::Bool,
-        RawDataType::Object => {
-            let n = getStr(bytes, index + consumed);
-            consumed += n.1;
-            let n2 = getSize(bytes, index + consumed);
-            consumed += n2.1;
-            todo!();
+        w.bytes
+    }
 
-            // Object { 0: Box::new(ObjectMeta { name: "".to_string(), generics: Box::new([]) }) }
+    pub fn fromBytes(bytes: &[u8]) -> Result<Module, Box<dyn Error>> {
+        let mut r = Reader { bytes, index: 0 };
+        if r.take(4)? != MAGIC {
+            return Err("not a vipl bytecode file".into());
+        }
+        let version = r.u8()?;
+        if version != VERSION {
+            return Err(format!("unsupported bytecode version {version}").into());
         }
-    };
 
-    println!("b");
+        let mut module = Module::default();
+        let pool = &mut module.pool;
 
-    (t, consumed)
-}
+        for _ in 0..r.u32()? {
+            let s = r.str()?;
+            pool.addString(&s)?;
+        }
+        for _ in 0..r.u32()? {
+            let s = r.str()?;
+            pool.addFunction(&s)?;
+        }
+        for _ in 0..r.u32()? {
+            let t = r.typ()?;
+            pool.types.push(t);
+        }
+        for _ in 0..r.u32()? {
+            let i = i64::from_le_bytes(r.take(8)?.try_into().unwrap());
+            pool.ints.push(i as isize);
+        }
+        for _ in 0..r.u32()? {
+            let mut locals = vec![];
+            for _ in 0..r.u32()? {
+                let name = r.str()?;
+                let typ = r.typ()?;
+                locals.push(VariableMetadata { name, typ });
+            }
+            pool.locals.push(locals.into_boxed_slice());
+        }
 
-pub fn getMeta(bytes: &[u8], index: usize) -> (VariableMetadata, usize) {
-    let n = getStr(bytes, index);
+        for _ in 0..r.u32()? {
+            let code = RawOpCode::try_from(r.u8()?).map_err(|code| format!("invalid opcode {code}"))?;
+            let flag = r.u8()?;
+            let small = u16::from_le_bytes(r.take(2)?.try_into().unwrap());
+            let arg = r.u32()?;
+            module.code.push(Instruction {
+                code,
+                flag,
+                small,
+                arg,
+            });
+        }
 
-    let mut consumed = n.1;
+        if r.u8()? == 1 {
+            module.lines.file = Some(r.str()?.to_string());
+        }
+        for _ in 0..r.u32()? {
+            let index = r.u32()? as usize;
+            let line = r.u32()? as usize;
+            let column = r.u32()? as usize;
+            let function = match r.u32()? {
+                0 => None,
+                id => Some(
+                    module
+                        .pool
+                        .strings
+                        .get(id as usize - 1)
+                        .ok_or_else(|| format!("string {} out of pool", id - 1))?
+                        .clone(),
+                ),
+            };
+            if index > module.code.len() {
+                return Err(format!("line entry at {index} out of code").into());
+            }
+            module.lines.entries.push(LineEntry {
+                index,
+                location: Location { line, column },
+                function,
+            });
+        }
 
-    let t = getType(bytes, index + consumed);
-    consumed += n.1;
+        module.validate()?;
 
-    (
-        VariableMetadata {
-            name: MyStr::Runtime(n.0.into_boxed_str()),
-            typ: t.0,
-        },
-        consumed,
-    )
-}
+        Ok(module)
+    }
 
-// FIXME
+    // loaded bytes are untrusted, every pool reference has to point inside the pool
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let pool = &self.pool;
+        for ins in &self.code {
+            let (table, id, len) = match ins.code {
+                RawOpCode::FunName | RawOpCode::ClassName | RawOpCode::New | RawOpCode::StrNew => {
+                    ("string", ins.arg, pool.strings.len())
+                }
+                RawOpCode::ClassField | RawOpCode::GetField | RawOpCode::SetField => {
+                    if ins.small as usize >= pool.types.len() {
+                        return Err(format!("type {} out of pool", ins.small).into());
+                    }
+                    ("string", ins.arg, pool.strings.len())
+                }
+                RawOpCode::Call | RawOpCode::Spawn | RawOpCode::TailCall => ("function", ins.arg, pool.functions.len()),
+                RawOpCode::LocalVarTable => ("locals", ins.arg, pool.locals.len()),
+                RawOpCode::PushInt if ins.flag == 1 => ("int", ins.arg, pool.ints.len()),
+                RawOpCode::FunReturn if ins.flag == 0 => continue,
+                RawOpCode::FunReturn
+                | RawOpCode::Add
+                | RawOpCode::Sub
+                | RawOpCode::Div
+                | RawOpCode::Mul
+                | RawOpCode::Equals
+                | RawOpCode::Greater
+                | RawOpCode::Less
+                | RawOpCode::ArrayNew
+                | RawOpCode::ArrayStore
+                | RawOpCode::ArrayLoad
+                | RawOpCode::ArrayFill
+                | RawOpCode::ArrayPush
+                | RawOpCode::MapStore
+                | RawOpCode::MapLoad
+                | RawOpCode::MapKeys
+                | RawOpCode::ArraySlice
+                | RawOpCode::GenNew
+                | RawOpCode::GenValue
+                | RawOpCode::ChanNew
+                | RawOpCode::ChanRecv => ("type", ins.arg, pool.types.len()),
+                RawOpCode::MapNew => {
+                    if ins.small as usize >= pool.types.len() {
+                        return Err(format!("type {} out of pool", ins.small).into());
+                    }
+                    ("type", ins.arg, pool.types.len())
+                }
+                RawOpCode::Jmp if (3..=8).contains(&ins.flag) => {
+                    ("type", ins.small as u32, pool.types.len())
+                }
+                RawOpCode::SetLocal | RawOpCode::Inc | RawOpCode::Dec => {
+                    ("type", ins.small as u32, pool.types.len())
+                }
+                _ => continue,
+            };
+            if id as usize >= len {
+                return Err(format!("{table} {id} out of pool").into());
+            }
+        }
+        self.validateFunctions()
+    }
 
-pub fn deserialize(data: Vec<u8>) -> Vec<OpCode> {
-    let mut buf = vec![];
-    let mut skip = 0;
+    // vm indexes locals without bounds checks and expects each function header in order,
+    // jumps have to stay inside code they come from
+    fn validateFunctions(&self) -> Result<(), Box<dyn Error>> {
+        let code = &self.code;
+        // function owning each instruction, header and end included, FunBegin belongs to enclosing code
+        let mut owner = vec![None; code.len()];
+        let mut i = 0;
+        while i < code.len() {
+            match code[i].code {
+                RawOpCode::FunBegin => {
+                    let header = [RawOpCode::FunName, RawOpCode::LocalVarTable, RawOpCode::FunReturn];
+                    for (k, expected) in header.iter().enumerate() {
+                        if code.get(i + 1 + k).map(|it| it.code) != Some(*expected) {
+                            return Err(format!("function at {i} is missing {expected:?}").into());
+                        }
+                    }
+                    let table = &code[i + 2];
+                    if table.small as usize > self.pool.locals[table.arg as usize].len() {
+                        return Err(format!("function at {i} has more arguments than locals").into());
+                    }
+                    let end = (i + 4..code.len())
+                        .find(|it| matches!(code[*it].code, RawOpCode::FunBegin | RawOpCode::FunEnd))
+                        .filter(|it| code[*it].code == RawOpCode::FunEnd)
+                        .ok_or_else(|| format!("function at {i} has no end"))?;
+                    for o in &mut owner[i + 1..=end] {
+                        *o = Some(i);
+                    }
+                    i = end;
+                }
+                RawOpCode::FunEnd => return Err(format!("FunEnd at {i} outside of function").into()),
+                _ => {}
+            }
+            i += 1;
+        }
 
-    for (ind, o) in data.iter().enumerate() {
-        let i = ind;
-        if skip > 0 {
-            skip -= 1;
-            continue;
+        for (i, ins) in code.iter().enumerate() {
+            match ins.code {
+                RawOpCode::PushLocal | RawOpCode::SetLocal | RawOpCode::Inc | RawOpCode::Dec => {
+                    // top level locals are given by caller, vm checks them before run
+                    if let Some(f) = owner[i] {
+                        let locals = self.pool.locals[code[f + 2].arg as usize].len();
+                        if ins.arg as usize >= locals {
+                            return Err(format!("local {} at {i} out of {locals} locals", ins.arg).into());
+                        }
+                    }
+                }
+                RawOpCode::Jmp => {
+                    let target = i as isize + 1 + ins.arg as i32 as isize;
+                    let valid = match owner[i] {
+                        Some(f) => target >= f as isize + 4 && owner.get(target as usize) == Some(&Some(f)),
+                        None => {
+                            target >= 0
+                                && (target as usize == code.len() || owner.get(target as usize) == Some(&None))
+                        }
+                    };
+                    if !valid {
+                        return Err(format!("jump at {i} to {target} leaves its code").into());
+                    }
+                }
+                _ => {}
+            }
         }
+        Ok(())
+    }
+}
 
+struct Writer {
+    bytes: Vec<u8>,
+}
 
-        let op = getOp(&data, i);
-        i += op.1;
-        println!("{op:?}");
+impl Writer {
+    fn len(&mut self, len: usize) {
+        self.bytes.extend((len as u32).to_le_bytes());
+    }
 
-        match op.0 {
-            RawOpCode::FunBegin => buf.push(FunBegin),
-            RawOpCode::FunName => {
-                let s = getStr(&data, i);
-                skip += s.1;
-                buf.push(FunName { name: MyStr::Runtime(s.0.into_boxed_str()) })
-            }
-            RawOpCode::FunReturn => buf.push(FunReturn { typ: None }),
-            RawOpCode::LocalVarTable => {
-                let mut offset = i;
-                let s = getSize(&data, offset);
-                skip += s.1;
-                offset += s.1;
-                let mut bu = Vec::with_capacity(s.0);
-                for _ in 0..s.0 {
-                    let v = getMeta(&data, offset);
-                    skip += v.1;
-                    offset += v.1;
-                    bu.push(v.0)
+    fn str(&mut self, s: &str) {
+        self.len(s.len());
+        self.bytes.extend(s.as_bytes());
+    }
+
+    fn typ(&mut self, t: &DataType) {
+        match t {
+            DataType::Int => self.bytes.push(0),
+            DataType::Float => self.bytes.push(1),
+            DataType::Bool => self.bytes.push(2),
+            DataType::Char => self.bytes.push(3),
+            DataType::Object(o) => {
+                self.bytes.push(4);
+                self.str(o.name.as_str());
+                self.len(o.generics.len());
+                for g in o.generics.iter() {
+                    match g {
+                        Generic::Any => self.bytes.push(0),
+                        Generic::Type(t) => {
+                            self.bytes.push(1);
+                            self.typ(t)
+                        }
+                    }
                 }
-                let siz = getSize(&data, offset);
-                skip += siz.1;
-                buf.push(LocalVarTable {
-                    typ: bu.into_boxed_slice(),
-                    argsCount: siz.0,
-                })
-            }
-            RawOpCode::FunEnd => buf.push(FunEnd),
-            RawOpCode::F2I => buf.push(F2I),
-            RawOpCode::I2F => buf.push(I2F),
-            RawOpCode::PushInt => {
-                let s = getSize(&data, i);
-                skip += s.1;
-                buf.push(PushInt(s.0 as isize))
             }
-            RawOpCode::PushFloat => {
-                let s = getFloat(&data, i);
-                println!("push f {} {}", s.0, s.1);
-                skip += s.1;
-                buf.push(PushFloat(s.0))
-            }
-            RawOpCode::PushBool => buf.push(PushBool(data[i] != 0)),
-            RawOpCode::Pop => buf.push(Pop),
-            RawOpCode::Dup => buf.push(Dup),
-            RawOpCode::PushLocal => {
-                let s = getSize(&data, i);
-                skip += s.1;
-                buf.push(PushLocal { index: s.0 })
-            }
-            RawOpCode::SetLocal => {
-                let t = getType(&data, i);
-                skip += t.1;
-                let s = getSize(&data, i + t.1);
-                skip += s.1;
-                buf.push(SetLocal {
-                    index: s.0,
-                    typ: t.0,
-                })
-            }
-            RawOpCode::Jmp => {
-                let s = getSize(&data, i);
-                skip += s.1;
+        }
+    }
+}
 
-                let jmpType: JmpType = unsafe { transmute(data[i + s.1]) };
-                skip += 1;
+struct Reader<'a> {
+    bytes: &'a [u8],
+    index: usize,
+}
 
-                buf.push(Jmp {
-                    offset: s.0 as isize,
-                    jmpType,
-                })
-            }
-            RawOpCode::Call => {
-                let mut offset = i;
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let res = self
+            .bytes
+            .get(self.index..self.index + n)
+            .ok_or("unexpected end of bytecode")?;
+        self.index += n;
+        Ok(res)
+    }
 
-                let encName = getStr(&data, offset);
-                skip += encName.1;
-                offset += encName.1;
+    fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.take(1)?[0])
+    }
 
-                buf.push(Call { encoded: MyStr::Runtime(encName.0.into_boxed_str()) })
-            }
-            RawOpCode::Return => buf.push(Return),
-            RawOpCode::Add => {
-                let d = getType(&data, i);
-                skip += d.1;
-                buf.push(Add(d.0))
-            }
-            RawOpCode::Sub => {
-                let d = getType(&data, i);
-                skip += d.1;
-                buf.push(Sub(d.0))
-            }
-            RawOpCode::Div => {
-                let d = getType(&data, i);
-                skip += d.1;
-                buf.push(Div(d.0))
-            }
-            RawOpCode::Mul => {
-                let d = getType(&data, i);
-                skip += d.1;
-                buf.push(Mul(d.0))
-            }
-            RawOpCode::Equals => {
-                let d = getType(&data, i);
-                skip += d.1;
-                buf.push(Equals(d.0))
-            }
-            RawOpCode::Greater => {
-                let d = getType(&data, i);
-                skip += d.1;
-                buf.push(Greater(d.0))
-            }
-            RawOpCode::Less => {
-                let d = getType(&data, i);
-                skip += d.1;
-                buf.push(Less(d.0))
-            }
-            RawOpCode::Or => buf.push(Or),
-            RawOpCode::And => buf.push(And),
-            RawOpCode::Not => buf.push(Not),
-            RawOpCode::ClassBegin => buf.push(ClassBegin),
-            RawOpCode::ClassName => {}
-            RawOpCode::ClassField => {}
-            RawOpCode::ClassEnd => buf.push(ClassEnd),
-            RawOpCode::New => {
-                let s = getStr(&data, i);
-                skip += s.1;
-                buf.push(New { name: MyStr::Runtime(s.0.into_boxed_str()) })
-            }
-            RawOpCode::GetField => {}
-            RawOpCode::SetField => {}
-            RawOpCode::ArrayNew => {
-                let t = getType(&data, i);
-                skip += t.1;
-                buf.push(ArrayNew(t.0))
-            }
-            RawOpCode::ArrayStore => {}
-            RawOpCode::ArrayLoad => {}
-            RawOpCode::ArrayLength => buf.push(ArrayLength),
-            RawOpCode::Inc => {
-                let t = getType(&data, i);
-                skip += t.1;
-                let s = getSize(&data, i + t.1);
-                skip += s.1;
-                buf.push(Inc {
-                    index: s.0,
-                    typ: t.0,
-                })
-            }
-            RawOpCode::Dec => {
-                let t = getType(&data, i);
-                skip += t.1;
-                let s = getSize(&data, i + t.1);
-                skip += s.1;
-                buf.push(Dec {
-                    index: s.0,
-                    typ: t.0,
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<MyStr, Box<dyn Error>> {
+        let len = self.u32()? as usize;
+        let s = std::str::from_utf8(self.take(len)?)?;
+        Ok(MyStr::from(s.to_string()))
+    }
+
+    fn typ(&mut self) -> Result<DataType, Box<dyn Error>> {
+        Ok(match self.u8()? {
+            0 => DataType::Int,
+            1 => DataType::Float,
+            2 => DataType::Bool,
+            3 => DataType::Char,
+            4 => {
+                let name = self.str()?;
+                let mut generics = vec![];
+                for _ in 0..self.u32()? {
+                    match self.u8()? {
+                        0 => generics.push(Generic::Any),
+                        1 => generics.push(Generic::Type(self.typ()?)),
+                        g => return Err(format!("invalid generic tag {g}").into()),
+                    }
+                }
+                DataType::Object(ObjectMeta {
+                    name,
+                    generics: generics.into_boxed_slice(),
                 })
             }
-        }
+            t => return Err(format!("invalid type tag {t}").into()),
+        })
     }
+}
+
+pub fn serialize(ops: &[OpCode], lines: &LineTable) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(Module::compile(ops, lines)?.toBytes())
+}
 
-    buf
+pub fn deserialize(bytes: &[u8]) -> Result<(Vec<OpCode>, LineTable), Box<dyn Error>> {
+    let module = Module::fromBytes(bytes)?;
+    Ok((module.decode()?, module.lines))
 }
@@ -1,12 +1,11 @@
 use std::env::args;
-use std::rc::Rc;
 
 use crate::lexer::TokenType::Var;
-use crate::objects::Str;
+use crate::objects::{StringBuilder, ViplObject};
 use crate::vm::*;
 use crate::vm::DataType::*;
 use crate::vm::OpCode::*;
-use crate::vm::Value::*;
+use crate::vmError::VmError;
 
 pub fn bootStrapVM() -> VirtualMachine {
     let mut vm = VirtualMachine::new();
@@ -17,7 +16,10 @@ pub fn bootStrapVM() -> VirtualMachine {
             name: MyStr::Static("Value"),
             typ: Int,
         }]),
-        |_a, b| println!("{}", b.localVariables[0].getNum()),
+        |_a, b| {
+            println!("{}", b.localVariables[0].getNum()?);
+            Ok(())
+        },
         None,
     );
 
@@ -27,7 +29,10 @@ pub fn bootStrapVM() -> VirtualMachine {
             name: MyStr::Static("Value"),
             typ: Float,
         }]),
-        |_a, b| println!("{}", b.localVariables[0].getFlo()),
+        |_a, b| {
+            println!("{}", b.localVariables[0].getFlo()?);
+            Ok(())
+        },
         None,
     );
 
@@ -38,11 +43,12 @@ pub fn bootStrapVM() -> VirtualMachine {
             VariableMetadata::i(MyStr::Static("right")),
         ]),
         |_a, b| {
-            let left = b.localVariables[1].getNum();
-            let right = b.localVariables[0].getNum();
+            let left = b.localVariables[1].getNum()?;
+            let right = b.localVariables[0].getNum()?;
             if left != right {
-                panic!("assert {left} != {right}")
+                return Err(VmError::NativeError(format!("assert {left} != {right}")));
             }
+            Ok(())
         },
         None,
     );
@@ -57,7 +63,7 @@ pub fn bootStrapVM() -> VirtualMachine {
                 Some(v) => v
             };
              */
-            let mut genOps = [
+            let genOps = [
                 PushInt(1),
                 Pop,
                 PushInt(69),
@@ -65,13 +71,16 @@ pub fn bootStrapVM() -> VirtualMachine {
                     encoded: MyStr::Static("print(int)"),
                 },
             ];
+            let mut code = vec![];
+            link(a, &genOps, &mut code)?;
 
             let mut seek = SeekableOpcodes {
                 index: 0,
-                opCodes: &mut genOps,
+                opCodes: &mut code,
             };
 
-            run(&mut seek, a, b);
+            run(&mut seek, a, b)?;
+            Ok(())
         },
         None,
     );
@@ -84,95 +93,89 @@ pub fn bootStrapVM() -> VirtualMachine {
         }]),
         |_a, b| {
             let c = b.localVariables.get(0).unwrap();
-            match c {
-                Num(_) => {}
-                Flo(_) => {}
-                Bol(_) => {}
-                Chr(_) => {}
-                Reference { instance } => match instance {
+            match c.kind() {
+                ValueKind::Num(_) => {}
+                ValueKind::Flo(_) => {}
+                ValueKind::Bol(_) => {}
+                ValueKind::Chr(_) => {}
+                ValueKind::Reference(instance) => match instance {
                     None => {}
-                    Some(ee) => unsafe {
-                        let mut clon = ee.clone();
-                        let ne = Rc::get_mut_unchecked(&mut clon);
-                        let ff = ne.getStr();
-                        println!("{}", ff.string);
-                    },
+                    Some(ee) => println!("{}", ee.getStr()?.string),
                 },
             }
+            Ok(())
         },
         None,
     );
 
     vm.makeNative(
-        String::from("makeString"),
+        String::from("makeBuilder"),
         Box::new([]),
         |a, _b| {
-            a.stack.push(Value::Reference {
-                instance: Some(Rc::new(
-                    Str {
-                        string: "".to_string(),
-                    }
-                        .into(),
-                )),
-            })
+            a.stack.push(Value::Reference(Some(ViplObject::alloc(StringBuilder::default().into()))));
+            Ok(())
         },
-        Some(DataType::str()),
+        Some(DataType::builder()),
     );
 
     vm.makeNative(
         String::from("appendChar"),
         Box::new([
             VariableMetadata {
-                name: MyStr::Static("str"),
-                typ: DataType::str(),
+                name: MyStr::Static("builder"),
+                typ: DataType::builder(),
             },
             VariableMetadata {
                 name: MyStr::Static("chr"),
                 typ: DataType::Char,
             },
         ]),
-        |_a, b| {
-            let chr = match b.localVariables.get(1).unwrap() {
-                Chr(c) => *c,
-                n => {
-                    panic!("{n:?}")
-                }
-            };
-            let str = b.localVariables.get_mut(0).unwrap();
-            match str {
-                Reference { instance } => match instance {
-                    None => {
-                        panic!()
-                    }
-                    Some(v) => unsafe {
-                        let ne = Rc::get_mut_unchecked(v);
-                        let e = ne.getMutStr();
-                        e.string.push(chr);
-                    },
-                },
-                ee => {
-                    panic!("{ee:?}");
-                }
-            }
+        |vm, b| {
+            let chr = b.localVariables.get(1).unwrap().getChar()?;
+            vm.reserve(chr.len_utf8())?;
+            b.localVariables.get(0).unwrap().getObject()?.getBuilder()?.push(chr);
+            Ok(())
         },
         None,
     );
 
+    // copies current content, builder can keep growing without affecting the string
+    vm.makeNative(
+        String::from("buildString"),
+        Box::new([VariableMetadata {
+            name: MyStr::Static("builder"),
+            typ: DataType::builder(),
+        }]),
+        |vm, locals| {
+            let b = locals.localVariables.get(0).unwrap().getObject()?.getBuilder()?;
+            vm.reserve(b.buffer.borrow().len())?;
+            let s = b.buffer.borrow().clone();
+            vm.stack.push(Value::makeString(s));
+            Ok(())
+        },
+        Some(DataType::str()),
+    );
+
+    vm.makeNative(
+        String::from("gc"),
+        Box::new([]),
+        |vm, _| {
+            vm.stack.push(Value::Num(crate::gc::collect() as isize));
+            Ok(())
+        },
+        Some(DataType::Int),
+    );
+
     vm.makeNative(
         "arrayLen".to_string(),
         Box::new([VariableMetadata {
             name: MyStr::Static(""),
             typ: DataType::arr(Generic::Any),
         }]),
-        |vm, locals| match locals.localVariables.get_mut(0).unwrap() {
-            Reference { instance } => match instance {
-                None => panic!(),
-                Some(v) => unsafe {
-                    let v = v.getArr();
-                    vm.stack.push(Value::Num(v.internal.len() as isize))
-                },
-            },
-            _ => panic!(),
+        |vm, locals| {
+            let v = locals.localVariables.get(0).unwrap().getObject()?.getArr()?;
+            vm.stack.push(Value::Num(v.len() as isize));
+            Ok(())
         },
         Some(DataType::Int),
     );
@@ -183,15 +186,10 @@ pub fn bootStrapVM() -> VirtualMachine {
             name: MyStr::Static(""),
             typ: DataType::str(),
         }]),
-        |vm, locals| match locals.localVariables.get_mut(0).unwrap() {
-            Reference { instance } => match instance {
-                None => panic!(),
-                Some(v) => unsafe {
-                    let a = v.getStr();
-                    vm.stack.push(Value::Num(a.string.len() as isize))
-                },
-            },
-            _ => panic!(),
+        |vm, locals| {
+            let a = locals.localVariables.get(0).unwrap().getString()?;
+            vm.stack.push(Value::Num(a.len() as isize));
+            Ok(())
         },
         Some(DataType::Int),
     );
@@ -209,19 +207,13 @@ pub fn bootStrapVM() -> VirtualMachine {
             },
         ]),
         |vm, locals| {
-            let index = locals.localVariables.get(1).unwrap().getNum();
-            match locals.localVariables.get_mut(0).unwrap() {
-                Reference { instance } => match instance {
-                    None => panic!(),
-                    Some(v) => unsafe {
-                        let a = v.getStr();
-                        vm.stack.push(Value::Chr(
-                            *a.string.as_bytes().get_unchecked(index as usize) as char,
-                        ))
-                    },
-                },
-                _ => panic!(),
+            let index = locals.localVariables.get(1).unwrap().getNum()?;
+            let a = locals.localVariables.get(0).unwrap().getString()?;
+            match a.as_bytes().get(index as usize) {
+                Some(c) if index >= 0 => vm.stack.push(Value::Chr(*c as char)),
+                _ => return Err(VmError::IndexOutOfBounds { index, len: a.len() }),
             }
+            Ok(())
         },
         Some(DataType::Char),
     );
@@ -239,26 +231,10 @@ pub fn bootStrapVM() -> VirtualMachine {
             },
         ]),
         |vm, locals| {
-            let sec = locals.localVariables.get(1).unwrap().clone();
-            match locals.localVariables.get_mut(0).unwrap() {
-                Reference { instance } => match instance {
-                    None => panic!(),
-                    Some(v) => unsafe {
-                        let e = v.getStr();
-                        match sec {
-                            Reference { instance } => match instance {
-                                None => panic!(),
-                                Some(k) => {
-                                    let c = k.getStr();
-                                    vm.stack.push(Value::Bol(e.string.ends_with(&c.string)))
-                                }
-                            },
-                            _ => panic!(),
-                        }
-                    },
-                },
-                _ => panic!(),
-            }
+            let e = locals.localVariables.get(0).unwrap().getString()?;
+            let c = locals.localVariables.get(1).unwrap().getString()?;
+            vm.stack.push(Value::Bol(e.ends_with(c.as_str())));
+            Ok(())
         },
         Some(DataType::Bool),
     );
@@ -271,9 +247,9 @@ pub fn bootStrapVM() -> VirtualMachine {
             VariableMetadata::from(DataType::Int),   // arg count
         ],
         |vm, locals| unsafe {
-            let path = locals.localVariables.get(0).unwrap().getString();
-            let name = locals.localVariables.get(1).unwrap().getString();
-            let argCount = locals.localVariables.get(2).unwrap().getNum();
+            let path = locals.localVariables.get(0).unwrap().getString()?;
+            let name = locals.localVariables.get(1).unwrap().getString()?;
+            let argCount = locals.localVariables.get(2).unwrap().getNum()?;
 
             vm.loadRawNative(path, name, None, argCount as usize)
         },
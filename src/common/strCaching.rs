@@ -1,6 +0,0 @@
-use std::collections::HashMap;
-use std::sync::Mutex;
-use crate::ast::Op;
-use crate::objects::ViplObject;
-
-static strCache: Option<Mutex<HashMap<String, ViplObject>>> = None;
\ No newline at end of file
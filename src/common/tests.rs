@@ -1,20 +1,30 @@
+use std::collections::HashMap;
 use std::ops::Add;
 use std::ptr::null;
 use std::rc::Rc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use crate::ast::{Expression, Op};
-use crate::codegen::bytecodeGen;
+use crate::bytecodeBuilder::BytecodeBuilder;
+use crate::codegen::{bytecodeGen, bytecodeGen2, complexBytecodeGen};
 use crate::ffi::evaluate;
+use crate::gc;
 use crate::lexer::{lexingUnits, SourceProvider, tokenize, tokenizeSource, TokenType};
 use crate::lexer::TokenType::IntLiteral;
+use crate::limits::{Limit, Limits};
 use crate::parser::parseTokens;
+use crate::lineTable::LineTable;
+use crate::lints::{Lint, lintProgram, LintConfig};
 use crate::rice::Rice;
+use crate::serialization::{deserialize, Instruction, Module, serialize};
 use crate::std::bootStrapVM;
 use crate::vm::{
-    DataType, evaluateBytecode, MyStr, OpCode, StackFrame, Value, VariableMetadata, VirtualMachine,
+    DataType, evaluateBytecode, Generic, JmpType, link, LinkError, LinkedOp, MyStr, OpCode, RawOpCode, runTraced,
+    SeekableOpcodes, StackFrame, Value, ValueKind, VariableMetadata,
+    VirtualMachine,
 };
 use crate::vm::RawOpCode::PushInt;
+use crate::vmError::VmError;
 
 #[test]
 fn testNumericLexingUnit() {
@@ -189,7 +199,7 @@ fn testOptimization() {
 
 #[test]
 pub fn testLexingUnits() {
-    let input = "fn test(x: int): int { print( x ) } test ( 25 ) ";
+    let input = "fn test(x: int): int { print( x ) return x } test ( 25 ) ";
 
     let tokens = tokenizeSource(input).unwrap();
     println!("tokens {:?}", &tokens);
@@ -212,12 +222,13 @@ pub fn testLibLoading() {
             "test123",
             None,
             Box::new([VariableMetadata::i(MyStr::Static(""))]),
-        );
+        )
+        .unwrap();
     }
 
     let a = Instant::now();
 
-    vm.call(MyStr::Static("test123(int)"));
+    vm.call(MyStr::Static("test123(int)")).unwrap();
 
     let elapsed = a.elapsed();
     println!("finished in: {:.2?}", elapsed);
@@ -245,13 +256,17 @@ pub fn testComiple() {
 
     let res = crate::gccWrapper::compile(sc).unwrap();
 
-    let ops = vec![
+    let load = vec![
         OpCode::StrNew(MyStr::Runtime(res.into_boxed_str())),
         OpCode::StrNew(MyStr::Static("lool(int)")),
         OpCode::PushInt(1),
         OpCode::Call {
             encoded: MyStr::Static("loadNative(String, String, int)"),
         },
+    ];
+
+    // lool only exists after loading so it has to be linked separately
+    let ops = vec![
         OpCode::PushInt(69),
         OpCode::Call {
             encoded: MyStr::Static("lool(int)"),
@@ -271,10 +286,11 @@ pub fn testComiple() {
         },
     ];
 
-    vm.eval(ops, vec![]);
+    vm.eval(load, vec![]).unwrap();
+    vm.eval(ops, vec![]).unwrap();
 
     for _ in 0..1000 {
-        vm.eval(ops2.clone(), vec![]);
+        vm.eval(ops2.clone(), vec![]).unwrap();
     }
 
     println!("{}", vm.stack.len());
@@ -293,3 +309,830 @@ pub fn testRice() {
 
     println!("{}", notPtr.add(5));
 }
+
+#[test]
+fn testMap() {
+    let input = "m = {\"a\": 1, \"b\": 2} m[\"c\"] = 3 m[\"a\"] = 10 assert(m[\"a\"] + m[\"c\"], 13) remove(m, \"b\") x = 0 if contains(m, \"b\") { x = 1 } assert(x, 0) n = 0 for k in keys(m) { n = n + 1 } assert(n, 2)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+
+    evaluateBytecode(bs.0, bs.1);
+}
+
+#[test]
+fn testMapTypes() {
+    let input = "m: Map<int, String> = {1: \"a\", 2: \"bc\"} m[3] = \"def\" assert(strLen(m[2]) + strLen(m[3]), 5)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+    evaluateBytecode(bs.0, bs.1);
+
+    // entries have to agree on key and value type
+    for input in ["m = {1: 2, 2: \"x\"}", "m = {1: 2, \"a\": 3}"] {
+        let res = parseTokens(tokenizeSource(input).unwrap()).unwrap();
+        assert!(bytecodeGen(res).is_err(), "{input}");
+    }
+}
+
+#[test]
+fn testMapTypeErrors() {
+    let check = |input: &str, expected: &str| {
+        let res = parseTokens(tokenizeSource(input).unwrap()).unwrap();
+        let err = bytecodeGen(res).unwrap_err().to_string();
+        assert_eq!(err.lines().count(), 1, "{err}");
+        assert!(err.ends_with(expected), "{err}");
+    };
+
+    let input = "m = {1: 2, 3: 4} xs = [1, 2] s = \"ab\" x = m[1] + xs[0] c = s[1] ys = xs[0..1]";
+    assert!(bytecodeGen(parseTokens(tokenizeSource(input).unwrap()).unwrap()).is_ok());
+
+    check("m = {1: 2, 2: \"x\"}", "map values must have same type expected int got String");
+    check("m = {1: 2, \"a\": 3}", "map keys must have same type expected int got String");
+    check("m = {1: 2} x = m[\"x\"]", "index must be int got String");
+    check("m = {\"a\": 2} m[1] = 3", "index must be String got int");
+    check("xs = [1, 2] x = xs[\"a\"]", "index must be int got String");
+    check("s = \"ab\" c = s[true]", "index must be int got bool");
+}
+
+#[test]
+fn testForLoop() {
+    let input = "sum = 0 for i in [1, 2, 3] { sum = sum + i } assert(sum, 6) m = {1: 5, 2: 6} for k in m { sum = sum + m[k] } assert(sum, 17) count = 0 for c in \"abc\" { count = count + 1 } assert(count, 3)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+
+    evaluateBytecode(bs.0, bs.1);
+}
+
+#[test]
+fn testSlices() {
+    let input = "fn total(r: Range): int { sum = 0 for i in r { sum = sum + i } return sum } assert(total(0..5), 10) xs = [1, 2, 3, 4] n = 3 ys = xs[1..n] assert(ys[0] + ys[1], 5) r = 2..n+2 assert(r[1], 3) s = \"hello\"[1..3] count = 0 for c in s { count = count + 1 } assert(count, 2)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+
+    evaluateBytecode(bs.0, bs.1);
+}
+
+#[test]
+fn testCharAndStringComparison() {
+    let input = "c = '7' assert(c - '0', 7) x = 0 if c + 2 == '9' { x = x + 1 } if 'a' < 'b' { x = x + 1 } name = \"main\" if name == \"main\" { x = x + 1 } if \"abc\" < \"abd\" { x = x + 1 } xs = [1] ys = xs zs = [1] if xs == ys { x = x + 1 } if xs == zs { x = 0 } assert(x, 5)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+
+    evaluateBytecode(bs.0, bs.1);
+}
+
+#[test]
+fn testShortCircuit() {
+    let input = "xs = [1, 2, 3] i = 3 x = 0 if i < 3 && xs[i] == 1 { x = 1 } if i == 3 || xs[i] == 1 { x = x + 2 } y = false && xs[i] == 1 assert(x, 2)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+
+    evaluateBytecode(bs.0, bs.1);
+
+    let a = Expression::ArithmeticOp {
+        left: box Expression::BoolLiteral(false),
+        right: box Expression::Variable(String::from("abc")),
+        op: Op::And,
+    };
+    assert_eq!(crate::optimizer::evalE(&a), Some(Expression::BoolLiteral(false)));
+}
+
+#[test]
+fn testGenerators() {
+    let input = "fn count(n: int): Generator<int> { i = 0 while i < n { yield i i += 1 } } sum = 0 for x in count(4) { sum = sum + x } assert(sum, 6) g = count(2) a = next(g) b = next(g) assert(a + b, 1)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+
+    evaluateBytecode(bs.0, bs.1);
+}
+
+#[test]
+fn testGeneratorAfterError() {
+    // inputs share locals like repl so generator outlives failed input
+    let mut vm = bootStrapVM();
+    let mut functionReturns = vm.functionReturns();
+    let mut mainLocals = HashMap::new();
+    let mut localTypes = vec![];
+    let mut structs = HashMap::new();
+    let mut locals = vec![];
+    let mut opcodes = vec![];
+    let mut linked = vec![];
+    let mut results = vec![];
+
+    for input in [
+        "fn gen(z: int): Generator<int> { a = 5 yield a / z yield a } g = gen(0)",
+        "x = next(g)",
+        "y = next(g)",
+    ] {
+        let res = parseTokens(tokenizeSource(input).unwrap()).unwrap();
+        let bs = complexBytecodeGen(res, &mut localTypes, &mut functionReturns, &mut mainLocals, &mut structs, false)
+            .unwrap();
+        locals.extend(localTypes[locals.len()..].iter().map(DataType::toDefaultValue));
+        let start = opcodes.len();
+        opcodes.extend(bs.0);
+        link(&mut vm, &opcodes, &mut linked).unwrap();
+
+        let mut frame = StackFrame::new(&mut locals);
+        let mut code = SeekableOpcodes {
+            index: start as isize,
+            opCodes: &mut linked,
+        };
+        results.push(runTraced(&mut code, &mut vm, &mut frame));
+        assert!(vm.stack.is_empty());
+        assert!(vm.callStack.is_empty());
+    }
+
+    assert_eq!(results[0], Ok(()));
+    assert_eq!(results[1], Err(VmError::DivisionByZero));
+    assert_eq!(results[2], Err(VmError::InvalidOperation("generator is exhausted".to_string())));
+}
+
+#[test]
+fn testIsolates() {
+    let input = "fn work(n: int, out: Chan<int>): int { i = 0 s = 0 while i < n { s = s + i send(out, i) i += 1 } return s } fn first(xs: Array<int>): int { xs[0] = 7 return xs[0] } ch: Chan<int> = chan() t = spawn(work, 4, ch) got = 0 j = 0 while j < 4 { got = got + recv(ch) j += 1 } assert(got, 6) assert(join(t), 6) xs = [5] assert(join(spawn(first, xs)), 7) assert(xs[0], 5)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+
+    evaluateBytecode(bs.0, bs.1);
+}
+
+#[test]
+fn testSemanticErrors() {
+    let input = "fn half(x: float): float {\n  return 1\n}\nx = 1 + 2.5\nhalf(1, 2)\nmissing(3)\ny += 1\nz: int = half(2.0)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let err = bytecodeGen(res).unwrap_err().to_string();
+
+    let lines = err.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[0], "2:3: returned value must be float got int (in function half)");
+    assert_eq!(lines[1], "4:1: cannot apply Add to int and float");
+    assert!(lines[2].starts_with("5:1: wrong number of arguments for half"));
+    assert_eq!(lines[3], "6:1: function missing is not defined");
+    assert_eq!(lines[4], "7:1: assignment to undeclared variable y");
+    assert_eq!(lines[5], "8:1: cannot assign float to variable z of type int");
+}
+
+#[test]
+fn testOverloadWidening() {
+    let input = "fn half(x: float): float { return x / 2.0 } fn code(c: int): int { return c } fn pick(a: int, b: float): int { return 1 } fn pick(a: float, b: int): int { return 2 } h = half(3) assert(code('a'), 97) assert(pick(1, 2.0), 1) assert(pick(1.0, 2), 2)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+    assert!(bs.0.iter().any(|it| matches!(it, OpCode::I2F)));
+    assert!(bs.0.iter().any(|it| matches!(it, OpCode::C2I)));
+    evaluateBytecode(bs.0, bs.1);
+
+    let input = "fn pick(a: int, b: float): int { return 1 } fn pick(a: float, b: int): int { return 2 } pick(1, 2)";
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let err = bytecodeGen(res).unwrap_err().to_string();
+    assert!(err.contains("call to pick(int, int) is ambiguous, candidates are: pick(float, int), pick(int, float)"), "{err}");
+}
+
+#[test]
+fn testFlowAnalysis() {
+    let input = "fn sign(x: int): int {\n  if x > 0 {\n    return 1\n  }\n}\nfn pick(c: bool): int {\n  if c {\n    y = 1\n  }\n  return y\n}\nfn stop(): int {\n  return 1\n  print(2)\n}\nfn spin(): int {\n  while true {\n  }\n}";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let err = bytecodeGen(res).unwrap_err().to_string();
+
+    let lines = err.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "1:1: missing return of int at end of function (in function sign)");
+    assert_eq!(lines[1], "10:3: variable y might not be assigned (in function pick)");
+    assert_eq!(lines[2], "14:3: unreachable code (in function stop)");
+
+    let input = "fn abs(x: int): int { if x > 0 { return x } else { return 0 - x } } fn first(): int { loop { return 1 } } y = 0 if abs(0 - 2) > 1 { y = 1 } else { y = 2 } assert(y, 1) assert(first(), 1)";
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+    evaluateBytecode(bs.0, bs.1);
+}
+
+#[test]
+fn testLints() {
+    let input = "fn unused(a: int, _b: int): int {\n  tmp = 1\n  return 2\n}\nfn print2(print2: int) {\n  print(print2)\n}\nx = 1\nwhile true {\n  x += 1\n}\nif x < x {\n  print2(1)\n}\n// allow(unused-variable)\ny = 3\nz = 4 // allow(unused-variable)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+
+    let warnings = lintProgram(&res, input, &LintConfig::default())
+        .iter()
+        .map(|it| it.to_string())
+        .collect::<Vec<_>>();
+
+    assert_eq!(warnings, vec![
+        "1:1: warning: parameter a is never read [unused-parameter] (in function unused)",
+        "1:1: warning: function unused is never called [unused-function]",
+        "2:3: warning: variable tmp is never read [unused-variable] (in function unused)",
+        "5:1: warning: parameter print2 shadows function print2 [shadowed-name] (in function print2)",
+        "9:1: warning: loop never exits [constant-condition]",
+        "12:1: warning: comparison is always false [always-false]",
+    ]);
+
+    let config = LintConfig::parse("# project settings\nallow unused-parameter\nallow always-false").unwrap();
+    let warnings = lintProgram(&res, input, &config);
+    assert!(warnings.iter().all(|it| it.lint != Lint::UnusedParameter && it.lint != Lint::AlwaysFalse));
+    assert_eq!(warnings.len(), 4);
+
+    assert!(LintConfig::parse("allow unused-everything").is_err());
+
+    // comment markers inside literals are not comments
+    let input = "s = \"http://x\" // allow(unused-variable)\nt = \"// allow(unused-variable)\"";
+    let res = parseTokens(tokenizeSource(input).unwrap()).unwrap();
+    let warnings = lintProgram(&res, input, &LintConfig::default())
+        .iter()
+        .map(|it| it.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(warnings, vec!["2:1: warning: variable t is never read [unused-variable]"]);
+}
+
+#[test]
+fn testCompactBytecode() {
+    let input = "fn greet(name: String): String { return name } m = {1: 'a'} s = greet(\"hello\") big = 10_000_000_000 f = 2.5 for i in 0..3 { f += 1.0 } assert(m[1], 'a') assert(big, 10_000_000_000)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+
+    assert_eq!(std::mem::size_of::<Instruction>(), 8);
+    assert_eq!(std::mem::size_of::<LinkedOp>(), 16);
+    for code in 0..=u8::MAX {
+        if let Ok(raw) = RawOpCode::try_from(code) {
+            assert_eq!(raw as u8, code);
+        }
+    }
+    assert_eq!(RawOpCode::try_from(RawOpCode::ArrayPush as u8), Ok(RawOpCode::ArrayPush));
+    assert_eq!(RawOpCode::try_from(u8::MAX), Err(u8::MAX));
+
+    let module = Module::compile(&bs.0, &bs.2).unwrap();
+    assert_eq!(module.code.len(), bs.0.len());
+    // repeated types and names are stored once
+    assert_eq!(module.pool.types.iter().filter(|it| **it == DataType::Int).count(), 1);
+
+    let bytes = module.toBytes();
+    let decoded = deserialize(&bytes).unwrap();
+    assert_eq!(decoded.1, bs.2);
+    assert_eq!(serialize(&decoded.0, &decoded.1).unwrap(), bytes);
+
+    assert!(deserialize(&bytes[..bytes.len() - 3]).is_err());
+    assert!(deserialize(b"ELF").is_err());
+
+    evaluateBytecode(decoded.0, bs.1);
+}
+
+#[test]
+fn testModuleValidation() {
+    let input = "fn f(a: int): int { b = a if a > 0 { b = 1 } return b } x = f(1) assert(x, 1)";
+
+    let bs = bytecodeGen(parseTokens(tokenizeSource(input).unwrap()).unwrap()).unwrap();
+    let module = Module::compile(&bs.0, &bs.2).unwrap();
+    assert!(Module::fromBytes(&module.toBytes()).is_ok());
+
+    let begin = module.code.iter().position(|it| it.code == RawOpCode::FunBegin).unwrap();
+    let find = |code: RawOpCode| begin + module.code[begin..].iter().position(|it| it.code == code).unwrap();
+    let tampered = |index: usize, f: &dyn Fn(&mut Instruction)| {
+        let mut m = Module::compile(&bs.0, &bs.2).unwrap();
+        f(&mut m.code[index]);
+        Module::fromBytes(&m.toBytes()).map(|_| ()).map_err(|it| it.to_string())
+    };
+
+    assert!(tampered(find(RawOpCode::PushLocal), &|it| it.arg = 50).unwrap_err().contains("local 50"));
+    assert!(tampered(find(RawOpCode::Jmp), &|it| it.arg = 1000).unwrap_err().contains("jump"));
+    assert!(tampered(find(RawOpCode::Jmp), &|it| it.arg = (-100i32) as u32).unwrap_err().contains("jump"));
+    assert!(tampered(find(RawOpCode::FunEnd), &|it| it.code = RawOpCode::Pop).unwrap_err().contains("no end"));
+    assert!(tampered(begin + 2, &|it| it.code = RawOpCode::Pop).unwrap_err().contains("missing"));
+    assert!(tampered(begin, &|it| it.code = RawOpCode::Pop).unwrap_err().contains("outside of function"));
+
+    // top level locals come from caller and are checked before run
+    let decoded = deserialize(&module.toBytes()).unwrap();
+    let mut vm = bootStrapVM();
+    assert!(matches!(vm.eval(decoded.0, vec![]), Err(VmError::InvalidOperation(_))));
+    assert!(vm.stack.is_empty());
+}
+
+#[test]
+fn testStringInterning() {
+    let input = "for i in 0..100 { s = \"literal\" } t = \"literal\" + \"!\" assert(strLen(t), 8) m = makeBuilder() appendChar(m, 'a') assert(strLen(buildString(m)), 1)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+    let mut vm = bootStrapVM();
+    link(&mut vm, &bs.0, &mut vec![]).unwrap();
+    // literals are looked up once when linking
+    assert_eq!(vm.constants.len(), 2);
+    let vm = evaluateBytecode(bs.0, bs.1);
+    assert_eq!(vm.constants.len(), 2);
+
+    // strings are immutable, only builder can be appended to
+    let input = "s = \"abc\" appendChar(s, 'd')";
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    assert!(bytecodeGen(res).is_err());
+}
+
+#[test]
+fn testLinking() {
+    let input = "fn double(x: int): int { return x * 2 } assert(double(21), 42)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+
+    let mut vm = bootStrapVM();
+    let mut code = vec![];
+    link(&mut vm, &bs.0, &mut code).unwrap();
+    // function ranges and line table index both forms
+    assert_eq!(code.len(), bs.0.len());
+    assert!(vm.function(&MyStr::Static("double(int)")).is_some());
+
+    let mut vm = bootStrapVM();
+    let err = vm
+        .eval(
+            vec![
+                OpCode::PushInt(1),
+                OpCode::Call {
+                    encoded: MyStr::Static("missing(int)"),
+                },
+            ],
+            vec![],
+        )
+        .unwrap_err();
+    assert_eq!(err, VmError::UnknownFunction("missing(int)".to_string()));
+    // nothing runs when linking fails
+    assert!(vm.stack.is_empty());
+
+    // malformed headers are reported instead of panicking
+    let header = vec![
+        OpCode::FunBegin,
+        OpCode::FunName {
+            name: MyStr::Static("f"),
+        },
+        OpCode::LocalVarTable {
+            typ: Box::new([]),
+            argsCount: 0,
+        },
+        OpCode::FunReturn { typ: None },
+    ];
+    for ops in [vec![OpCode::FunBegin], header[..2].to_vec(), header.clone(), [header.clone(), header].concat()] {
+        let mut vm = bootStrapVM();
+        let res = link(&mut vm, &ops, &mut vec![]);
+        assert!(matches!(res, Err(LinkError::MalformedFunction(0))), "{res:?}");
+    }
+}
+
+#[test]
+fn testTailCalls() {
+    let input = "fn count(n: int, acc: int): int { if n == 0 { return acc } return count(n - 1, acc + 1) } \
+    fn fib(n: int): int { if n < 2 { return n } return fib(n - 1) + fib(n - 2) } \
+    assert(count(1000000, 0), 1000000) assert(fib(15), 610)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+    assert_eq!(
+        bs.0.iter().filter(|it| matches!(it, OpCode::TailCall { .. })).count(),
+        1
+    );
+
+    let bytes = serialize(&bs.0, &bs.2).unwrap();
+    let decoded = deserialize(&bytes).unwrap();
+    assert_eq!(serialize(&decoded.0, &decoded.1).unwrap(), bytes);
+
+    evaluateBytecode(bs.0, bs.1);
+}
+
+#[test]
+fn testLoopJumps() {
+    let input = "fn odd(n: int): int { s = 0 i = 0 while i < n { i += 1 if i == 2 * (i / 2) { continue } s += i } return s } \
+    fn firstOver(n: int): int { i = 0 loop { i += 1 if n < i { break } } return i } \
+    fn skipB(s: String): int { c = 0 for ch in s { if ch == 'b' { continue } c += 1 } return c } \
+    assert(odd(6), 9) assert(firstOver(4), 5) assert(skipB(\"abcb\"), 2) if true && odd(1) == 1 || false { assert(1, 1) } else { assert(0, 1) }";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+    evaluateBytecode(bs.0, bs.1);
+
+    let mut builder = BytecodeBuilder::new();
+    let end = builder.label();
+    builder.jump(end, JmpType::Jmp);
+    assert!(builder.finish(&mut vec![], &mut LineTable::default(), None).is_err());
+
+    let mut builder = BytecodeBuilder::new();
+    builder.push(OpCode::Jmp {
+        offset: 2,
+        jmpType: JmpType::Jmp,
+    });
+    assert_eq!(
+        builder
+            .finish(&mut vec![], &mut LineTable::default(), None)
+            .unwrap_err().to_string(),
+        "jump at 0 lands at 3 outside of function of length 1"
+    );
+}
+
+#[test]
+fn testLineTable() {
+    let input = "fn div(a: int, b: int): int {\n  return a / b\n}\nfn half(x: int): int {\n  y = div(x, 0)\n  return y\n}\nprint(1)\nhalf(4)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+
+    let call = bs.0.iter().position(|it| matches!(it, OpCode::Call { encoded } if encoded.as_str() == "div(int, int)")).unwrap();
+    let entry = bs.2.lookup(call).unwrap();
+    assert_eq!(entry.location.line, 5);
+    assert_eq!(entry.function.as_ref().unwrap().as_str(), "half");
+
+    let mut vm = bootStrapVM();
+    vm.lines = bs.2;
+    vm.lines.file = Some("half.vipl".to_string());
+    let err = vm.eval(bs.0, bs.1).unwrap_err();
+    assert_eq!(err, VmError::DivisionByZero);
+    let msg = vm.describeError(&err);
+    assert!(msg.contains("at half.vipl:2:"));
+    assert!(msg.contains("in div\n    at half.vipl:5:"));
+    assert!(msg.contains("in half\n    at half.vipl:9:"));
+}
+
+#[test]
+fn testFusedBranches() {
+    let input = "fn count(n: int): int { i = 0 c = 0 while i < n { if !(i == 3) { c += 1 } i += 1 } return c } \
+    fn chars(s: String): int { c = 0 for ch in s { if ch > 'a' { c += 1 } } return c } \
+    fn floats(x: float): int { if x > 1.5 { return 1 } if 0.5 < x { return 2 } return 3 } \
+    assert(count(5), 4) assert(chars(\"abca\"), 2) assert(floats(2.0), 1) assert(floats(1.0), 2) assert(floats(0.0), 3)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+
+    assert!(bs.0.iter().any(|it| matches!(it, OpCode::Jmp { jmpType: JmpType::Ge(DataType::Int), .. })));
+    assert!(bs.0.iter().any(|it| matches!(it, OpCode::Jmp { jmpType: JmpType::Eq(DataType::Int), .. })));
+    assert!(bs.0.iter().any(|it| matches!(it, OpCode::Jmp { jmpType: JmpType::Le(DataType::Char), .. })));
+    assert!(bs.0.iter().any(|it| matches!(it, OpCode::Jmp { jmpType: JmpType::Ge(DataType::Float), .. })));
+    assert!(!bs.0.iter().any(|it| matches!(it, OpCode::Greater(_) | OpCode::Less(_))));
+
+    let bytes = serialize(&bs.0, &bs.2).unwrap();
+    let decoded = deserialize(&bytes).unwrap();
+    assert_eq!(serialize(&decoded.0, &decoded.1).unwrap(), bytes);
+
+    evaluateBytecode(bs.0, bs.1);
+}
+
+#[test]
+fn testTaggedValues() {
+    assert_eq!(std::mem::size_of::<Value>(), 8);
+
+    assert_eq!(Value::Num(-42).getNum().unwrap(), -42);
+    assert_eq!(Value::Num(1 << 59).getNum().unwrap(), 1 << 59);
+    assert_eq!(Value::Flo(-1.5).getFlo().unwrap(), -1.5);
+    assert!(Value::Bol(true).getBool().unwrap());
+    assert_eq!(Value::Chr('ž').getChar().unwrap(), 'ž');
+    assert!(Value::null().getReference().unwrap().is_none());
+    assert!(matches!(Value::Flo(f32::NAN).kind(), ValueKind::Flo(f) if f.is_nan()));
+
+    let s = Value::makeString("abc".to_string());
+    let copy = s.clone();
+    assert_eq!(Rc::strong_count(s.getObject().unwrap()), 2);
+    drop(copy);
+    assert_eq!(Rc::strong_count(s.getObject().unwrap()), 1);
+    assert_eq!(s.getString().unwrap(), "abc");
+
+    let input = "fn f(n: int): int { a = -7 b = n * a return b / 2 } assert(f(3), -10) \
+    x = 0.5 + 1.0 if x > 1.2 { assert(1, 1) } else { assert(1, 0) } \
+    s = \"a\" + \"b\" assert(strLen(s), 2) c = 'a' assert(c - 'a', 0)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+
+    evaluateBytecode(bs.0, bs.1);
+}
+
+#[test]
+fn testIntegerOverflow() {
+    let mut vm = bootStrapVM();
+    let mut eval = |input: &str| {
+        let tokens = tokenizeSource(input).unwrap();
+        let res = parseTokens(tokens).unwrap();
+        let bs = bytecodeGen(res).unwrap();
+        vm.eval(bs.0, bs.1)
+    };
+
+    let double = "x = 1 i = 0 while i < n { x = x * 2 i += 1 }";
+    assert_eq!(eval(&format!("n = 59 {double} assert(x / 2, 288230376151711744)")), Ok(()));
+    assert_eq!(eval(&format!("n = 60 {double} assert(x / 2, 576460752303423488)")), Err(VmError::IntegerOverflow));
+    assert_eq!(eval("x = 1152921504606846975 x += 1"), Err(VmError::IntegerOverflow));
+    assert_eq!(eval("x = 0 - 1152921504606846975 x = x - 2"), Err(VmError::IntegerOverflow));
+    assert_eq!(eval("x = 1152921504606846975 assert(x, 1152921504606846975)"), Ok(()));
+
+    let tokens = tokenizeSource("x = 1152921504606846976").unwrap();
+    let err = bytecodeGen(parseTokens(tokens).unwrap()).unwrap_err().to_string();
+    assert!(err.contains("out of range"));
+}
+
+#[test]
+fn testRuntimeErrors() {
+    let mut vm = bootStrapVM();
+    let mut eval = |input: &str| {
+        let tokens = tokenizeSource(input).unwrap();
+        let res = parseTokens(tokens).unwrap();
+        let bs = bytecodeGen(res).unwrap();
+        vm.eval(bs.0, bs.1)
+    };
+
+    assert_eq!(eval("xs = [1, 2] print(xs[5])"), Err(VmError::IndexOutOfBounds { index: 5, len: 2 }));
+    assert_eq!(eval("fn f(a: int): int { return 10 / a } print(f(0))"), Err(VmError::DivisionByZero));
+    assert_eq!(eval("assert(1, 2)"), Err(VmError::NativeError("assert 2 != 1".to_string())));
+    assert_eq!(eval("print(getChar(\"ab\", 3))"), Err(VmError::IndexOutOfBounds { index: 3, len: 2 }));
+    assert_eq!(eval("assert(2, 2)"), Ok(()));
+
+    assert_eq!(vm.call(MyStr::Static("missing()")), Err(VmError::UnknownFunction("missing()".to_string())));
+    assert_eq!(vm.eval(vec![OpCode::Add(DataType::Int)], vec![]), Err(VmError::StackUnderflow));
+    assert_eq!(
+        vm.eval(vec![OpCode::PushInt(1), OpCode::PushBool(true), OpCode::Add(DataType::Int)], vec![]),
+        Err(VmError::TypeError { expected: "int".to_string(), got: "bool".to_string() })
+    );
+    assert_eq!(vm.backtrace.iter().map(|it| it.index).collect::<Vec<_>>(), vec![Some(2)]);
+}
+
+fn stackDepth(vm: &mut VirtualMachine, frame: &mut StackFrame) -> Result<(), VmError> {
+    assert_eq!(frame.name, Some("depth"));
+    let frames = vm.frames();
+    assert_eq!(frames[0].index, None);
+    vm.stack.push(Value::Num(frames.len() as isize));
+    Ok(())
+}
+
+#[test]
+fn testCallStack() {
+    let mut vm = bootStrapVM();
+    vm.makeNative(String::from("depth"), Box::new([]), stackDepth, Some(DataType::Int));
+    let eval = |vm: &mut VirtualMachine, input: &str| {
+        let tokens = tokenizeSource(input).unwrap();
+        let res = parseTokens(tokens).unwrap();
+        let bs = bytecodeGen2(res, &mut vm.functionReturns()).unwrap();
+        vm.lines = bs.2;
+        vm.eval(bs.0, bs.1)
+    };
+
+    // depth, three calls of f and root, tail calls would replace the frame
+    eval(&mut vm, "fn f(n: int): int { if n == 0 { return depth() + 0 } return f(n - 1) + 0 } assert(f(2), 5)").unwrap();
+    assert!(vm.callStack.is_empty());
+
+    let err = eval(&mut vm, "fn g(a: int): int { b = a + 1 return b / (a - a) }\nfn h(x: int): int { return g(x) + 1 }\nh(3)").unwrap_err();
+    assert_eq!(err, VmError::DivisionByZero);
+    let names = vm.backtrace.iter().map(|it| it.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["g", "h", "root"]);
+    assert_eq!(vm.backtrace[0].locals[0].getNum(), Ok(3));
+    assert_eq!(vm.backtrace[0].locals[1].getNum(), Ok(4));
+    assert!(vm.callStack.is_empty());
+
+    let msg = vm.describeError(&err);
+    assert!(msg.contains("in g\n    at 2:"));
+    assert!(msg.contains("in h\n    at 3:"));
+    assert!(msg.ends_with("in root"));
+}
+
+extern "C" fn thirdItem(vm: &mut VirtualMachine, frame: &mut StackFrame) {
+    let arr = (vm.nativeWrapper.getLocalsRef)(frame, 0);
+    let v = (vm.nativeWrapper.arrGetInt)(vm, unsafe { &*arr }, 2);
+    (vm.nativeWrapper.pushInt)(vm, v);
+}
+
+#[test]
+fn testExternErrors() {
+    let mut vm = bootStrapVM();
+    vm.makeExtern(
+        String::from("thirdItem"),
+        Box::new([DataType::arr(Generic::Type(DataType::Int)).into()]),
+        thirdItem,
+        Some(DataType::Int),
+    );
+    let eval = |vm: &mut VirtualMachine, input: &str| {
+        let tokens = tokenizeSource(input).unwrap();
+        let res = parseTokens(tokens).unwrap();
+        let bs = bytecodeGen2(res, &mut vm.functionReturns()).unwrap();
+        vm.eval(bs.0, bs.1)
+    };
+
+    assert_eq!(eval(&mut vm, "assert(thirdItem([1, 2, 3]), 3)"), Ok(()));
+    assert_eq!(eval(&mut vm, "print(thirdItem([1]))"), Err(VmError::IndexOutOfBounds { index: 2, len: 1 }));
+    assert_eq!(vm.pendingError, None);
+    assert_eq!(eval(&mut vm, "assert(thirdItem([4, 5, 6]), 6)"), Ok(()));
+}
+
+#[test]
+fn testDeepRecursion() {
+    let input = "fn down(n: int): int { if n == 0 { return 0 } return down(n - 1) + 1 } assert(down(200000), 200000)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+
+    let mut vm = bootStrapVM();
+    vm.eval(bs.0, bs.1).unwrap();
+    assert!(vm.callStack.is_empty());
+    assert!(vm.locals.is_empty());
+}
+
+#[test]
+fn testExecutionLimits() {
+    let run = |limits: Limits, input: &str| {
+        let tokens = tokenizeSource(input).unwrap();
+        let res = parseTokens(tokens).unwrap();
+        let bs = bytecodeGen(res).unwrap();
+        let mut vm = bootStrapVM();
+        vm.limits = limits;
+        let res = vm.eval(bs.0, bs.1);
+        assert!(vm.callStack.is_empty());
+        (res, vm.budget.executed())
+    };
+    let spin = "fn spin() { i = 0 while true { i += 1 } } spin()";
+
+    let (res, executed) = run(Limits { instructions: Some(10_000), ..Default::default() }, spin);
+    assert_eq!(res, Err(VmError::LimitExceeded(Limit::Instructions)));
+    assert_eq!(executed, 10_000);
+
+    let start = Instant::now();
+    let (res, _) = run(Limits { timeout: Some(Duration::from_millis(50)), ..Default::default() }, spin);
+    assert_eq!(res, Err(VmError::LimitExceeded(Limit::Timeout)));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let (res, _) = run(
+        Limits { callDepth: Some(100), ..Default::default() },
+        "fn fact(n: int): int { return n * fact(n - 1) * fact(n * 2) } fact(20)",
+    );
+    assert_eq!(res, Err(VmError::LimitExceeded(Limit::CallDepth)));
+
+    let (res, _) = run(
+        Limits { stackSize: Some(1000), ..Default::default() },
+        "fn down(n: int): int { return 1 + down(n - 1) } down(1)",
+    );
+    assert_eq!(res, Err(VmError::LimitExceeded(Limit::StackSize)));
+
+    let (res, _) = run(
+        Limits { heapObjects: Some(1000), ..Default::default() },
+        "fn fill() { xs = [[0]] i = 0 while true { push(xs, [i]) i += 1 } } fill()",
+    );
+    assert_eq!(res, Err(VmError::LimitExceeded(Limit::HeapObjects)));
+
+    let (res, _) = run(
+        Limits { heapBytes: Some(100_000), ..Default::default() },
+        "fn grow() { s = makeBuilder() while true { appendChar(s, 'a') } } grow()",
+    );
+    assert_eq!(res, Err(VmError::LimitExceeded(Limit::HeapBytes)));
+
+    // doubling reaches limit long before periodic check runs
+    let (res, executed) = run(
+        Limits { heapBytes: Some(1 << 20), ..Default::default() },
+        "fn grow() { s = \"ab\" while true { s = s + s } } grow()",
+    );
+    assert_eq!(res, Err(VmError::LimitExceeded(Limit::HeapBytes)));
+    assert!(executed < 1024);
+
+    // spawned isolate gets only what is left of the budget
+    let (res, _) = run(
+        Limits { instructions: Some(100_000), ..Default::default() },
+        "fn count(): int { i = 0 while i < 3000000 { i += 1 } return i } assert(join(spawn(count)), 3000000)",
+    );
+    assert_eq!(res, Err(VmError::LimitExceeded(Limit::Instructions)));
+
+    // blocking waits give up on deadline
+    let start = Instant::now();
+    let (res, _) = run(
+        Limits { timeout: Some(Duration::from_millis(50)), ..Default::default() },
+        "ch: Chan<int> = chan() recv(ch)",
+    );
+    assert_eq!(res, Err(VmError::LimitExceeded(Limit::Timeout)));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // temporary objects are freed so they do not count
+    let (res, _) = run(
+        Limits { heapObjects: Some(10), ..Default::default() },
+        "fn churn(): int { i = 0 while i < 10000 { xs = [i] i += 1 } return i } assert(churn(), 10000)",
+    );
+    assert_eq!(res, Ok(()));
+
+    let tokens = tokenizeSource(spin).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+    let mut vm = bootStrapVM();
+    let handle = vm.interruptHandle();
+    let stopper = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        handle.interrupt()
+    });
+    assert_eq!(vm.eval(bs.0, bs.1), Err(VmError::Interrupted));
+    stopper.join().unwrap();
+
+    // interrupt reaches both the joining vm and the isolate it waits for
+    let input = "fn spin() { i = 0 while true { i += 1 } } join(spawn(spin))";
+    let bs = bytecodeGen(parseTokens(tokenizeSource(input).unwrap()).unwrap()).unwrap();
+    let mut vm = bootStrapVM();
+    let handle = vm.interruptHandle();
+    let stopper = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        handle.interrupt()
+    });
+    assert_eq!(vm.eval(bs.0, bs.1), Err(VmError::Interrupted));
+    stopper.join().unwrap();
+}
+
+#[test]
+fn testCycleCollection() {
+    let typ = DataType::arr(Generic::Any);
+    let a = Value::makeArray(vec![], typ.clone());
+    let b = Value::makeArray(vec![a.clone()], typ.clone());
+    a.getObject().unwrap().getArr().unwrap().push(b.clone());
+    let freedA = Rc::downgrade(a.getObject().unwrap());
+
+    let kept = Value::makeArray(vec![], typ.clone());
+    kept.getObject().unwrap().getArr().unwrap().push(kept.clone());
+
+    drop(a);
+    drop(b);
+    assert!(freedA.upgrade().is_some());
+    assert_eq!(gc::collect(), 2);
+    assert!(freedA.upgrade().is_none());
+    assert_eq!(kept.getObject().unwrap().getArr().unwrap().len(), 1);
+    assert_eq!(gc::gcStats().live, 1);
+
+    // typed script code cannot build cycle, builtin still runs collection
+    let input = "xs = [[1]] assert(gc(), 0)";
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+    let mut vm = bootStrapVM();
+    vm.eval(bs.0, bs.1).unwrap();
+    assert_eq!(gc::gcStats().collections, 2);
+}
+
+#[test]
+fn testSizedArrays() {
+    let mut vm = bootStrapVM();
+    let mut eval = |input: &str| {
+        let tokens = tokenizeSource(input).unwrap();
+        let res = parseTokens(tokens).unwrap();
+        let bs = bytecodeGen(res).unwrap();
+        vm.eval(bs.0, bs.1)
+    };
+
+    assert_eq!(
+        eval("n = 3 xs = Array<int>(n + 1, 7) assert(arrayLen(xs), 4) assert(xs[3], 7) xs[3] = 1 assert(xs[3], 1) ys = Array<float>(0, 1.0) assert(arrayLen(ys), 0)"),
+        Ok(())
+    );
+    assert_eq!(eval("xs = [1] push(xs, 2) push(xs, 3) assert(arrayLen(xs), 3) assert(xs[2], 3)"), Ok(()));
+    assert_eq!(eval("xs = [1, 2] xs[2] = 3"), Err(VmError::IndexOutOfBounds { index: 2, len: 2 }));
+    assert_eq!(eval("xs = Array<int>(2, 0) xs[0 - 1] = 3"), Err(VmError::IndexOutOfBounds { index: -1, len: 2 }));
+    assert_eq!(eval("xs = [1, 2] print(xs[0 - 1])"), Err(VmError::IndexOutOfBounds { index: -1, len: 2 }));
+    assert!(matches!(eval("xs = Array<int>(0 - 1, 0)"), Err(VmError::InvalidOperation(_))));
+
+    assert!(matches!(eval("xs = Array<int>(1152921504606846975, 0)"), Err(VmError::InvalidOperation(_))));
+
+    let tokens = tokenizeSource("xs = Array<int>(2, true)").unwrap();
+    assert!(bytecodeGen(parseTokens(tokens).unwrap()).is_err());
+
+    // size is checked against heap limit before allocation
+    let mut vm = bootStrapVM();
+    vm.limits = Limits { heapBytes: Some(1 << 20), ..Default::default() };
+    for input in ["xs = Array<int>(1152921504606846975, 0)", "xs = Array<int>(1000000, 0)"] {
+        let bs = bytecodeGen(parseTokens(tokenizeSource(input).unwrap()).unwrap()).unwrap();
+        assert_eq!(vm.eval(bs.0, bs.1), Err(VmError::LimitExceeded(Limit::HeapBytes)));
+    }
+}
+
+#[test]
+fn testAliasing() {
+    let input = "fn set(xs: Array<int>) { xs[0] = 9 } ys = [1, 2] zs = ys set(zs) assert(ys[0], 9) push(zs, 3) assert(arrayLen(ys), 3) \
+        m = {1: 2} n = m n[1] = 5 assert(m[1], 5) \
+        fn lit(): String { return \"lit\" } a = lit() b = a a = a + \"!\" assert(strLen(b), 3) assert(strLen(lit()), 3) assert(strLen(a), 4) \
+        sb = makeBuilder() alias = sb appendChar(alias, 'x') s = buildString(sb) appendChar(sb, 'y') assert(strLen(s), 1) assert(strLen(buildString(alias)), 2)";
+
+    let tokens = tokenizeSource(input).unwrap();
+    let res = parseTokens(tokens).unwrap();
+    let bs = bytecodeGen(res).unwrap();
+    let mut vm = bootStrapVM();
+    assert_eq!(vm.eval(bs.0, bs.1), Ok(()));
+}
@@ -0,0 +1,626 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::ast::{
+    Expression, FunctionCall, FunctionDef, generatorType, mapTypes, Node, Op, resolveOverload, Statement,
+};
+use crate::lexer::Location;
+use crate::optimizer::evalExpr;
+use crate::parser::Operation;
+use crate::vm::{DataType, genFunNameMeta, MyStr, Value, ValueKind};
+
+#[derive(Debug)]
+pub struct SemanticError {
+    pub location: Location,
+    pub function: Option<String>,
+    pub msg: String,
+}
+
+impl Display for SemanticError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.function {
+            None => write!(f, "{}: {}", self.location, self.msg),
+            Some(fun) => write!(f, "{}: {} (in function {})", self.location, self.msg, fun),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SemanticErrors {
+    pub errors: Vec<SemanticError>,
+}
+
+impl Display for SemanticErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, e) in self.errors.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{e}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for SemanticErrors {}
+
+type Scope = HashMap<MyStr, (DataType, usize)>;
+
+// variables definitely assigned on current path, None when the path never gets here
+type Assigned = Option<HashSet<String>>;
+
+fn merge(a: Assigned, b: Assigned) -> Assigned {
+    match (a, b) {
+        (None, b) => b,
+        (a, None) => a,
+        (Some(a), Some(b)) => Some(a.intersection(&b).cloned().collect()),
+    }
+}
+
+fn readVars(e: &Expression, out: &mut Vec<String>) {
+    match e {
+        Expression::Variable(v) => out.push(v.clone()),
+        Expression::ArithmeticOp { left, right, .. } => {
+            readVars(left, out);
+            readVars(right, out)
+        }
+        Expression::FunctionCall(f) => {
+            // first argument of spawn names function not variable
+            let skip = if f.isSpawn() { 1 } else { 0 };
+            for arg in &f.arguments[skip..] {
+                readVars(arg, out)
+            }
+        }
+        Expression::ArrayLiteral(items) => {
+            for item in items {
+                readVars(item, out)
+            }
+        }
+        Expression::ArrayIndexing(a) => {
+            readVars(&a.expr, out);
+            readVars(&a.index, out)
+        }
+        Expression::NotExpression(i) => readVars(i, out),
+        Expression::MapLiteral(entries) => {
+            for (k, v) in entries {
+                readVars(k, out);
+                readVars(v, out)
+            }
+        }
+        Expression::Range { start, end } => {
+            readVars(start, out);
+            readVars(end, out)
+        }
+        Expression::SizedArray { size, fill, .. } => {
+            readVars(size, out);
+            readVars(fill, out)
+        }
+        _ => {}
+    }
+}
+
+// break inside nested loop exits only that loop
+fn containsBreak(body: &[Statement]) -> bool {
+    body.iter().any(|s| match s {
+        Statement::Break => true,
+        Statement::If(i) => {
+            containsBreak(&i.body) || i.elseBody.as_ref().is_some_and(|b| containsBreak(b))
+        }
+        _ => false,
+    })
+}
+
+fn isRange(t: &Option<DataType>) -> bool {
+    matches!(t, Some(DataType::Object(o)) if o.name.as_str() == "Range")
+}
+
+fn typeName(t: &Option<DataType>) -> &str {
+    t.as_ref().map_or("none", |it| it.toString())
+}
+
+struct Checker {
+    functionReturns: HashMap<MyStr, Option<DataType>>,
+    errors: Vec<SemanticError>,
+    location: Location,
+    function: Option<FunctionDef>,
+}
+
+impl Checker {
+    fn error(&mut self, msg: String) {
+        self.errors.push(SemanticError {
+            location: self.location,
+            function: self.function.as_ref().map(|it| it.name.clone()),
+            msg,
+        })
+    }
+
+    fn report<T>(&mut self, res: Result<T, Box<dyn Error>>) -> Option<T> {
+        match res {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.error(e.to_string());
+                None
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: &DataType, actual: &Option<DataType>, what: &str) {
+        if actual.as_ref() != Some(expected) {
+            self.error(format!(
+                "{} must be {} got {}",
+                what,
+                expected.toString(),
+                typeName(actual)
+            ))
+        }
+    }
+
+    // None means error was already reported for this expression
+    fn exprType(
+        &mut self,
+        e: &Expression,
+        scope: &Scope,
+        typeHint: Option<DataType>,
+    ) -> Option<Option<DataType>> {
+        match e {
+            Expression::FunctionCall(f) => return self.callType(f, scope, typeHint),
+            Expression::Variable(name) => {
+                return match scope.get(&MyStr::from(name.clone())) {
+                    None => {
+                        self.error(format!("variable {name} is not defined"));
+                        None
+                    }
+                    Some((t, _)) => Some(Some(t.clone())),
+                };
+            }
+            Expression::ArithmeticOp { left, right, .. } => {
+                let l = self.exprType(left, scope, None);
+                let r = self.exprType(right, scope, None);
+                l?;
+                r?;
+            }
+            Expression::ArrayLiteral(items) => {
+                let mut first: Option<Option<DataType>> = None;
+                for item in items {
+                    let t = self.exprType(item, scope, None)?;
+                    match &first {
+                        None => first = Some(t),
+                        Some(f) if *f != t => {
+                            self.error(format!(
+                                "array elements must have same type expected {} got {}",
+                                typeName(f),
+                                typeName(&t)
+                            ));
+                            return None;
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+            Expression::MapLiteral(entries) => {
+                let mut first: Option<(Option<DataType>, Option<DataType>)> = None;
+                for (k, v) in entries {
+                    let key = self.exprType(k, scope, None)?;
+                    let value = self.exprType(v, scope, None)?;
+                    match &first {
+                        None => first = Some((key, value)),
+                        Some((f, _)) if *f != key => {
+                            self.error(format!(
+                                "map keys must have same type expected {} got {}",
+                                typeName(f),
+                                typeName(&key)
+                            ));
+                            return None;
+                        }
+                        Some((_, f)) if *f != value => {
+                            self.error(format!(
+                                "map values must have same type expected {} got {}",
+                                typeName(f),
+                                typeName(&value)
+                            ));
+                            return None;
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+            Expression::ArrayIndexing(a) => {
+                let target = self.exprType(&a.expr, scope, None)?;
+                let index = self.exprType(&a.index, scope, None)?;
+                // maps are indexed by their key, everything else by int or range when slicing
+                let expected = match &target {
+                    Some(t @ DataType::Object(o)) if o.name.as_str() == "Map" => mapTypes(t).map(|it| it.0),
+                    Some(_) if isRange(&index) => None,
+                    Some(_) => Some(DataType::Int),
+                    None => None,
+                };
+                if let Some(expected) = expected {
+                    if index.as_ref() != Some(&expected) {
+                        self.expect(&expected, &index, "index");
+                        return None;
+                    }
+                }
+            }
+            Expression::NotExpression(i) => {
+                self.exprType(i, scope, None)?;
+            }
+            Expression::Range { start, end } => {
+                let s = self.exprType(start, scope, None);
+                let e = self.exprType(end, scope, None);
+                s?;
+                e?;
+            }
+            Expression::SizedArray { typ, size, fill } => {
+                let s = self.exprType(size, scope, None);
+                let f = self.exprType(fill, scope, Some(typ.clone()));
+                s?;
+                f?;
+            }
+            _ => {}
+        }
+
+        let res = e.toDataType(scope, &self.functionReturns, typeHint);
+        self.report(res)
+    }
+
+    fn argTypes(&mut self, args: &[Expression], scope: &Scope) -> Option<Vec<DataType>> {
+        let mut types = vec![];
+        let mut ok = true;
+        for arg in args {
+            match self.exprType(arg, scope, None) {
+                None => ok = false,
+                Some(None) => {
+                    self.error("function argument must have value".to_string());
+                    ok = false
+                }
+                Some(Some(t)) => types.push(t),
+            }
+        }
+        if ok {
+            Some(types)
+        } else {
+            None
+        }
+    }
+
+    fn callType(
+        &mut self,
+        f: &FunctionCall,
+        scope: &Scope,
+        typeHint: Option<DataType>,
+    ) -> Option<Option<DataType>> {
+        if f.isSpawn() {
+            let types = self.argTypes(&f.arguments[1..], scope)?;
+            let name = match &f.arguments[0] {
+                Expression::Variable(v) => v.clone(),
+                _ => unreachable!(),
+            };
+            let ret = self.resolve(&name, &types)?;
+            return Some(Some(DataType::task(ret)));
+        }
+
+        let types = self.argTypes(&f.arguments, scope)?;
+
+        let builtin = f.builtin(&types, typeHint.as_ref());
+        if let Some((_, ret)) = self.report(builtin)? {
+            return Some(ret);
+        }
+
+        self.resolve(f.name.as_str(), &types)
+    }
+
+    fn resolve(&mut self, name: &str, types: &[DataType]) -> Option<Option<DataType>> {
+        let res = resolveOverload(name, types, &self.functionReturns);
+        self.report(res).map(|it| it.returnType)
+    }
+
+    fn checkBody(&mut self, body: &[Statement], scope: &mut Scope) {
+        for s in body {
+            self.checkStatement(s, scope)
+        }
+    }
+
+    fn checkStatement(&mut self, s: &Statement, scope: &mut Scope) {
+        match s {
+            Statement::Location(l) => self.location = *l,
+            Statement::FunctionExpr(f) => {
+                self.callType(f, scope, None);
+            }
+            Statement::Variable(v) => {
+                let init = match &v.init {
+                    None => return,
+                    Some(i) => i,
+                };
+                let t = match self.exprType(init, scope, v.typeHint.clone()) {
+                    None => None,
+                    Some(None) => {
+                        self.error(format!("cannot assign none to variable {}", v.name));
+                        None
+                    }
+                    Some(Some(t)) => Some(t),
+                };
+                if let (Some(hint), Some(t)) = (&v.typeHint, &t) {
+                    if hint != t {
+                        self.error(format!(
+                            "cannot assign {} to variable {} of type {}",
+                            t.toString(),
+                            v.name,
+                            hint.toString()
+                        ))
+                    }
+                }
+                // keep variable defined even after error so its uses dont cascade
+                if let Some(t) = t.or(v.typeHint.clone()) {
+                    scope.insert(MyStr::from(v.name.clone()), (t, 0));
+                }
+            }
+            Statement::VariableMod(m) => {
+                let typ = match scope.get(&MyStr::from(m.varName.clone())) {
+                    None => {
+                        self.error(format!("assignment to undeclared variable {}", m.varName));
+                        return;
+                    }
+                    Some((t, _)) => t.clone(),
+                };
+                let op = match m.modType {
+                    crate::ast::ModType::Add => Op::Add,
+                    crate::ast::ModType::Sub => Op::Sub,
+                    crate::ast::ModType::Div => Op::Div,
+                    crate::ast::ModType::Mul => Op::Mul,
+                };
+                let e = Expression::ArithmeticOp {
+                    left: Box::new(Expression::Variable(m.varName.clone())),
+                    right: Box::new(m.expr.clone()),
+                    op,
+                };
+                if let Some(t) = self.exprType(&e, scope, None) {
+                    self.expect(&typ, &t, &format!("value assigned to {}", m.varName));
+                }
+            }
+            Statement::ArrayAssign { left, right } => {
+                let e = Expression::ArrayIndexing(Box::new(left.clone()));
+                let target = self.exprType(&e, scope, None);
+                let value = self.exprType(right, scope, None);
+                if let (Some(Some(t)), Some(v)) = (target, value) {
+                    self.expect(&t, &v, "assigned value");
+                }
+            }
+            Statement::While(w) => {
+                if let Some(t) = self.exprType(&w.exp, scope, None) {
+                    self.expect(&DataType::Bool, &t, "condition");
+                }
+                self.checkBody(&w.body, scope)
+            }
+            Statement::If(i) => {
+                if let Some(t) = self.exprType(&i.condition, scope, None) {
+                    self.expect(&DataType::Bool, &t, "condition");
+                }
+                self.checkBody(&i.body, scope);
+                if let Some(b) = &i.elseBody {
+                    self.checkBody(b, scope)
+                }
+            }
+            Statement::Return(r) => {
+                let t = match self.exprType(&r.exp, scope, None) {
+                    None => return,
+                    Some(t) => t,
+                };
+                let ret = match &self.function {
+                    None => return,
+                    Some(f) => f.returnType.clone(),
+                };
+                match ret {
+                    None => self.error("function without return type cannot return value".to_string()),
+                    Some(r) if generatorType(&r).is_some() => {
+                        self.error("generator cannot return value".to_string())
+                    }
+                    Some(r) => self.expect(&r, &t, "returned value"),
+                }
+            }
+            Statement::Loop(body) => self.checkBody(body, scope),
+            Statement::ForLoop(f) => {
+                if self.exprType(&f.iterable, scope, None).is_some() {
+                    let res = f.elementType(scope, &self.functionReturns);
+                    if let Some(t) = self.report(res) {
+                        scope.insert(MyStr::from(f.varName.clone()), (t, 0));
+                    }
+                }
+                self.checkBody(&f.body, scope)
+            }
+            Statement::Yield(e) => {
+                let t = match self.exprType(e, scope, None) {
+                    None => return,
+                    Some(t) => t,
+                };
+                let gen = self
+                    .function
+                    .as_ref()
+                    .and_then(|it| it.returnType.as_ref())
+                    .and_then(generatorType);
+                match gen {
+                    None => self.error("yield outside of generator function".to_string()),
+                    Some(g) => self.expect(&g, &t, "yielded value"),
+                }
+            }
+            Statement::Continue => {}
+            Statement::Break => {}
+        }
+    }
+
+    fn checkReads(&mut self, e: &Expression, assigned: &HashSet<String>, scope: &Scope) {
+        let mut vars = vec![];
+        readVars(e, &mut vars);
+        for v in vars {
+            // undefined variables are already reported by type checking
+            if !assigned.contains(&v) && scope.contains_key(&MyStr::from(v.clone())) {
+                self.error(format!("variable {v} might not be assigned"));
+            }
+        }
+    }
+
+    fn flowBody(&mut self, body: &[Statement], assigned: HashSet<String>, scope: &Scope) -> Assigned {
+        let mut assigned = Some(assigned);
+        for s in body {
+            if let Statement::Location(l) = s {
+                self.location = *l;
+                continue;
+            }
+            match assigned {
+                None => {
+                    self.error("unreachable code".to_string());
+                    break;
+                }
+                Some(a) => assigned = self.flowStatement(s, a, scope),
+            }
+        }
+        assigned
+    }
+
+    fn flowStatement(&mut self, s: &Statement, mut assigned: HashSet<String>, scope: &Scope) -> Assigned {
+        match s {
+            Statement::Location(l) => self.location = *l,
+            Statement::FunctionExpr(f) => {
+                self.checkReads(&Expression::FunctionCall(f.clone()), &assigned, scope)
+            }
+            Statement::Variable(v) => {
+                if let Some(init) = &v.init {
+                    self.checkReads(init, &assigned, scope);
+                }
+                assigned.insert(v.name.clone());
+            }
+            Statement::VariableMod(m) => {
+                self.checkReads(&Expression::Variable(m.varName.clone()), &assigned, scope);
+                self.checkReads(&m.expr, &assigned, scope);
+            }
+            Statement::ArrayAssign { left, right } => {
+                self.checkReads(&left.expr, &assigned, scope);
+                self.checkReads(&left.index, &assigned, scope);
+                self.checkReads(right, &assigned, scope);
+            }
+            Statement::While(w) => {
+                self.checkReads(&w.exp, &assigned, scope);
+                self.flowBody(&w.body, assigned.clone(), scope);
+                let forever = matches!(evalExpr(&w.exp).as_ref().map(Value::kind), Some(ValueKind::Bol(true)));
+                if forever && !containsBreak(&w.body) {
+                    return None;
+                }
+            }
+            Statement::If(i) => {
+                self.checkReads(&i.condition, &assigned, scope);
+                let body = self.flowBody(&i.body, assigned.clone(), scope);
+                let elseBody = match &i.elseBody {
+                    None => Some(assigned),
+                    Some(b) => self.flowBody(b, assigned, scope),
+                };
+                return merge(body, elseBody);
+            }
+            Statement::Return(r) => {
+                self.checkReads(&r.exp, &assigned, scope);
+                return None;
+            }
+            Statement::Loop(body) => {
+                self.flowBody(body, assigned.clone(), scope);
+                if !containsBreak(body) {
+                    return None;
+                }
+            }
+            Statement::ForLoop(f) => {
+                self.checkReads(&f.iterable, &assigned, scope);
+                let mut inner = assigned.clone();
+                inner.insert(f.varName.clone());
+                self.flowBody(&f.body, inner, scope);
+            }
+            Statement::Yield(e) => self.checkReads(e, &assigned, scope),
+            Statement::Continue | Statement::Break => return None,
+        }
+        Some(assigned)
+    }
+}
+
+// checks whole program before codegen and reports every error found instead of just the first one
+pub fn checkProgram(
+    operations: &[Operation],
+    functionReturns: &HashMap<MyStr, Option<DataType>>,
+    mainLocals: &Scope,
+) -> Result<(), Box<dyn Error>> {
+    let mut checker = Checker {
+        functionReturns: functionReturns.clone(),
+        errors: vec![],
+        location: Location::default(),
+        function: None,
+    };
+
+    for op in operations {
+        if let Operation::Global(Node::FunctionDef(f)) = op {
+            checker.functionReturns.insert(
+                MyStr::from(genFunNameMeta(f.name.as_str(), &f.args, f.argCount)),
+                f.returnType.clone(),
+            );
+        }
+    }
+
+    let mut scope = mainLocals.clone();
+
+    for op in operations {
+        match op {
+            Operation::Statement(s) => checker.checkStatement(s, &mut scope),
+            Operation::Expr(e) => {
+                checker.exprType(e, &scope, None);
+            }
+            Operation::Global(Node::FunctionDef(f)) => {
+                let mut locals = Scope::new();
+                for arg in &f.args[..f.argCount] {
+                    locals.insert(arg.name.clone(), (arg.typ.clone(), 0));
+                }
+                let location = checker.location;
+                checker.function = Some(f.clone());
+                checker.checkBody(&f.body, &mut locals);
+
+                let args = f.args[..f.argCount].iter().map(|it| it.name.to_string()).collect();
+                let end = checker.flowBody(&f.body, args, &locals);
+                if let Some(r) = &f.returnType {
+                    if end.is_some() && generatorType(r).is_none() {
+                        checker.location = location;
+                        checker.error(format!("missing return of {} at end of function", r.toString()));
+                    }
+                }
+                checker.function = None;
+                checker.location = location;
+            }
+            Operation::Global(Node::StructDef(_)) => {}
+        }
+    }
+
+    checker.location = Location::default();
+    let mut assigned: Assigned = Some(mainLocals.keys().map(|it| it.to_string()).collect());
+    for op in operations {
+        match op {
+            Operation::Statement(Statement::Location(l)) => checker.location = *l,
+            Operation::Statement(s) => match assigned {
+                None => {
+                    checker.error("unreachable code".to_string());
+                    break;
+                }
+                Some(a) => assigned = checker.flowStatement(s, a, &scope),
+            },
+            Operation::Expr(e) => {
+                if let Some(a) = &assigned {
+                    checker.checkReads(e, a, &scope)
+                }
+            }
+            Operation::Global(_) => {}
+        }
+    }
+
+    // flow errors are found in separate pass, keep report in source order
+    checker.errors.sort_by_key(|it| (it.location.line, it.location.column));
+
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Box::new(SemanticErrors {
+            errors: checker.errors,
+        }))
+    }
+}
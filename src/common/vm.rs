@@ -1,10 +1,13 @@
-use std::{ptr, rc};
+use std::{mem, ptr, rc};
 use std::borrow::BorrowMut;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
-use std::mem::{forget, transmute};
+use std::marker::PhantomData;
+use std::mem::{ManuallyDrop, size_of, transmute, transmute_copy};
 use std::rc::Rc;
 use std::thread::sleep;
 use std::time::Duration;
@@ -13,14 +16,17 @@ use libloading::{Library, Symbol};
 
 use crate::ast::{Expression, Op};
 use crate::ffi::NativeWrapper;
-use crate::objects::{ObjectDefinition, Str, ViplObject};
+use crate::isolate::{Channel, SendValue};
+use crate::limits::{Budget, InterruptHandle, Limit, Limits};
+use crate::lineTable::LineTable;
+use crate::objects::{Generator, GeneratorState, heapResize, MapKey, ObjectDefinition, Str, ViplObject};
 use crate::parser::Operation::Expr;
 use crate::parser::parseDataType;
 use crate::std::bootStrapVM;
 use crate::vm::DataType::*;
 use crate::vm::FuncType::*;
 use crate::vm::OpCode::*;
-use crate::vm::Value::*;
+use crate::vmError::VmError;
 
 #[derive(Debug, Clone, Eq)]
 pub enum MyStr {
@@ -107,28 +113,28 @@ impl From<DataType> for Value {
 impl From<isize> for Value {
     #[inline]
     fn from(val: isize) -> Self {
-        Num(val)
+        Value::Num(val)
     }
 }
 
 impl From<char> for Value {
     #[inline]
     fn from(val: char) -> Self {
-        Chr(val)
+        Value::Chr(val)
     }
 }
 
 impl From<f32> for Value {
     #[inline]
     fn from(val: f32) -> Self {
-        Flo(val)
+        Value::Flo(val)
     }
 }
 
 impl From<bool> for Value {
     #[inline]
     fn from(val: bool) -> Self {
-        Bol(val)
+        Value::Bol(val)
     }
 }
 
@@ -139,12 +145,49 @@ impl DataType {
             generics: Box::new([]),
         })
     }
+    pub fn builder() -> Self {
+        Object(ObjectMeta {
+            name: MyStr::Static("StringBuilder"),
+            generics: Box::new([]),
+        })
+    }
     pub fn arr(inner: Generic) -> Self {
         Object(ObjectMeta {
             name: MyStr::Static("Array"),
             generics: Box::new([inner]),
         })
     }
+    pub fn map(key: Generic, value: Generic) -> Self {
+        Object(ObjectMeta {
+            name: MyStr::Static("Map"),
+            generics: Box::new([key, value]),
+        })
+    }
+    pub fn generator(inner: Generic) -> Self {
+        Object(ObjectMeta {
+            name: MyStr::Static("Generator"),
+            generics: Box::new([inner]),
+        })
+    }
+    pub fn chan(inner: Generic) -> Self {
+        Object(ObjectMeta {
+            name: MyStr::Static("Chan"),
+            generics: Box::new([inner]),
+        })
+    }
+    // task of void function has no generic
+    pub fn task(ret: Option<DataType>) -> Self {
+        Object(ObjectMeta {
+            name: MyStr::Static("Task"),
+            generics: ret.into_iter().map(Generic::Type).collect(),
+        })
+    }
+    pub fn range() -> Self {
+        Object(ObjectMeta {
+            name: MyStr::Static("Range"),
+            generics: Box::new([]),
+        })
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -205,26 +248,51 @@ impl DataType {
     #[inline]
     pub fn toDefaultValue(&self) -> Value {
         match self {
-            Int => Num(0),
-            Float => Flo(0.),
-            Bool => Bol(false),
-            Object { .. } => Reference { instance: None },
-            Char => Chr(0u8 as char),
+            Int => Value::Num(0),
+            Float => Value::Flo(0.),
+            Bool => Value::Bol(false),
+            Object { .. } => Value::null(),
+            Char => Value::Chr(0u8 as char),
         }
     }
 }
 
-#[derive(Clone, Debug)]
-pub enum JmpType {
+// linked code keeps index of the compared type instead of the type itself
+#[derive(Clone, Copy, Debug)]
+pub enum JmpType<T = DataType> {
     One,
     Zero,
     Jmp,
-    Gt,
-    Less,
+    // pop two values and jump when left op right holds
+    // le, ge and ne are negations of gt, lt and eq so NaN takes the branch unfused code would
+    Lt(T),
+    Le(T),
+    Gt(T),
+    Ge(T),
+    Eq(T),
+    Ne(T),
     True,
     False,
 }
 
+impl<T> JmpType<T> {
+    pub fn tryMap<U, E>(&self, f: impl FnOnce(&T) -> Result<U, E>) -> Result<JmpType<U>, E> {
+        Ok(match self {
+            JmpType::One => JmpType::One,
+            JmpType::Zero => JmpType::Zero,
+            JmpType::Jmp => JmpType::Jmp,
+            JmpType::Lt(t) => JmpType::Lt(f(t)?),
+            JmpType::Le(t) => JmpType::Le(f(t)?),
+            JmpType::Gt(t) => JmpType::Gt(f(t)?),
+            JmpType::Ge(t) => JmpType::Ge(f(t)?),
+            JmpType::Eq(t) => JmpType::Eq(f(t)?),
+            JmpType::Ne(t) => JmpType::Ne(f(t)?),
+            JmpType::True => JmpType::True,
+            JmpType::False => JmpType::False,
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 #[repr(C)]
 pub struct VariableMetadata {
@@ -276,6 +344,7 @@ pub enum OpCode {
     FunEnd,
     F2I,
     I2F,
+    C2I,
     PushInt(isize),
     PushFloat(f32),
     PushBool(bool),
@@ -296,6 +365,10 @@ pub enum OpCode {
     Call {
         encoded: MyStr,
     },
+    // call in return position, reuses the frame of the returning function
+    TailCall {
+        encoded: MyStr,
+    },
     Return,
 
     Add(DataType),
@@ -335,6 +408,8 @@ pub enum OpCode {
     ArrayStore(DataType),
     ArrayLoad(DataType),
     ArrayLength,
+    ArrayFill(DataType),
+    ArrayPush(DataType),
     Inc {
         typ: DataType,
         index: usize,
@@ -345,11 +420,49 @@ pub enum OpCode {
     },
     StrNew(MyStr),
     GetChar,
+    MapNew {
+        key: DataType,
+        value: DataType,
+    },
+    MapStore(DataType),
+    MapLoad(DataType),
+    MapContains,
+    MapRemove,
+    MapKeys(DataType),
+    RangeNew,
+    ArraySlice(DataType),
+    StrSlice,
+    GenNew(DataType),
+    Yield,
+    GenResume,
+    GenValue(DataType),
+    Spawn {
+        encoded: MyStr,
+        argCount: usize,
+    },
+    Join,
+    ChanNew(DataType),
+    ChanSend,
+    ChanRecv(DataType),
+}
+
+// discriminant of OpCode without operands, first byte of every encoded instruction
+// variants are listed once so that decoding cannot miss new ones
+macro_rules! rawOpCodes {
+    ($($name:ident),* $(,)?) => {
+        #[repr(u8)]
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum RawOpCode {
+            $($name),*
+        }
+
+        impl RawOpCode {
+            const ALL: &'static [RawOpCode] = &[$(RawOpCode::$name),*];
+        }
+    };
 }
 
-#[repr(C)]
-#[derive(Debug)]
-pub enum RawOpCode {
+rawOpCodes! {
     FunBegin,
     FunName,
     FunReturn,
@@ -357,9 +470,11 @@ pub enum RawOpCode {
     FunEnd,
     F2I,
     I2F,
+    C2I,
     PushInt,
     PushFloat,
     PushBool,
+    PushChar,
     Pop,
     Dup,
     PushLocal,
@@ -371,15 +486,12 @@ pub enum RawOpCode {
     Sub,
     Div,
     Mul,
-
     Equals,
     Greater,
     Less,
-
     Or,
     And,
     Not,
-
     ClassBegin,
     ClassName,
     ClassField,
@@ -387,13 +499,133 @@ pub enum RawOpCode {
     New,
     GetField,
     SetField,
-
     ArrayNew,
     ArrayStore,
     ArrayLoad,
     ArrayLength,
     Inc,
     Dec,
+    StrNew,
+    GetChar,
+    MapNew,
+    MapStore,
+    MapLoad,
+    MapContains,
+    MapRemove,
+    MapKeys,
+    RangeNew,
+    ArraySlice,
+    StrSlice,
+    GenNew,
+    Yield,
+    GenResume,
+    GenValue,
+    Spawn,
+    Join,
+    ChanNew,
+    ChanSend,
+    ChanRecv,
+    TailCall,
+    ArrayFill,
+    ArrayPush,
+}
+
+impl TryFrom<u8> for RawOpCode {
+    type Error = u8;
+
+    fn try_from(code: u8) -> Result<Self, u8> {
+        RawOpCode::ALL.get(code as usize).copied().ok_or(code)
+    }
+}
+
+// executable form of OpCode made by link, it only exists in memory
+// types are indexes into VirtualMachine::types so that every instruction is 16 bytes
+#[derive(Debug, Clone, Copy)]
+pub enum LinkedOp {
+    FunBegin,
+    FunEnd,
+    // function and class declarations, link reads them and they are never executed
+    Declaration(RawOpCode),
+    F2I,
+    I2F,
+    C2I,
+    PushInt(isize),
+    PushFloat(f32),
+    PushBool(bool),
+    PushChar(char),
+    Pop,
+    Dup,
+    PushLocal {
+        index: usize,
+    },
+    SetLocal {
+        index: usize,
+    },
+    Jmp {
+        offset: isize,
+        jmpType: JmpType<u32>,
+    },
+    // index into VirtualMachine::functions
+    Call {
+        index: usize,
+    },
+    TailCall {
+        index: usize,
+    },
+    Return,
+    Add(u32),
+    Sub(u32),
+    Div(u32),
+    Mul(u32),
+    Equals(u32),
+    Greater(u32),
+    Less(u32),
+    Or,
+    And,
+    Not,
+    ArrayNew(u32),
+    ArrayStore,
+    ArrayLoad,
+    ArrayLength,
+    ArrayFill(u32),
+    ArrayPush,
+    Inc {
+        typ: u32,
+        index: u32,
+    },
+    Dec {
+        typ: u32,
+        index: u32,
+    },
+    // index into VirtualMachine::constants
+    StrNew {
+        index: usize,
+    },
+    GetChar,
+    MapNew {
+        key: u32,
+        value: u32,
+    },
+    MapStore,
+    MapLoad,
+    MapContains,
+    MapRemove,
+    MapKeys(u32),
+    RangeNew,
+    ArraySlice(u32),
+    StrSlice,
+    GenNew(u32),
+    Yield,
+    GenResume,
+    GenValue,
+    Spawn {
+        index: u32,
+        argCount: u32,
+    },
+    Join,
+    ChanNew(u32),
+    ChanSend,
+    ChanRecv,
 }
 
 pub struct MyObjectField {
@@ -425,91 +657,245 @@ pub struct MyClass {
     pub fields: HashMap<String, MyClassField>,
 }
 
+const TAG_BITS: u64 = 3;
+const TAG_MASK: u64 = (1 << TAG_BITS) - 1;
+
+// range of ints that fit into payload, results outside of it are overflow
+pub const INT_MAX: isize = isize::MAX >> TAG_BITS;
+pub const INT_MIN: isize = isize::MIN >> TAG_BITS;
+const OBJECT: u64 = 0;
+const INT: u64 = 1;
+const FLOAT: u64 = 2;
+const BOOL: u64 = 3;
+const CHAR: u64 = 4;
+
+// object word is the Option<Rc> itself so references can be borrowed straight from it
+const _: () = assert!(size_of::<Option<Rc<ViplObject>>>() == size_of::<u64>());
+
+// tagged 64 bit word, low 3 bits are the tag and the rest is payload
+// rc pointers are 8 aligned so their tag is always OBJECT, ints are 61 bit
 // TODO maybe null still retains object info
-#[derive(Clone, Debug)]
-#[repr(C)]
-pub enum Value {
+#[repr(transparent)]
+pub struct Value(u64, PhantomData<Rc<ViplObject>>);
+
+// unpacked value for matching on its type
+#[derive(Debug)]
+pub enum ValueKind<'a> {
     Num(isize),
     Flo(f32),
     Bol(bool),
     Chr(char),
-    Reference { instance: Option<Rc<ViplObject>> },
+    Reference(&'a Option<Rc<ViplObject>>),
 }
 
+#[allow(non_snake_case)]
 impl Value {
+    #[inline]
+    const fn tagged(payload: u64, tag: u64) -> Value {
+        Value((payload << TAG_BITS) | tag, PhantomData)
+    }
+
+    #[inline]
+    pub const fn Num(v: isize) -> Value {
+        Self::tagged(v as u64, INT)
+    }
+
+    #[inline]
+    pub fn checkedNum(v: isize) -> Result<Value, VmError> {
+        if (INT_MIN..=INT_MAX).contains(&v) {
+            Ok(Value::Num(v))
+        } else {
+            Err(VmError::IntegerOverflow)
+        }
+    }
+
+    #[inline]
+    pub fn Flo(v: f32) -> Value {
+        Self::tagged(v.to_bits() as u64, FLOAT)
+    }
+
+    #[inline]
+    pub const fn Bol(v: bool) -> Value {
+        Self::tagged(v as u64, BOOL)
+    }
+
+    #[inline]
+    pub const fn Chr(v: char) -> Value {
+        Self::tagged(v as u64, CHAR)
+    }
+
+    #[inline]
+    pub fn Reference(instance: Option<Rc<ViplObject>>) -> Value {
+        Value(unsafe { transmute::<Option<Rc<ViplObject>>, u64>(instance) }, PhantomData)
+    }
+
+    #[inline]
+    pub const fn null() -> Value {
+        Value(0, PhantomData)
+    }
+
+    #[inline]
+    fn tag(&self) -> u64 {
+        self.0 & TAG_MASK
+    }
+
+    #[inline]
+    fn payload(&self) -> u64 {
+        self.0 >> TAG_BITS
+    }
+
+    #[inline]
+    pub fn kind(&self) -> ValueKind<'_> {
+        unsafe {
+            match self.tag() {
+                INT => ValueKind::Num((self.0 as isize) >> TAG_BITS),
+                FLOAT => ValueKind::Flo(f32::from_bits(self.payload() as u32)),
+                BOOL => ValueKind::Bol(self.payload() != 0),
+                CHAR => ValueKind::Chr(char::from_u32_unchecked(self.payload() as u32)),
+                _ => ValueKind::Reference(self.reference()),
+            }
+        }
+    }
+
     #[inline]
     pub fn tryValueAsFloat(&self) -> Option<f32> {
-        match self {
-            Num(v) => Some(*v as f32),
-            Flo(v) => Some(*v),
-            Bol(v) => Some(*v as isize as f32),
-            Chr(v) => Some(*v as isize as f32),
-            Reference { .. } => None,
+        match self.kind() {
+            ValueKind::Num(v) => Some(v as f32),
+            ValueKind::Flo(v) => Some(v),
+            ValueKind::Bol(v) => Some(v as isize as f32),
+            ValueKind::Chr(v) => Some(v as isize as f32),
+            ValueKind::Reference(_) => None,
+        }
+    }
+}
+
+impl Clone for Value {
+    #[inline]
+    fn clone(&self) -> Self {
+        if self.tag() == OBJECT {
+            Value::Reference(unsafe { self.reference() }.clone())
+        } else {
+            Value(self.0, PhantomData)
+        }
+    }
+}
+
+impl Drop for Value {
+    #[inline]
+    fn drop(&mut self) {
+        if self.tag() == OBJECT {
+            unsafe { ptr::drop_in_place(self.mutReference()) }
+        }
+    }
+}
+
+impl Debug for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.kind() {
+            ValueKind::Reference(instance) => f.debug_struct("Reference").field("instance", instance).finish(),
+            k => write!(f, "{k:?}"),
         }
     }
 }
 
 impl Into<Expression> for Value {
     fn into(self) -> Expression {
-        match self {
-            Num(it) => Expression::IntLiteral(format!("{}", it)),
-            Flo(it) => Expression::FloatLiteral(format!("{}", it)),
-            Bol(it) => Expression::BoolLiteral(it),
-            Chr(it) => Expression::CharLiteral(it),
-            Reference { .. } => panic!(),
+        match self.kind() {
+            ValueKind::Num(it) => Expression::IntLiteral(format!("{}", it)),
+            ValueKind::Flo(it) => Expression::FloatLiteral(format!("{}", it)),
+            ValueKind::Bol(it) => Expression::BoolLiteral(it),
+            ValueKind::Chr(it) => Expression::CharLiteral(it),
+            ValueKind::Reference(_) => panic!(),
         }
     }
 }
 
 impl Value {
     #[inline]
-    pub fn getString(&self) -> &String {
-        match self {
-            Reference { instance } => match instance {
-                None => panic!(),
-                Some(v) => &v.getStr().string,
-            },
-            e => panic!("{e:?}"),
+    pub fn getString(&self) -> Result<&String, VmError> {
+        Ok(&self.getObject()?.getStr()?.string)
+    }
+
+    pub fn typeName(&self) -> String {
+        match self.kind() {
+            ValueKind::Num(_) => Int.toString().to_string(),
+            ValueKind::Flo(_) => Float.toString().to_string(),
+            ValueKind::Bol(_) => Bool.toString().to_string(),
+            ValueKind::Chr(_) => Char.toString().to_string(),
+            ValueKind::Reference(None) => String::from("null"),
+            ValueKind::Reference(Some(v)) => v.asObj().getName(),
         }
     }
 
     #[inline]
     pub fn makeString(str: String) -> Value {
-        Reference {
-            instance: Some(Rc::new(Str { string: str }.into())),
-        }
+        Value::Reference(Some(ViplObject::alloc(Str { string: str }.into())))
     }
 
     #[inline]
     pub fn makeObject(obj: Box<dyn crate::objects::Object>) -> Value {
-        Reference {
-            instance: Some(Rc::from(ViplObject::Runtime(obj))),
-        }
+        Value::Reference(Some(ViplObject::Runtime(obj).alloc()))
     }
 
     #[inline]
     pub fn makeArray(arr: Vec<Value>, typ: DataType) -> Value {
-        Reference {
-            instance: Some(Rc::new(crate::objects::Array { internal: arr, typ }.into())),
-        }
+        Value::Reference(Some(ViplObject::alloc(crate::objects::Array::new(arr, typ).into())))
+    }
+
+    #[inline]
+    pub fn makeMap(map: HashMap<MapKey, Value>, keyTyp: DataType, valueTyp: DataType) -> Value {
+        Value::Reference(Some(ViplObject::alloc(
+            crate::objects::Map {
+                internal: RefCell::new(map),
+                keyTyp,
+                valueTyp,
+            }
+                .into(),
+        )))
+    }
+
+    #[inline]
+    pub fn makeChannel(chan: Channel) -> Value {
+        Value::Reference(Some(ViplObject::alloc(chan.into())))
+    }
+
+    #[inline]
+    pub fn makeRange(start: isize, end: isize) -> Value {
+        Value::Reference(Some(ViplObject::alloc(crate::objects::Range { start, end }.into())))
     }
 
     #[inline]
     pub fn valueStr(&self) -> String {
-        match self {
-            Num(it) => format!("{it}"),
-            Flo(it) => format!("{it}"),
-            Bol(it) => format!("{it}"),
-            Chr(it) => format!("{it}"),
-            Reference { instance } => match instance {
+        match self.kind() {
+            ValueKind::Num(it) => format!("{it}"),
+            ValueKind::Flo(it) => format!("{it}"),
+            ValueKind::Bol(it) => format!("{it}"),
+            ValueKind::Chr(it) => format!("{it}"),
+            ValueKind::Reference(instance) => match instance {
                 None => String::from("null"),
                 Some(v) => match &**v {
                     ViplObject::Arr(a) => {
-                        format!("{:?}", a.internal)
+                        format!("{:?}", a.internal.borrow())
                     }
                     ViplObject::Str(v) => {
                         format!("{:?}", v.string)
                     }
+                    ViplObject::Builder(b) => {
+                        format!("{:?}", b.buffer.borrow())
+                    }
+                    ViplObject::Map(m) => {
+                        format!("{:?}", m.internal.borrow())
+                    }
+                    ViplObject::Range(r) => {
+                        format!("{}..{}", r.start, r.end)
+                    }
+                    ViplObject::Gen(g) => {
+                        format!("Generator<{}>", g.typ.toString())
+                    }
+                    ViplObject::Chan(c) => {
+                        format!("Chan<{}>", c.typ.toString())
+                    }
+                    ViplObject::Task(_) => String::from("Task"),
                     ViplObject::Runtime(r) => {
                         format!("{:?}", r)
                     }
@@ -521,213 +907,205 @@ impl Value {
 
 impl Value {
     #[inline]
-    pub fn getNum(&self) -> isize {
-        match self {
-            Num(v) => *v,
-            _ => panic!(),
+    pub fn getNum(&self) -> Result<isize, VmError> {
+        if self.tag() != INT {
+            return Err(VmError::typeError("int", self));
         }
+        Ok((self.0 as isize) >> TAG_BITS)
     }
 
     #[inline]
-    pub fn getFlo(&self) -> f32 {
-        match self {
-            Flo(v) => *v,
-            _ => panic!(),
+    pub fn getFlo(&self) -> Result<f32, VmError> {
+        if self.tag() != FLOAT {
+            return Err(VmError::typeError("float", self));
         }
+        Ok(f32::from_bits(self.payload() as u32))
     }
 
     #[inline]
-    pub fn getRefFlo(&mut self) -> &mut f32 {
-        match self {
-            Flo(v) => v,
-            _ => panic!(),
+    pub fn getBool(&self) -> Result<bool, VmError> {
+        if self.tag() != BOOL {
+            return Err(VmError::typeError("bool", self));
         }
+        Ok(self.payload() != 0)
     }
 
     #[inline]
-    pub fn getRefNum(&mut self) -> &mut isize {
-        match self {
-            Num(v) => v,
-            _ => panic!(),
+    pub fn getChar(&self) -> Result<char, VmError> {
+        if self.tag() != CHAR {
+            return Err(VmError::typeError("char", self));
         }
+        Ok(unsafe { char::from_u32_unchecked(self.payload() as u32) })
     }
 
+    // caller checked that tag is OBJECT
     #[inline]
-    pub fn getRefBol(&mut self) -> &mut bool {
-        match self {
-            Bol(v) => v,
-            _ => panic!(),
-        }
+    unsafe fn reference(&self) -> &Option<Rc<ViplObject>> {
+        &*(&self.0 as *const u64 as *const Option<Rc<ViplObject>>)
     }
 
     #[inline]
-    pub fn getBool(&self) -> bool {
-        match self {
-            Bol(v) => *v,
-            _ => panic!(),
-        }
+    unsafe fn mutReference(&mut self) -> &mut Option<Rc<ViplObject>> {
+        &mut *(&mut self.0 as *mut u64 as *mut Option<Rc<ViplObject>>)
     }
 
     // FIXME c is sending some junk data fix ffi
     #[inline]
-    pub fn getReference(&self) -> &Option<Rc<ViplObject>> {
-        match self {
-            Reference { instance } => instance,
-            v => panic!("{:?}", v),
+    pub fn getReference(&self) -> Result<&Option<Rc<ViplObject>>, VmError> {
+        if self.tag() != OBJECT {
+            return Err(VmError::typeError("reference", self));
         }
+        Ok(unsafe { self.reference() })
     }
 
     #[inline]
-    pub fn getMutReference(&mut self) -> &mut Option<Rc<ViplObject>> {
-        match self {
-            Reference { instance } => instance,
-            v => panic!("{:?}", v),
+    pub fn getMutReference(&mut self) -> Result<&mut Option<Rc<ViplObject>>, VmError> {
+        if self.tag() != OBJECT {
+            return Err(VmError::typeError("reference", self));
         }
+        Ok(unsafe { self.mutReference() })
     }
 
     #[inline]
-    pub fn getReferenceValue(self) -> Option<Rc<ViplObject>> {
-        match self {
-            Reference { instance } => instance,
-            _ => panic!(),
+    pub fn getReferenceValue(self) -> Result<Option<Rc<ViplObject>>, VmError> {
+        if self.tag() != OBJECT {
+            return Err(VmError::typeError("reference", &self));
         }
+        // ownership of the rc moves out of the word
+        let v = ManuallyDrop::new(self);
+        Ok(unsafe { transmute_copy::<u64, Option<Rc<ViplObject>>>(&v.0) })
+    }
+
+    // non null reference
+    #[inline]
+    pub fn getObject(&self) -> Result<&Rc<ViplObject>, VmError> {
+        self.getReference()?.as_ref().ok_or(VmError::NullReference)
+    }
+
+    #[inline]
+    pub fn intoObject(self) -> Result<Rc<ViplObject>, VmError> {
+        self.getReferenceValue()?.ok_or(VmError::NullReference)
     }
 }
 
 impl Value {
     #[inline]
-    pub fn or(&mut self, val: &Value) {
-        let r = self.getRefBol();
-        *r = *r || val.getBool();
+    pub fn or(&mut self, val: &Value) -> Result<(), VmError> {
+        *self = Value::Bol(self.getBool()? || val.getBool()?);
+        Ok(())
     }
 
     #[inline]
-    pub fn and(&mut self, val: &Value) {
-        let r = self.getRefBol();
-        *r = *r && val.getBool();
+    pub fn and(&mut self, val: &Value) -> Result<(), VmError> {
+        *self = Value::Bol(self.getBool()? && val.getBool()?);
+        Ok(())
     }
 
     #[inline]
-    pub fn not(&mut self) {
-        let r = self.getRefBol();
-        *r = !*r;
+    pub fn not(&mut self) -> Result<(), VmError> {
+        *self = Value::Bol(!self.getBool()?);
+        Ok(())
     }
 }
 
 impl Value {
     #[inline]
-    pub fn gt(&self, val: &Value, typ: &DataType) -> bool {
-        match typ {
-            Int => self.getNum() > val.getNum(),
-            Float => self.getFlo() > val.getFlo(),
-            Bool => self.getBool() & !val.getBool(),
-            Object { .. } => panic!(),
-            Char => panic!(),
-        }
+    pub fn gt(&self, val: &Value, typ: &DataType) -> Result<bool, VmError> {
+        Ok(match typ {
+            Int => self.getNum()? > val.getNum()?,
+            Float => self.getFlo()? > val.getFlo()?,
+            Bool => self.getBool()? & !val.getBool()?,
+            Object { .. } => self.cmpObj(val)? == Ordering::Greater,
+            Char => self.getChar()? > val.getChar()?,
+        })
     }
 
     #[inline]
-    pub fn inc(&mut self, typ: &DataType) {
+    pub fn inc(&mut self, typ: &DataType) -> Result<(), VmError> {
         match typ {
-            Int => {
-                *self.getRefNum() += 1;
-            }
-            Float => {
-                *self.getRefFlo() += 1.;
-            }
-            _ => panic!(),
+            Int => *self = Value::checkedNum(self.getNum()? + 1)?,
+            Float => *self = Value::Flo(self.getFlo()? + 1.),
+            _ => return Err(VmError::typeError("int or float", self)),
         }
+        Ok(())
     }
 
     #[inline]
-    pub fn dec(&mut self, typ: &DataType) {
+    pub fn dec(&mut self, typ: &DataType) -> Result<(), VmError> {
         match typ {
-            Int => {
-                *self.getRefNum() -= 1;
-            }
-            Float => {
-                *self.getRefFlo() -= 1.;
-            }
-            _ => panic!(),
+            Int => *self = Value::checkedNum(self.getNum()? - 1)?,
+            Float => *self = Value::Flo(self.getFlo()? - 1.),
+            _ => return Err(VmError::typeError("int or float", self)),
         }
+        Ok(())
     }
 
     #[inline]
-    pub fn less(&self, val: &Value, typ: &DataType) -> bool {
-        match typ {
-            Int => self.getNum() < val.getNum(),
-            Float => self.getFlo() < val.getFlo(),
-            Bool => !self.getBool() & val.getBool(),
-            Object { .. } => panic!(),
-            Char => panic!(),
-        }
+    pub fn less(&self, val: &Value, typ: &DataType) -> Result<bool, VmError> {
+        Ok(match typ {
+            Int => self.getNum()? < val.getNum()?,
+            Float => self.getFlo()? < val.getFlo()?,
+            Bool => !self.getBool()? & val.getBool()?,
+            Object { .. } => self.cmpObj(val)? == Ordering::Less,
+            Char => self.getChar()? < val.getChar()?,
+        })
     }
 
     #[inline]
-    pub fn refLess(&mut self, val: &Value, typ: &DataType) {
-        let l = match typ {
-            Int => self.getNum() > val.getNum(),
-            Float => self.getFlo() > val.getFlo(),
-            Bool => self.getBool() & !val.getBool(),
-            Object { .. } => panic!(),
-            Char => panic!(),
-        };
-
-        *self = Bol(l)
+    pub fn refLess(&mut self, val: &Value, typ: &DataType) -> Result<(), VmError> {
+        *self = Value::Bol(self.gt(val, typ)?);
+        Ok(())
     }
 
     #[inline]
-    pub fn refGt(&mut self, val: &Value, typ: &DataType) {
-        let l = match typ {
-            Int => self.getNum() < val.getNum(),
-            Float => self.getFlo() < val.getFlo(),
-            Bool => !self.getBool() & val.getBool(),
-            Object { .. } => panic!(),
-            Char => panic!(),
-        };
+    pub fn refGt(&mut self, val: &Value, typ: &DataType) -> Result<(), VmError> {
+        *self = Value::Bol(self.less(val, typ)?);
+        Ok(())
+    }
 
-        *self = Bol(l)
+    #[inline]
+    pub fn eq(&self, val: &Value, typ: &DataType) -> Result<bool, VmError> {
+        Ok(match typ {
+            Int => self.getNum()? == val.getNum()?,
+            Float => self.getFlo()? == val.getFlo()?,
+            Bool => self.getBool()? == val.getBool()?,
+            Char => self.getChar()? == val.getChar()?,
+            Object { .. } => self.eqObj(val)?,
+        })
     }
 
     #[inline]
-    pub fn eq(&self, val: &Value, typ: &DataType) -> bool {
-        match typ {
-            Int => self.getNum() == val.getNum(),
-            Float => self.getFlo() == val.getFlo(),
-            Bool => self.getBool() == val.getBool(),
-            Char => self.getChar() == val.getChar(),
-            Object { .. } => panic!(),
-        }
+    pub fn refEq(&mut self, val: &Value, typ: &DataType) -> Result<(), VmError> {
+        *self = Value::Bol(self.eq(val, typ)?);
+        Ok(())
     }
 
+    // strings are compared by value, everything else by reference
     #[inline]
-    pub fn refEq(&mut self, val: &Value, typ: &DataType) {
-        let x = match typ {
-            Int => self.getNum() == val.getNum(),
-            Float => self.getFlo() == val.getFlo(),
-            Bool => self.getBool() == val.getBool(),
-            Char => self.getChar() == val.getChar(),
-            Object(a) => panic!("{:?}", a),
-        };
-        *self = Bol(x)
+    pub fn eqObj(&self, val: &Value) -> Result<bool, VmError> {
+        Ok(match (self.getReference()?, val.getReference()?) {
+            (Some(a), Some(b)) => match (&**a, &**b) {
+                (ViplObject::Str(a), ViplObject::Str(b)) => a.string == b.string,
+                _ => Rc::ptr_eq(a, b),
+            },
+            (None, None) => true,
+            _ => false,
+        })
     }
 
     #[inline]
-    pub fn getChar(&self) -> char {
-        match self {
-            Chr(c) => *c,
-            _ => panic!(),
-        }
+    pub fn cmpObj(&self, val: &Value) -> Result<Ordering, VmError> {
+        Ok(self.getString()?.cmp(val.getString()?))
     }
 
     #[inline]
     pub fn toDataType(&self) -> DataType {
-        match self {
-            Num(_) => Int,
-            Flo(_) => Float,
-            Bol(_) => Bool,
-            Chr(_) => Char,
-            Reference { instance: v } => match v {
+        match self.kind() {
+            ValueKind::Num(_) => Int,
+            ValueKind::Flo(_) => Float,
+            ValueKind::Bol(_) => Bool,
+            ValueKind::Chr(_) => Char,
+            ValueKind::Reference(v) => match v {
                 None => panic!(),
                 Some(v) => Object(ObjectMeta {
                     name: MyStr::Runtime(v.asObj().getName().into_boxed_str()),
@@ -738,125 +1116,117 @@ impl Value {
     }
 }
 
-#[repr(C)]
-struct RcBox<T: ?Sized> {
-    strong: Cell<usize>,
-    weak: Cell<usize>,
-    value: T,
+fn toChar(c: isize) -> Result<char, VmError> {
+    u32::try_from(c)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| VmError::InvalidOperation(format!("{c} is not a valid char")))
 }
 
 impl Value {
     #[inline]
-    pub fn add(&mut self, value: &Value, typ: &DataType) {
+    pub fn add(&mut self, value: &Value, typ: &DataType) -> Result<(), VmError> {
         match typ {
-            Int => {
-                *self.getRefNum() += value.getNum();
-            }
-            Float => {
-                *self.getRefFlo() += value.getFlo();
-            }
+            Int => *self = Value::checkedNum(self.getNum()? + value.getNum()?)?,
+            Float => *self = Value::Flo(self.getFlo()? + value.getFlo()?),
             Bool => {}
             Object(it) => {
                 match it.name.as_str() {
+                    // operands are never modified, result is new string
                     "String" => {
-                        match self {
-                            Reference { instance } => {
-                                match instance {
-                                    None => panic!(),
-                                    Some(v) => {
-                                        let str1 = &v.getStr().string;
-                                        let str2 = value.getString();
-
-                                        let mut buf = String::with_capacity(str1.len() + str2.len());
-
-                                        buf.push_str(str1);
-                                        buf.push_str(str2);
-
-                                        *v = Rc::new(ViplObject::Str(Str { string: buf }))
-                                    }
-                                }
-                            }
-                            _ => panic!()
-                        }
+                        let str1 = self.getString()?;
+                        let str2 = value.getString()?;
+
+                        let mut buf = String::with_capacity(str1.len() + str2.len());
+
+                        buf.push_str(str1);
+                        buf.push_str(str2);
+
+                        *self = Value::makeString(buf)
                     }
-                    _ => panic!()
+                    n => return Err(VmError::InvalidOperation(format!("cannot add {n}"))),
                 }
             }
-            Char => panic!(),
+            Char => *self = Value::Chr(toChar(self.getChar()? as isize + value.getNum()?)?),
         }
+        Ok(())
     }
 
     #[inline]
-    pub fn sub(&mut self, value: &Value, typ: &DataType) {
+    pub fn sub(&mut self, value: &Value, typ: &DataType) -> Result<(), VmError> {
         match typ {
-            Int => {
-                *self.getRefNum() -= value.getNum();
-            }
-            Float => {
-                *self.getRefFlo() -= value.getFlo();
-            }
+            Int => *self = Value::checkedNum(self.getNum()? - value.getNum()?)?,
+            Float => *self = Value::Flo(self.getFlo()? - value.getFlo()?),
             Bool => {}
             Object { .. } => {}
-            Char => panic!(),
+            // char - char is distance between them, char - int is another char
+            Char => match value.kind() {
+                ValueKind::Chr(c) => *self = Value::Num(self.getChar()? as isize - c as isize),
+                _ => *self = Value::Chr(toChar(self.getChar()? as isize - value.getNum()?)?),
+            },
         }
+        Ok(())
     }
 
     #[inline]
-    pub fn mul(&mut self, value: &Value, typ: &DataType) {
+    pub fn mul(&mut self, value: &Value, typ: &DataType) -> Result<(), VmError> {
         match typ {
             Int => {
-                *self.getRefNum() *= value.getNum();
-            }
-            Float => {
-                *self.getRefFlo() *= value.getFlo();
+                let res = self.getNum()?.checked_mul(value.getNum()?).ok_or(VmError::IntegerOverflow)?;
+                *self = Value::checkedNum(res)?
             }
+            Float => *self = Value::Flo(self.getFlo()? * value.getFlo()?),
             Bool => {}
             Object { .. } => {}
-            Char => panic!(),
+            Char => return Err(VmError::InvalidOperation(String::from("cannot multiply char"))),
         }
+        Ok(())
     }
 
     #[inline]
-    pub fn div(&mut self, value: &Value, typ: &DataType) {
+    pub fn div(&mut self, value: &Value, typ: &DataType) -> Result<(), VmError> {
         match typ {
             Int => {
-                *self.getRefNum() /= value.getNum();
-            }
-            Float => {
-                *self.getRefFlo() /= value.getFlo();
+                let d = value.getNum()?;
+                if d == 0 {
+                    return Err(VmError::DivisionByZero);
+                }
+                *self = Value::checkedNum(self.getNum()? / d)?
             }
+            Float => *self = Value::Flo(self.getFlo()? / value.getFlo()?),
             Bool => {}
             Object { .. } => {}
-            Char => panic!(),
+            Char => return Err(VmError::InvalidOperation(String::from("cannot divide char"))),
         }
+        Ok(())
     }
 
     #[inline]
-    pub fn f2i(&mut self) -> Value {
-        Num(self.getFlo() as isize)
+    pub fn f2i(&mut self) -> Result<Value, VmError> {
+        Value::checkedNum(self.getFlo()? as isize)
     }
 
     #[inline]
-    pub fn i2f(&mut self) -> Value {
-        Flo(self.getNum() as f32)
+    pub fn i2f(&mut self) -> Result<Value, VmError> {
+        Ok(Value::Flo(self.getNum()? as f32))
     }
 }
 
 impl Value {
     #[inline]
     pub fn isType(&self, typ: &DataType) -> bool {
-        match self {
-            Num(_) => {
+        match self.kind() {
+            ValueKind::Num(_) => {
                 matches!(typ, Int)
             }
-            Flo(_) => {
+            ValueKind::Flo(_) => {
                 matches!(typ, Float)
             }
-            Bol(_) => {
+            ValueKind::Bol(_) => {
                 matches!(typ, Bool)
             }
-            Reference { .. } => panic!(),
-            Chr(_) => matches!(typ, Char),
+            ValueKind::Reference(_) => matches!(typ, Object(_)),
+            ValueKind::Chr(_) => matches!(typ, Char),
         }
     }
 }
@@ -867,38 +1237,52 @@ pub struct StackFrame<'a> {
     pub localVariables: &'a mut [Value],
     pub name: Option<&'a str>,
     pub objects: Option<Vec<Rc<ViplObject>>>,
-}
-
-impl Drop for StackFrame<'_> {
-    fn drop(&mut self) {
-        if let Some(objects) = &self.objects {
-            for o in objects {
-                let raw = Rc::into_raw(o.clone());
-                unsafe {
-                    Rc::decrement_strong_count(raw);
-                    Rc::decrement_strong_count(raw);
-                }
-            }
-        }
-    }
+    // failed ffi accessor that only had access to the frame
+    pub pendingError: Option<VmError>,
 }
 
 impl StackFrame<'_> {
+    // handle given to native code, it is a gc root and stays valid until the native returns
     #[inline]
-    pub fn addObject(&mut self, obj: Rc<ViplObject>) {
+    pub fn addObject(&mut self, obj: Rc<ViplObject>) -> *mut ViplObject {
+        let ptr = Rc::as_ptr(&obj) as *mut ViplObject;
         match &mut self.objects {
             None => panic!(),
             Some(v) => v.push(obj)
         }
+        ptr
     }
 }
 
+// activation of script or native function on vm call stack, its locals start at base in vm.locals
+#[derive(Debug)]
+pub struct CallFrame {
+    // none for top level code
+    pub function: Option<usize>,
+    // opcode the frame executes, for callers their call, none for natives
+    pub index: Option<usize>,
+    pub base: usize,
+    // operand stack height when frame was entered
+    pub stackBase: usize,
+    // resumed generator that gets locals and stack back on yield
+    pub generator: Option<Rc<ViplObject>>,
+}
+
+// copy of call frame that outlives it
+#[derive(Debug, Clone)]
+pub struct FrameInfo {
+    pub name: String,
+    pub index: Option<usize>,
+    pub locals: Vec<Value>,
+}
+
 impl StackFrame<'_> {
     pub fn new(localVariables: &mut [Value]) -> StackFrame {
         StackFrame {
             localVariables,
             name: Option::from("root"),
             objects: None,
+            pendingError: None,
         }
     }
 }
@@ -920,7 +1304,7 @@ impl VirtualMachine {
         name: &str,
         returnType: Option<DataType>,
         args: Box<[VariableMetadata]>,
-    ) {
+    ) -> Result<(), VmError> {
         self.loadRawNative(path, name, returnType, args.len())
     }
 
@@ -930,16 +1314,16 @@ impl VirtualMachine {
         name: &str,
         returnType: Option<DataType>,
         argCount: usize,
-    ) {
-        let l = Library::new(path).unwrap();
+    ) -> Result<(), VmError> {
+        let l = Library::new(path).map_err(|e| VmError::NativeError(e.to_string()))?;
 
         self.nativeLibraries.push(l);
         let lib = self.nativeLibraries.last().unwrap();
 
         let a: Symbol<extern "C" fn(&mut VirtualMachine, &mut StackFrame) -> ()> =
-            lib.get(b"call\0").unwrap();
+            lib.get(b"call\0").map_err(|e| VmError::NativeError(e.to_string()))?;
 
-        self.functions.insert(
+        self.addFunction(
             MyStr::from(name.to_owned().into_boxed_str()),
             Func {
                 name: name.to_owned(),
@@ -951,10 +1335,11 @@ impl VirtualMachine {
                 },
             },
         );
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Func {
     pub name: String,
     pub returnType: Option<DataType>,
@@ -963,6 +1348,8 @@ pub struct Func {
     pub typ: FuncType,
 }
 
+pub type NativeCallback = fn(&mut VirtualMachine, &mut StackFrame) -> Result<(), VmError>;
+
 #[derive(Clone)]
 pub enum FuncType {
     Runtime {
@@ -970,7 +1357,7 @@ pub enum FuncType {
         rangeStop: usize,
     },
     Native {
-        callback: fn(&mut VirtualMachine, &mut StackFrame) -> (),
+        callback: NativeCallback,
     },
     Extern {
         callback: extern fn(&mut VirtualMachine, &mut StackFrame) -> (),
@@ -993,67 +1380,56 @@ impl Debug for FuncType {
     }
 }
 
-#[derive(Debug)]
-pub enum CachedOpCode {
-    CallCache {
-        locals: Box<[Value]>,
-        typ: FuncType,
-        argCount: usize,
-    },
-}
-
 #[repr(C)]
 #[derive(Debug)]
 pub struct VirtualMachine {
     pub nativeWrapper: NativeWrapper,
-    pub functions: HashMap<MyStr, Func>,
+    // dense table, linked calls index into it
+    pub functions: Vec<Func>,
+    pub functionIds: HashMap<MyStr, usize>,
     pub classes: HashMap<MyStr, ObjectDefinition>,
     pub stack: Vec<Value>,
-    pub opCodes: Vec<OpCode>,
-    pub opCodeCache: Vec<Option<CachedOpCode>>,
-    pub nativeLibraries: Vec<Library>
+    pub opCodes: Vec<LinkedOp>,
+    pub nativeLibraries: Vec<Library>,
+    // one shared string per literal, strings are immutable so sharing is not observable
+    pub constants: Vec<Value>,
+    pub constantIds: HashMap<MyStr, usize>,
+    // operand types of linked code
+    pub types: Vec<DataType>,
+    pub lines: LineTable,
+    // frames of running code, innermost last
+    pub callStack: Vec<CallFrame>,
+    // locals of all script frames, natives get their own
+    pub locals: Vec<Value>,
+    // frames active when last error was raised, innermost first
+    pub backtrace: Vec<FrameInfo>,
+    pub limits: Limits,
+    pub budget: Budget,
+    pub(crate) interrupt: InterruptHandle,
+    // error of ffi accessor, raised once the extern function returns
+    pub pendingError: Option<VmError>,
 }
 
 impl VirtualMachine {
     #[inline]
-    pub fn call(&mut self, name: MyStr) {
-        let f = self.functions.get(&name).unwrap();
-        let mut locals = vec![Num(-1); f.varTable.len()];
-
-        for i in 0..(f.argAmount) {
-            let arg = self.stack.pop().unwrap();
-            locals[(f.argAmount - 1) - i] = arg;
-        }
-
-        let mut stack = StackFrame {
-            localVariables: &mut locals,
-            name: None,
-            objects: None,
-        };
-
-        let t = f.typ.clone();
+    pub fn call(&mut self, name: MyStr) -> Result<(), VmError> {
+        let id = *self
+            .functionIds
+            .get(&name)
+            .ok_or_else(|| VmError::UnknownFunction(name.to_string()))?;
 
         // FIXME this is so much cursed
         // FIXME i am bypassing all rust safety guaranties :)
 
+        self.backtrace.clear();
         let ptr = self as *mut VirtualMachine;
 
-        match t {
-            Runtime {
-                rangeStart: s,
-                rangeStop: _e,
-            } => unsafe {
-                let mut seekable = SeekableOpcodes {
-                    index: s as isize,
-                    opCodes: &mut (*ptr).opCodes,
-                };
-                run(&mut seekable, &mut *ptr, &mut stack);
-            },
-            Native { callback } => callback(self, &mut stack),
-            Extern { callback } => {
-                stack.objects = Some(vec![]);
-                callback(self, &mut stack);
-            },
+        unsafe {
+            let mut seekable = SeekableOpcodes {
+                index: 0,
+                opCodes: &mut (*ptr).opCodes,
+            };
+            invoke(&mut seekable, &mut *ptr, id)
         }
     }
 }
@@ -1061,26 +1437,187 @@ impl VirtualMachine {
 impl VirtualMachine {
     pub fn new() -> Self {
         Self {
-            functions: Default::default(),
+            functions: vec![],
+            functionIds: Default::default(),
             stack: vec![],
             classes: Default::default(),
             opCodes: vec![],
-            opCodeCache: vec![],
             nativeWrapper: NativeWrapper::new(),
             nativeLibraries: vec![],
+            constants: vec![],
+            constantIds: Default::default(),
+            types: vec![],
+            lines: Default::default(),
+            callStack: vec![],
+            locals: vec![],
+            backtrace: vec![],
+            limits: Default::default(),
+            budget: Default::default(),
+            interrupt: Default::default(),
+            pendingError: None,
+        }
+    }
+
+    pub fn function(&self, encoded: &MyStr) -> Option<&Func> {
+        self.functionIds.get(encoded).map(|it| &self.functions[*it])
+    }
+
+    // redefinition keeps the index so already linked calls reach the new body
+    pub fn addFunction(&mut self, encoded: MyStr, f: Func) -> usize {
+        match self.functionIds.get(&encoded) {
+            Some(i) => {
+                self.functions[*i] = f;
+                *i
+            }
+            None => {
+                self.functions.push(f);
+                self.functionIds.insert(encoded, self.functions.len() - 1);
+                self.functions.len() - 1
+            }
+        }
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Result<Value, VmError> {
+        self.stack.pop().ok_or(VmError::StackUnderflow)
+    }
+
+    #[inline]
+    pub fn top(&mut self) -> Result<&mut Value, VmError> {
+        self.stack.last_mut().ok_or(VmError::StackUnderflow)
+    }
+
+    // top of stack together with type operand of linked instruction
+    #[inline]
+    fn topWithType(&mut self, typ: u32) -> Result<(&mut Value, &DataType), VmError> {
+        let t = &self.types[typ as usize];
+        Ok((self.stack.last_mut().ok_or(VmError::StackUnderflow)?, t))
+    }
+
+    // snapshot of call stack, innermost first
+    pub fn frames(&self) -> Vec<FrameInfo> {
+        let mut res = vec![];
+        let mut end = self.locals.len();
+        for it in self.callStack.iter().rev() {
+            res.push(FrameInfo {
+                name: match it.function {
+                    None => String::from("root"),
+                    Some(id) => self.functions[id].name.clone(),
+                },
+                index: it.index,
+                locals: self.locals[it.base..end].to_vec(),
+            });
+            end = it.base;
+        }
+        res
+    }
+
+    pub fn interruptHandle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    #[inline]
+    fn checkDepth(&self) -> Result<(), VmError> {
+        match self.limits.callDepth {
+            Some(max) if self.callStack.len() >= max => Err(VmError::LimitExceeded(Limit::CallDepth)),
+            _ => Ok(()),
+        }
+    }
+
+    // must be called before allocation whose size depends on script input
+    pub fn reserve(&self, bytes: usize) -> Result<(), VmError> {
+        self.budget.reserve(&self.limits, bytes)
+    }
+
+    // size comes from script, it is checked against heap limit before anything is allocated
+    fn arrayStorage(&self, size: isize) -> Result<Vec<Value>, VmError> {
+        if size < 0 {
+            return Err(VmError::InvalidOperation(format!("negative array size {size}")));
         }
+        let bytes = (size as usize)
+            .checked_mul(size_of::<Value>())
+            .ok_or_else(|| VmError::InvalidOperation(format!("array size {size} is too large")))?;
+        self.reserve(bytes)?;
+
+        let mut storage = vec![];
+        storage
+            .try_reserve_exact(size as usize)
+            .map_err(|_| VmError::InvalidOperation(format!("array size {size} is too large")))?;
+        Ok(storage)
+    }
+
+    #[inline]
+    fn pushFrame(&mut self, function: Option<usize>, index: Option<usize>) {
+        self.callStack.push(CallFrame {
+            function,
+            index,
+            base: self.locals.len(),
+            stackBase: self.stack.len(),
+            generator: None,
+        })
+    }
+
+    // drops frames above depth left by failed code, generators running in them can not be resumed
+    fn unwind(&mut self, depth: usize) {
+        let stackBase = self.callStack[depth - 1].stackBase;
+        for frame in self.callStack.drain(depth - 1..) {
+            if let Some(Ok(g)) = frame.generator.as_deref().map(ViplObject::getGen) {
+                let mut state = g.state.borrow_mut();
+                state.done = true;
+                state.current = None;
+            }
+        }
+        self.stack.truncate(stackBase);
+    }
+
+    // called by every unwound frame, only the innermost one takes the snapshot
+    fn recordBacktrace(&mut self, opCodes: &SeekableOpcodes) {
+        if !self.backtrace.is_empty() {
+            return;
+        }
+        if let Some(CallFrame { index: Some(i), .. }) = self.callStack.last_mut() {
+            *i = opCodes.index as usize - 1;
+        }
+        self.backtrace = self.frames();
+    }
+
+    pub fn functionReturns(&self) -> HashMap<MyStr, Option<DataType>> {
+        self.functionIds
+            .iter()
+            .map(|(k, i)| (k.clone(), self.functions[*i].returnType.clone()))
+            .collect()
+    }
+
+    pub fn internString(&mut self, s: &MyStr) -> usize {
+        if let Some(i) = self.constantIds.get(s) {
+            return *i;
+        }
+        let i = self.constants.len();
+        self.constants.push(Value::makeString(s.to_string()));
+        self.constantIds.insert(s.clone(), i);
+        i
+    }
+
+    // types are compared structurally, programs rarely have more than few dozen of them
+    pub fn internType(&mut self, t: &DataType) -> Result<u32, LinkError> {
+        if let Some(i) = self.types.iter().position(|it| it == t) {
+            return narrow(i, "types");
+        }
+        let i = narrow(self.types.len(), "types")?;
+        self.types.push(t.clone());
+        Ok(i)
     }
 
     pub fn makeNative(
         &mut self,
         name: String,
         args: Box<[VariableMetadata]>,
-        fun: fn(&mut VirtualMachine, &mut StackFrame) -> (),
+        fun: NativeCallback,
         ret: Option<DataType>,
     ) {
         let genName = genFunNameMeta(&name, &args, args.len());
         let l = args.len();
-        self.functions.insert(
+        self.addFunction(
             MyStr::Runtime(genName.into_boxed_str()),
             Func {
                 name,
@@ -1101,7 +1638,7 @@ impl VirtualMachine {
     ) {
         let genName = genFunNameMeta(&name, &args, args.len());
         let l = args.len();
-        self.functions.insert(
+        self.addFunction(
             MyStr::Runtime(genName.into_boxed_str()),
             Func {
                 name,
@@ -1135,17 +1672,16 @@ impl VirtualMachine {
             },
         };
 
-        self.functions
-            .insert(MyStr::Runtime(genName.into_boxed_str()), fun);
+        self.addFunction(MyStr::Runtime(genName.into_boxed_str()), fun);
     }
 }
 
-pub struct SeekableOpcodes<'a> {
+pub struct SeekableOpcodes<'a, T = LinkedOp> {
     pub index: isize,
-    pub opCodes: &'a mut [OpCode],
+    pub opCodes: &'a mut [T],
 }
 
-impl SeekableOpcodes<'_> {
+impl<T> SeekableOpcodes<'_, T> {
     #[inline]
     pub fn seek(&mut self, offset: isize) {
         // FIXME boundary check
@@ -1153,7 +1689,7 @@ impl SeekableOpcodes<'_> {
     }
 
     #[inline]
-    pub fn nextOpcode(&mut self) -> (Option<&mut OpCode>, usize) {
+    pub fn nextOpcode(&mut self) -> (Option<&mut T>, usize) {
         let n = self.opCodes.get_mut(self.index as usize);
         let i = self.index;
         self.index += 1;
@@ -1162,7 +1698,7 @@ impl SeekableOpcodes<'_> {
     }
 
     #[inline]
-    pub fn getOpcode(&self, index: usize) -> Option<&OpCode> {
+    pub fn getOpcode(&self, index: usize) -> Option<&T> {
         self.opCodes.get(index)
     }
 }
@@ -1203,343 +1739,628 @@ pub fn genFunNameMeta(name: &str, args: &[VariableMetadata], argsLen: usize) ->
     format!("{}({})", name, argsToStringMeta(&args[0..argsLen]))
 }
 
+// runs function called from rust, like natives calling back into script
+fn invoke(opCodes: &mut SeekableOpcodes, vm: &mut VirtualMachine, id: usize) -> Result<(), VmError> {
+    if vm.callStack.is_empty() {
+        vm.budget.start(&vm.limits);
+    }
+    let base = vm.locals.len();
+    if !enter(opCodes, vm, id)? {
+        return Ok(());
+    }
+    let depth = vm.callStack.len();
+    let res = dispatch(opCodes, vm, depth);
+    if res.is_err() {
+        vm.recordBacktrace(opCodes);
+        vm.unwind(depth);
+    }
+    vm.callStack.truncate(depth - 1);
+    vm.locals.truncate(base);
+    res
+}
+
+// pushes frame of script function and jumps to its code, natives run to completion and return false
+#[inline]
+fn enter(opCodes: &mut SeekableOpcodes, vm: &mut VirtualMachine, id: usize) -> Result<bool, VmError> {
+    let f = unsafe { vm.functions.get_unchecked(id) };
+    let argCount = f.argAmount;
+    let size = f.varTable.len();
+    let typ = f.typ.clone();
+    // function table is not modified while its code runs
+    let name = unsafe { &*(f.name.as_str() as *const str) };
+
+    let at = vm.stack.len().checked_sub(argCount).ok_or(VmError::StackUnderflow)?;
+    vm.checkDepth()?;
+
+    if let Runtime { rangeStart, .. } = typ {
+        vm.pushFrame(Some(id), Some(rangeStart));
+        vm.locals.extend(vm.stack.drain(at..));
+        vm.locals.resize(vm.locals.len() + size - argCount, Value::null());
+        opCodes.index = rangeStart as isize;
+        return Ok(true);
+    }
+
+    let mut locals = vm.stack.split_off(at);
+    vm.pushFrame(Some(id), None);
+    let mut stack = StackFrame {
+        localVariables: &mut locals,
+        name: Some(name),
+        objects: None,
+        pendingError: None,
+    };
+    let res = match typ {
+        Native { callback } => callback(vm, &mut stack),
+        Extern { callback } => {
+            stack.objects = Some(vec![]);
+            callback(vm, &mut stack);
+            match vm.pendingError.take().or(stack.pendingError.take()) {
+                Some(e) => Err(e),
+                None => Ok(()),
+            }
+        }
+        Runtime { .. } => unreachable!(),
+    };
+    if res.is_err() {
+        vm.recordBacktrace(opCodes);
+    }
+    vm.callStack.pop();
+    res.map(|_| false)
+}
+
+// pops returning frame and continues in its caller, none when it was the frame at depth
 #[inline]
-pub fn run(opCodes: &mut SeekableOpcodes, vm: &mut VirtualMachine, stackFrame: &mut StackFrame) {
+fn leave(opCodes: &mut SeekableOpcodes, vm: &mut VirtualMachine, depth: usize) -> Result<Option<usize>, VmError> {
+    let frame = unsafe { vm.callStack.pop().unwrap_unchecked() };
+    if vm.callStack.len() < depth {
+        return Ok(None);
+    }
+    vm.locals.truncate(frame.base);
+
+    if let Some(instance) = frame.generator {
+        let mut g = instance.getGen()?.state.borrow_mut();
+        g.current = None;
+        g.done = true;
+        vm.stack.truncate(frame.stackBase);
+        vm.stack.push(Value::Bol(false));
+    }
+
+    let caller = unsafe { vm.callStack.last().unwrap_unchecked() };
+    if let Some(i) = caller.index {
+        opCodes.index = i as isize + 1;
+    }
+    Ok(Some(caller.base))
+}
+
+// entry of script, on error vm.backtrace holds every active frame
+pub fn runTraced(
+    opCodes: &mut SeekableOpcodes,
+    vm: &mut VirtualMachine,
+    stackFrame: &mut StackFrame,
+) -> Result<(), VmError> {
+    vm.backtrace.clear();
+    run(opCodes, vm, stackFrame)
+}
+
+// runs code with top level locals of stackFrame, calls are executed on vm frame stack without recursion
+pub fn run(
+    opCodes: &mut SeekableOpcodes,
+    vm: &mut VirtualMachine,
+    stackFrame: &mut StackFrame,
+) -> Result<(), VmError> {
+    if vm.callStack.is_empty() {
+        let code = opCodes.opCodes.get(opCodes.index as usize..).unwrap_or(&[]);
+        checkTopLocals(code, stackFrame.localVariables.len())?;
+        vm.budget.start(&vm.limits);
+    }
+    let base = vm.locals.len();
+    vm.pushFrame(None, Some(opCodes.index as usize));
+    vm.locals.extend(stackFrame.localVariables.iter_mut().map(|it| mem::replace(it, Value::null())));
+    let depth = vm.callStack.len();
+
+    let res = dispatch(opCodes, vm, depth);
+    if res.is_err() {
+        vm.recordBacktrace(opCodes);
+        vm.unwind(depth);
+    }
+
+    vm.callStack.truncate(depth - 1);
+    for (local, v) in stackFrame.localVariables.iter_mut().zip(vm.locals.drain(base..)) {
+        *local = v;
+    }
+    res
+}
+
+// locals are accessed without bounds checks, function bodies are checked when module is loaded
+// but top level locals are known only here
+fn checkTopLocals(code: &[LinkedOp], locals: usize) -> Result<(), VmError> {
+    let mut inFunction = false;
+    for op in code {
+        let index = match *op {
+            LinkedOp::FunBegin => {
+                inFunction = true;
+                continue;
+            }
+            LinkedOp::FunEnd => {
+                inFunction = false;
+                continue;
+            }
+            LinkedOp::PushLocal { index } | LinkedOp::SetLocal { index } => index,
+            LinkedOp::Inc { index, .. } | LinkedOp::Dec { index, .. } => index as usize,
+            _ => continue,
+        };
+        if !inFunction && index >= locals {
+            return Err(VmError::InvalidOperation(format!("local {index} out of {locals} top level locals")));
+        }
+    }
+    Ok(())
+}
+
+// executes frames until the one at depth returns, it is left to the caller together with its locals
+fn dispatch(opCodes: &mut SeekableOpcodes, vm: &mut VirtualMachine, depth: usize) -> Result<(), VmError> {
+    // budget is counted in register, vm sees it only when natives may run
+    let mut fuel = vm.budget.fuel;
+    let res = dispatchLoop(opCodes, vm, depth, &mut fuel);
+    vm.budget.fuel = fuel;
+    res
+}
+
+#[inline(always)]
+fn dispatchLoop(
+    opCodes: &mut SeekableOpcodes,
+    vm: &mut VirtualMachine,
+    depth: usize,
+    fuel: &mut u64,
+) -> Result<(), VmError> {
+    use LinkedOp::*;
+
+    let mut base = unsafe { vm.callStack.get_unchecked(depth - 1) }.base;
     loop {
         let (op, index) = match opCodes.nextOpcode() {
             (None, _) => {
-                return;
+                return Ok(());
             }
-            (Some(v), i) => (v, i),
+            (Some(v), i) => (*v, i),
         };
+        if *fuel == 0 {
+            vm.budget.fuel = 0;
+            vm.budget.refuel(&vm.limits, &vm.interrupt, vm.stack.len())?;
+            *fuel = vm.budget.fuel;
+        }
+        *fuel -= 1;
+
         // println!("evaluating {:?}", op);
         match op {
             FunBegin => {
-                let mut index = opCodes.index as usize;
-                let name = match opCodes.getOpcode(index).unwrap() {
-                    FunName { name } => name,
-                    v => panic!("{v:?}"),
-                };
-                index += 1;
-                let (vars, argCount) = match opCodes.getOpcode(index).unwrap() {
-                    LocalVarTable { typ, argsCount } => (typ, argsCount),
-                    v => panic!("{v:?}"),
-                };
-                index += 1;
-                let ret = match opCodes.getOpcode(index).unwrap() {
-                    FunReturn { typ } => typ,
-                    v => panic!("{v:?}"),
-                };
-                index += 1;
-                let startIndex = index;
-
-                'a: loop {
-                    let peek = opCodes.getOpcode(index).unwrap();
-                    // println!("eee {:?}", peek);
-                    match peek {
-                        FunEnd => {
-                            index += 1;
-                            break 'a;
-                        }
-                        _ => {
-                            index += 1;
-                        }
-                    }
+                // functions are registered by link, execution only skips their bodies
+                while !matches!(opCodes.getOpcode(opCodes.index as usize), Some(FunEnd)) {
+                    opCodes.index += 1;
                 }
-
-                vm.makeRuntime(
-                    name.to_string(),
-                    vars.clone(),
-                    startIndex,
-                    *argCount,
-                    ret.clone(),
-                    0,
-                );
-                opCodes.index = index as isize;
+                opCodes.index += 1;
             }
             F2I => {
-                let mut x = vm.stack.pop().unwrap();
-                vm.stack.push(x.f2i())
+                let mut x = vm.pop()?;
+                vm.stack.push(x.f2i()?)
             }
             I2F => {
-                let mut x = vm.stack.pop().unwrap();
-                vm.stack.push(x.i2f())
+                let mut x = vm.pop()?;
+                vm.stack.push(x.i2f()?)
             }
-            PushInt(v) => vm.stack.push(Num(*v)),
-            PushFloat(v) => vm.stack.push(Flo(*v)),
-            PushBool(v) => vm.stack.push(Bol(*v)),
+            C2I => {
+                // char codes are at most 21 bits so they always fit
+                let x = vm.pop()?;
+                vm.stack.push(Value::Num(x.getChar()? as isize))
+            }
+            PushInt(v) => vm.stack.push(Value::checkedNum(v)?),
+            PushFloat(v) => vm.stack.push(Value::Flo(v)),
+            PushBool(v) => vm.stack.push(Value::Bol(v)),
             Pop => {
                 vm.stack.pop();
             }
-            Dup => unsafe {
-                let val = vm.stack.get_unchecked(vm.stack.len() - 1);
-                vm.stack.push(val.clone());
-            },
+            Dup => {
+                let val = vm.top()?.clone();
+                vm.stack.push(val);
+            }
             PushLocal { index } => {
-                // println!("{:?}", stackFrame.localVariables.get(*index));
-                // println!("loclas size {}", stackFrame.localVariables.len());
-                vm.stack
-                    .push(unsafe { stackFrame.localVariables.get_unchecked(*index) }.clone())
+                let v = unsafe { vm.locals.get_unchecked(base + index) }.clone();
+                vm.stack.push(v)
             }
-            SetLocal { index, typ: _ } => {
-                let x = vm.stack.pop().unwrap();
-                *unsafe { stackFrame.localVariables.get_unchecked_mut(*index) } = x;
-                // println!("{:?}", stackFrame.localVariables.get(*index));
-                // stackFrame.get_mut().localVariables.insert(*index, x);
+            SetLocal { index } => {
+                let x = vm.pop()?;
+                *unsafe { vm.locals.get_unchecked_mut(base + index) } = x;
             }
             Jmp { offset, jmpType } => match jmpType {
                 JmpType::One => {
-                    vm.stack.pop().unwrap();
-                    let _b = vm.stack.pop().unwrap();
-                    panic!()
+                    return Err(VmError::InvalidOperation(String::from("unsupported jump type One")))
                 }
                 JmpType::Zero => {}
-                JmpType::Jmp => {
-                    let x = *offset;
-                    opCodes.seek(x);
+                JmpType::Jmp => opCodes.seek(offset),
+                JmpType::Lt(t) => {
+                    let b = vm.pop()?;
+                    let a = vm.pop()?;
+                    if a.less(&b, &vm.types[t as usize])? {
+                        opCodes.seek(offset)
+                    }
                 }
-                JmpType::Gt => {
-                    let a = vm.stack.pop().unwrap();
-                    let b = vm.stack.pop().unwrap();
-                    if a.gt(&b, &DataType::Float) {
-                        let x = *offset;
-                        opCodes.seek(x)
+                JmpType::Le(t) => {
+                    let b = vm.pop()?;
+                    let a = vm.pop()?;
+                    if !a.gt(&b, &vm.types[t as usize])? {
+                        opCodes.seek(offset)
                     }
                 }
-                JmpType::Less => {
-                    let a = vm.stack.pop().unwrap();
-                    let b = vm.stack.pop().unwrap();
-                    if a.less(&b, &DataType::Float) {
-                        let x = *offset;
-                        opCodes.seek(x)
+                JmpType::Gt(t) => {
+                    let b = vm.pop()?;
+                    let a = vm.pop()?;
+                    if a.gt(&b, &vm.types[t as usize])? {
+                        opCodes.seek(offset)
                     }
                 }
-                JmpType::True => {
-                    let a = vm.stack.pop().unwrap();
-                    if a.getBool() {
-                        let x = *offset;
-                        opCodes.seek(x)
+                JmpType::Ge(t) => {
+                    let b = vm.pop()?;
+                    let a = vm.pop()?;
+                    if !a.less(&b, &vm.types[t as usize])? {
+                        opCodes.seek(offset)
                     }
                 }
-                JmpType::False => {
-                    let a = vm.stack.pop().unwrap();
-                    if !a.getBool() {
-                        let x = *offset;
-                        opCodes.seek(x)
+                JmpType::Eq(t) => {
+                    let b = vm.pop()?;
+                    let a = vm.pop()?;
+                    if a.eq(&b, &vm.types[t as usize])? {
+                        opCodes.seek(offset)
                     }
                 }
-            },
-            Call { encoded } => unsafe {
-                // println!("function call {}", encoded);
-                // println!("stack size {}", vm.stack.len());
-                let cached = match &vm.opCodeCache.get_unchecked(index) {
-                    Some(v) => match v {
-                        CachedOpCode::CallCache {
-                            locals: stack,
-                            typ,
-                            argCount,
-                        } => (stack, typ, argCount),
-                    },
-                    None => {
-                        // println!("{:?}", &vm.functions.keys());
-                        // println!("{}", encoded);
-                        let f = vm.functions.get(&encoded).expect(&format!("function {} not found", &encoded));
-                        // println!("meta {:?}", f.varTable);
-                        let localVars = vec![Value::Num(-1); f.varTable.len()]; // Vec::with_capacity(f.varTable.len());
-
-                        /*
-                        for i in &*f.varTable {
-                            localVars.push(i.typ.toDefaultValue())
-                        }
-                        */
-
-                        vm.opCodeCache[index] = Some(CachedOpCode::CallCache {
-                            locals: localVars.into(),
-                            typ: f.typ.clone(),
-                            argCount: f.argAmount,
-                        });
-                        match vm.opCodeCache.get_unchecked(index) {
-                            None => panic!(),
-                            Some(v) => match v {
-                                CachedOpCode::CallCache {
-                                    locals: ref stack,
-                                    ref typ,
-                                    ref argCount,
-                                } => (stack, typ, argCount),
-                            },
-                        }
+                JmpType::Ne(t) => {
+                    let b = vm.pop()?;
+                    let a = vm.pop()?;
+                    if !a.eq(&b, &vm.types[t as usize])? {
+                        opCodes.seek(offset)
                     }
-                };
-
-                let mut cahedLocals = cached.0.clone();
-
-                // println!("{}", encoded);
-                for i in 0..(*cached.2) {
-                    let arg = vm.stack.pop().unwrap();
-                    cahedLocals[(cached.2 - 1) - i] = arg;
                 }
-
-                let mut stack = StackFrame {
-                    localVariables: &mut cahedLocals,
-                    name: None,
-                    objects: None,
-                };
-
-                match cached.1 {
-                    Runtime {
-                        rangeStart: s,
-                        rangeStop: _e,
-                    } => {
-                        let old = index + 1;
-
-                        opCodes.index = *s as isize;
-                        run(opCodes, vm, &mut stack);
-                        opCodes.index = old as isize;
+                JmpType::True => {
+                    let a = vm.pop()?;
+                    if a.getBool()? {
+                        opCodes.seek(offset)
                     }
-                    Native { callback } => callback(vm, &mut stack),
-                    Extern { callback } => {
-                        stack.objects = Some(vec![]);
-                        callback(vm, &mut stack);
+                }
+                JmpType::False => {
+                    let a = vm.pop()?;
+                    if !a.getBool()? {
+                        opCodes.seek(offset)
                     }
                 }
             },
-            Return => return,
-            Add(v) => unsafe {
-                let a = vm.stack.pop().unwrap();
-                let l = vm.stack.len() - 1;
-                vm.stack.get_unchecked_mut(l).add(&a, v);
-            },
-            Sub(v) => unsafe {
-                let a = vm.stack.pop().unwrap();
-                let l = vm.stack.len() - 1;
-                vm.stack.get_unchecked_mut(l).sub(&a, v);
-            },
-            Div(v) => unsafe {
-                let a = vm.stack.pop().unwrap();
-                let l = vm.stack.len() - 1;
-                vm.stack.get_unchecked_mut(l).div(&a, v);
-            },
-            Mul(v) => unsafe {
-                let a = vm.stack.pop().unwrap();
-                let l = vm.stack.len() - 1;
-                // println!("aaa {:?}", vm.stack.get(l));
-                vm.stack.get_unchecked_mut(l).mul(&a, v);
-            },
-            Equals(v) => unsafe {
-                let a = vm.stack.pop().unwrap();
-                let l = vm.stack.len() - 1;
-                vm.stack.get_unchecked_mut(l).refEq(&a, v);
-            },
-            Greater(v) => unsafe {
-                let a = vm.stack.pop().unwrap();
-                let l = vm.stack.len() - 1;
-                vm.stack.get_unchecked_mut(l).refGt(&a, &v);
-            },
-            Less(v) => unsafe {
-                let a = vm.stack.pop().unwrap();
-                let l = vm.stack.len() - 1;
-                vm.stack.get_unchecked_mut(l).refLess(&a, v);
-            },
-            Or => unsafe {
-                let a = vm.stack.pop().unwrap();
-                let l = vm.stack.len() - 1;
-                vm.stack.get_unchecked_mut(l).or(&a);
-            },
-            And => unsafe {
-                let a = vm.stack.pop().unwrap();
-                let l = vm.stack.len() - 1;
-                vm.stack.get_unchecked_mut(l).and(&a);
-            },
-            Not => unsafe {
-                let l = vm.stack.len() - 1;
-                vm.stack.get_unchecked_mut(l).not();
+            Call { index: id } => {
+                if let Some(CallFrame { index: Some(i), .. }) = vm.callStack.last_mut() {
+                    *i = index;
+                }
+                vm.budget.fuel = *fuel;
+                let entered = enter(opCodes, vm, id);
+                *fuel = vm.budget.fuel;
+                if entered? {
+                    base = unsafe { vm.callStack.last().unwrap_unchecked() }.base;
+                }
+            }
+            TailCall { index: id } => {
+                // frame of tail called function takes place of the current one
+                let mut frame = unsafe { vm.callStack.pop().unwrap_unchecked() };
+                vm.locals.truncate(frame.base);
+                vm.budget.fuel = *fuel;
+                let entered = enter(opCodes, vm, id);
+                *fuel = vm.budget.fuel;
+                if entered? {
+                    // resumed generator finishes when the tail called function returns
+                    let new = unsafe { vm.callStack.last_mut().unwrap_unchecked() };
+                    new.stackBase = frame.stackBase;
+                    new.generator = frame.generator.take();
+                    base = new.base;
+                } else {
+                    // native is done so the current frame returns its result
+                    vm.callStack.push(frame);
+                    match leave(opCodes, vm, depth)? {
+                        None => return Ok(()),
+                        Some(b) => base = b,
+                    }
+                }
+            }
+            Return => match leave(opCodes, vm, depth)? {
+                None => return Ok(()),
+                Some(b) => base = b,
             },
+            Add(t) => {
+                let a = vm.pop()?;
+                if let Object(o) = &vm.types[t as usize] {
+                    if o.name.as_str() == "String" {
+                        let len = vm.top()?.getString()?.len().saturating_add(a.getString()?.len());
+                        vm.reserve(len)?
+                    }
+                }
+                let (top, t) = vm.topWithType(t)?;
+                top.add(&a, t)?
+            }
+            Sub(t) => {
+                let a = vm.pop()?;
+                let (top, t) = vm.topWithType(t)?;
+                top.sub(&a, t)?
+            }
+            Div(t) => {
+                let a = vm.pop()?;
+                let (top, t) = vm.topWithType(t)?;
+                top.div(&a, t)?
+            }
+            Mul(t) => {
+                let a = vm.pop()?;
+                let (top, t) = vm.topWithType(t)?;
+                top.mul(&a, t)?
+            }
+            Equals(t) => {
+                let a = vm.pop()?;
+                let (top, t) = vm.topWithType(t)?;
+                top.refEq(&a, t)?
+            }
+            Greater(t) => {
+                let a = vm.pop()?;
+                let (top, t) = vm.topWithType(t)?;
+                top.refGt(&a, t)?
+            }
+            Less(t) => {
+                let a = vm.pop()?;
+                let (top, t) = vm.topWithType(t)?;
+                top.refLess(&a, t)?
+            }
+            Or => {
+                let a = vm.pop()?;
+                vm.top()?.or(&a)?
+            }
+            And => {
+                let a = vm.pop()?;
+                vm.top()?.and(&a)?
+            }
+            Not => vm.top()?.not()?,
             ArrayNew(d) => {
-                // println!("{}", vm.stack.len());
-                let _size = vm.stack.pop().unwrap();
-                vm.stack.push(Value::Reference {
-                    instance: Some(Rc::new(
-                        crate::objects::Array {
-                            internal: vec![],
-                            typ: d.clone(),
-                        }
-                            .into(),
-                    )),
-                })
-            }
-            ArrayStore(_) => {
-                let index = vm.stack.pop().unwrap().getNum();
-                let val = vm.stack.pop().unwrap();
-                match vm.stack.pop().unwrap() {
-                    Reference { instance } => unsafe {
-                        let mut clon = instance.unwrap();
-                        let ne = Rc::get_mut_unchecked(&mut clon);
-                        let v = ne.getMutArr();
-                        if index as usize == v.internal.len() {
-                            v.internal.push(val)
-                        } else {
-                            v.internal[index as usize] = val
-                        }
-                    },
-                    _ => panic!(),
+                // size only reserves space, elements are pushed
+                let size = vm.pop()?.getNum()?;
+                let storage = vm.arrayStorage(size)?;
+                vm.stack.push(Value::makeArray(storage, vm.types[d as usize].clone()))
+            }
+            ArrayFill(d) => {
+                let fill = vm.pop()?;
+                let size = vm.pop()?.getNum()?;
+                let mut storage = vm.arrayStorage(size)?;
+                storage.resize(size as usize, fill);
+                vm.stack.push(Value::makeArray(storage, vm.types[d as usize].clone()))
+            }
+            ArrayPush => {
+                let val = vm.pop()?;
+                vm.pop()?.getObject()?.getArr()?.push(val)
+            }
+            ArrayStore => {
+                let index = vm.pop()?.getNum()?;
+                let val = vm.pop()?;
+                vm.pop()?.getObject()?.getArr()?.set(index, val)?
+            }
+            ArrayLoad => {
+                let index = vm.pop()?.getNum()?;
+                let clon = vm.pop()?.intoObject()?;
+                let v = match &*clon {
+                    ViplObject::Range(r) => Value::Num(r.get(index)?),
+                    e => e.getArr()?.get(index)?,
                 };
+                vm.stack.push(v)
             }
-            ArrayLoad(_) => {
-                let index = vm.stack.pop().unwrap().getNum();
-                match vm.stack.pop().unwrap() {
-                    Reference { instance } => {
-                        let clon = instance.unwrap();
-                        let e = clon.getArr();
-                        vm.stack.push(e.internal.get(index as usize).unwrap().clone())
-                    },
-                    _ => panic!(),
+            ArrayLength => {
+                let len = match &**vm.pop()?.getObject()? {
+                    ViplObject::Str(s) => s.string.len(),
+                    ViplObject::Range(r) => r.len(),
+                    v => v.getArr()?.len(),
                 };
+                vm.stack.push(Value::Num(len as isize));
             }
-            ArrayLength => match vm.stack.pop().unwrap().getReference() {
-                None => {}
-                Some(v) => {
-                    vm.stack.push(Num(v.getArr().internal.len() as isize));
-                }
-            },
-            Inc { typ, index } => unsafe {
-                stackFrame.localVariables.get_unchecked_mut(*index).inc(typ)
-            },
-            Dec { typ, index } => unsafe {
-                stackFrame.localVariables.get_unchecked_mut(*index).dec(typ)
-            },
-            PushChar(c) => vm.stack.push(Chr(*c)),
-            StrNew(s) => vm.stack.push(Value::makeString(s.clone().to_string())),
+            Inc { typ, index } => {
+                let local = unsafe { vm.locals.get_unchecked_mut(base + index as usize) };
+                local.inc(&vm.types[typ as usize])?
+            }
+            Dec { typ, index } => {
+                let local = unsafe { vm.locals.get_unchecked_mut(base + index as usize) };
+                local.dec(&vm.types[typ as usize])?
+            }
+            PushChar(c) => vm.stack.push(Value::Chr(c)),
+            StrNew { index } => vm.stack.push(unsafe { vm.constants.get_unchecked(index) }.clone()),
             GetChar => {
-                let index = vm.stack.pop().unwrap().getNum();
+                let index = vm.pop()?.getNum()?;
 
-                let opIndex = vm.stack.len()-1;
+                let r = vm.top()?;
+                let s = r.getString()?;
+                let c = match s.as_bytes().get(index as usize) {
+                    Some(c) if index >= 0 => *c as char,
+                    _ => return Err(VmError::IndexOutOfBounds { index, len: s.len() }),
+                };
+                *r = Value::Chr(c);
+            }
+            MapNew { key, value } => {
+                let (key, value) = (vm.types[key as usize].clone(), vm.types[value as usize].clone());
+                vm.stack.push(Value::makeMap(HashMap::new(), key, value))
+            }
+            MapStore => {
+                let key = vm.pop()?;
+                let val = vm.pop()?;
+                vm.pop()?.getObject()?.getMap()?.insert(&key, val)?
+            }
+            MapLoad => {
+                let key = vm.pop()?;
+                let clon = vm.pop()?.intoObject()?;
+                match clon.getMap()?.get(&key)? {
+                    None => return Err(VmError::KeyNotFound(key.valueStr())),
+                    Some(v) => vm.stack.push(v),
+                }
+            }
+            MapContains => {
+                let key = vm.pop()?;
+                let res = vm.pop()?.getObject()?.getMap()?.contains(&key)?;
+                vm.stack.push(Value::Bol(res))
+            }
+            MapRemove => {
+                let key = vm.pop()?;
+                vm.pop()?.getObject()?.getMap()?.remove(&key)?;
+            }
+            MapKeys(t) => {
+                let m = vm.pop()?;
+                let map = m.getObject()?.getMap()?;
+                vm.reserve(map.len() * size_of::<Value>())?;
+                let keys = map.keys();
+                vm.stack.push(Value::makeArray(keys, vm.types[t as usize].clone()))
+            }
+            RangeNew => {
+                let end = vm.pop()?.getNum()?;
+                let start = vm.pop()?.getNum()?;
+                vm.stack.push(Value::makeRange(start, end))
+            }
+            ArraySlice(t) => {
+                let range = vm.pop()?;
+                let arr = vm.pop()?;
+                let a = arr.getObject()?.getArr()?.internal.borrow();
+                let (start, end) = range.getObject()?.getRange()?.checkSlice(a.len())?;
+                vm.reserve((end - start) * size_of::<Value>())?;
+                vm.stack.push(Value::makeArray(a[start..end].to_vec(), vm.types[t as usize].clone()))
+            }
+            StrSlice => {
+                let range = vm.pop()?;
+                let s = vm.pop()?;
+                let st = s.getString()?;
+                let (start, end) = range.getObject()?.getRange()?.checkSlice(st.len())?;
+                let slice = st
+                    .get(start..end)
+                    .ok_or_else(|| VmError::InvalidOperation(format!("{start}..{end} is not on char boundary")))?;
+                vm.reserve(slice.len())?;
+                vm.stack.push(Value::makeString(slice.to_string()))
+            }
+            GenNew(t) => {
+                // body of generator starts after the Return that hands out the generator
+                let g = Generator {
+                    function: unsafe { vm.callStack.last().unwrap_unchecked() }.function,
+                    state: RefCell::new(GeneratorState {
+                        locals: vm.locals[base..].to_vec(),
+                        stack: vec![],
+                        index: index as isize + 2,
+                        current: None,
+                        done: false,
+                    }),
+                    typ: vm.types[t as usize].clone(),
+                };
+                vm.stack.push(Value::Reference(Some(ViplObject::alloc(g.into()))))
+            }
+            Yield => unsafe {
+                let frame = vm.callStack.pop().unwrap_unchecked();
+                let instance = frame
+                    .generator
+                    .ok_or_else(|| VmError::InvalidOperation(String::from("yield outside of generator")))?;
+                let mut g = instance.getGen()?.state.borrow_mut();
+                g.current = vm.stack.pop();
+                g.stack = vm.stack.split_off(frame.stackBase);
+                g.locals.extend(vm.locals.drain(frame.base..));
+                g.index = opCodes.index;
+                vm.stack.push(Value::Bol(true));
+
+                let caller = vm.callStack.last().unwrap_unchecked();
+                if let Some(i) = caller.index {
+                    opCodes.index = i as isize + 1;
+                }
+                base = caller.base;
+            },
+            GenResume => unsafe {
+                let instance = vm.pop()?.intoObject()?;
+                let gen = instance.getGen()?;
+                let mut g = gen.state.borrow_mut();
+                if g.done {
+                    vm.stack.push(Value::Bol(false));
+                    continue;
+                }
 
-                let r = vm.stack.get_mut(opIndex).unwrap();
-                let c = *r.getString().as_bytes().get(index as usize).unwrap() as char;
-                *r = Chr(c);
+                vm.checkDepth()?;
+                if let Some(CallFrame { index: Some(i), .. }) = vm.callStack.last_mut() {
+                    *i = index;
+                }
+                vm.pushFrame(gen.function, Some(g.index as usize));
+                vm.stack.append(&mut g.stack);
+                vm.locals.append(&mut g.locals);
+                opCodes.index = g.index;
+                drop(g);
+                let frame = vm.callStack.last_mut().unwrap_unchecked();
+                frame.generator = Some(instance);
+                base = frame.base;
+            },
+            GenValue => {
+                let instance = vm.pop()?.intoObject()?;
+                let current = instance.getGen()?.state.borrow().current.clone();
+                match current {
+                    Some(v) => vm.stack.push(v),
+                    None => return Err(VmError::InvalidOperation(String::from("generator is exhausted"))),
+                }
+            }
+            Spawn { index: id, argCount } => {
+                let base = vm.stack.len().checked_sub(argCount as usize).ok_or(VmError::StackUnderflow)?;
+                let args = vm.stack.drain(base..).map(|it| SendValue::copyFrom(&it)).collect::<Result<_, _>>()?;
+                vm.budget.fuel = *fuel;
+                let task = crate::isolate::spawn(opCodes.opCodes, vm, id as usize, args);
+                vm.stack.push(Value::Reference(Some(ViplObject::alloc(task.into()))))
+            }
+            Join => {
+                if let Some(v) = vm.pop()?.getObject()?.getTask()?.join(&vm.budget, &vm.interrupt)? {
+                    vm.stack.push(v)
+                }
+            }
+            ChanNew(t) => vm.stack.push(Value::makeChannel(Channel::new(vm.types[t as usize].clone()))),
+            ChanSend => {
+                let value = vm.pop()?;
+                let chan = vm.pop()?.intoObject()?;
+                chan.getChan()?.send(&value)?
             }
-            o => panic!("unimplemented opcode {:?}", o)
+            ChanRecv => {
+                let chan = vm.pop()?.intoObject()?;
+                let v = chan.getChan()?.recv(&vm.budget, &vm.interrupt)?;
+                vm.stack.push(v)
+            }
+            FunEnd => return Err(VmError::InvalidOperation(String::from("unimplemented opcode FunEnd"))),
+            Declaration(code) => return Err(VmError::InvalidOperation(format!("unimplemented opcode {code:?}"))),
         }
     }
 }
 
 impl VirtualMachine {
-    pub fn eval(&mut self, mut bytecode: Vec<OpCode>, locals: Vec<DataType>) {
+    pub fn eval(&mut self, bytecode: Vec<OpCode>, locals: Vec<DataType>) -> Result<(), VmError> {
+        let mut code = vec![];
+        link(self, &bytecode, &mut code)?;
         let mut vals = vec![];
         for b in &locals {
             vals.push(b.toDefaultValue())
         }
-        for _ in &bytecode {
-            self.opCodeCache.push(None);
-        }
-        run(
+        let mut frame = StackFrame::new(&mut vals);
+        runTraced(
             &mut SeekableOpcodes {
                 index: 0,
-                opCodes: &mut bytecode,
+                opCodes: &mut code,
             },
             self,
-            &mut StackFrame::new(&mut vals),
-        );
+            &mut frame,
+        )
+    }
+
+    // error followed by every frame that was active when it was raised
+    pub fn describeError(&self, err: &VmError) -> String {
+        let mut msg = err.to_string();
+        msg.push_str(&self.describeFrames(&self.backtrace));
+        msg
+    }
+
+    pub fn describeFrames(&self, frames: &[FrameInfo]) -> String {
+        let mut msg = String::new();
+        for frame in frames {
+            let position = match frame.index {
+                None => String::from("native"),
+                Some(i) => self.lines.position(i),
+            };
+            msg.push_str(&format!("\n    at {position} in {}", frame.name));
+        }
+        msg
     }
 }
 
@@ -1549,45 +2370,252 @@ impl Drop for VirtualMachine {
     }
 }
 
-pub fn evaluateBytecode(mut bytecode: Vec<OpCode>, locals: Vec<DataType>) -> VirtualMachine {
+#[derive(Debug)]
+pub enum LinkError {
+    UnresolvedFunctions(Vec<String>),
+    // operand does not fit into linked instruction
+    TooLarge(&'static str),
+    // function header at given index is not the one genFunctionDef emits
+    MalformedFunction(usize),
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkError::UnresolvedFunctions(names) => write!(f, "unresolved functions: {}", names.join(", ")),
+            LinkError::TooLarge(what) => write!(f, "too many {what} to link"),
+            LinkError::MalformedFunction(index) => write!(f, "malformed function at {index}"),
+        }
+    }
+}
+
+impl Error for LinkError {}
+
+impl From<LinkError> for VmError {
+    fn from(value: LinkError) -> Self {
+        match value {
+            LinkError::UnresolvedFunctions(names) => VmError::UnknownFunction(names.join(", ")),
+            e => VmError::InvalidOperation(e.to_string()),
+        }
+    }
+}
+
+fn narrow<T: TryFrom<usize>>(value: usize, what: &'static str) -> Result<T, LinkError> {
+    T::try_from(value).map_err(|_| LinkError::TooLarge(what))
+}
+
+// header emitted by genFunctionDef, returns function and index of its FunEnd
+fn readFunction(ops: &[OpCode], begin: usize) -> Result<(MyStr, Func, usize), LinkError> {
+    let (name, vars, argCount, ret) = match ops.get(begin + 1..begin + 4) {
+        Some([FunName { name }, LocalVarTable { typ, argsCount }, FunReturn { typ: ret }]) => {
+            (name, typ, *argsCount, ret)
+        }
+        _ => return Err(LinkError::MalformedFunction(begin)),
+    };
+    if argCount > vars.len() {
+        return Err(LinkError::MalformedFunction(begin));
+    }
+    let end = match ops[begin + 1..].iter().position(|it| matches!(it, FunBegin | FunEnd)) {
+        Some(i) if matches!(ops[begin + 1 + i], FunEnd) => begin + 1 + i,
+        _ => return Err(LinkError::MalformedFunction(begin)),
+    };
+
+    let encoded = MyStr::from(genFunNameMeta(name.as_str(), vars, argCount));
+    let f = Func {
+        name: name.to_string(),
+        returnType: ret.clone(),
+        varTable: vars.clone(),
+        argAmount: argCount,
+        typ: Runtime {
+            rangeStart: begin + 4,
+            rangeStop: end,
+        },
+    };
+    Ok((encoded, f, end))
+}
+
+// registers functions defined in ops[code.len()..] and appends their executable form to code
+// nothing is registered when linking fails
+pub fn link(vm: &mut VirtualMachine, ops: &[OpCode], code: &mut Vec<LinkedOp>) -> Result<(), LinkError> {
+    let start = code.len();
+    let mut defined = vec![];
+    let mut i = start;
+    while i < ops.len() {
+        if let FunBegin = ops[i] {
+            let (encoded, f, end) = readFunction(ops, i)?;
+            defined.push((encoded, f));
+            i = end;
+        }
+        i += 1;
+    }
+
+    let mut missing = vec![];
+    for op in &ops[start..] {
+        if let Call { encoded } | TailCall { encoded } | Spawn { encoded, .. } = op {
+            let known = vm.functionIds.contains_key(encoded) || defined.iter().any(|it| it.0 == *encoded);
+            if !known && !missing.contains(&encoded.to_string()) {
+                missing.push(encoded.to_string());
+            }
+        }
+    }
+    if !missing.is_empty() {
+        return Err(LinkError::UnresolvedFunctions(missing));
+    }
+
+    // indices new functions get once they are registered
+    let mut ids = HashMap::new();
+    for (encoded, _) in &defined {
+        if !vm.functionIds.contains_key(encoded) && !ids.contains_key(encoded) {
+            ids.insert(encoded.clone(), vm.functions.len() + ids.len());
+        }
+    }
+    let linked = ops[start..]
+        .iter()
+        .map(|op| linkOp(vm, &ids, op))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (encoded, f) in defined {
+        vm.addFunction(encoded, f);
+    }
+    code.extend(linked);
+
+    Ok(())
+}
+
+fn linkOp(vm: &mut VirtualMachine, ids: &HashMap<MyStr, usize>, op: &OpCode) -> Result<LinkedOp, LinkError> {
+    let function = |vm: &VirtualMachine, encoded: &MyStr| *vm.functionIds.get(encoded).unwrap_or_else(|| &ids[encoded]);
+
+    Ok(match op {
+        FunBegin => LinkedOp::FunBegin,
+        FunEnd => LinkedOp::FunEnd,
+        FunName { .. } => LinkedOp::Declaration(RawOpCode::FunName),
+        FunReturn { .. } => LinkedOp::Declaration(RawOpCode::FunReturn),
+        LocalVarTable { .. } => LinkedOp::Declaration(RawOpCode::LocalVarTable),
+        ClassBegin => LinkedOp::Declaration(RawOpCode::ClassBegin),
+        ClassName { .. } => LinkedOp::Declaration(RawOpCode::ClassName),
+        ClassField { .. } => LinkedOp::Declaration(RawOpCode::ClassField),
+        ClassEnd => LinkedOp::Declaration(RawOpCode::ClassEnd),
+        New { .. } => LinkedOp::Declaration(RawOpCode::New),
+        GetField { .. } => LinkedOp::Declaration(RawOpCode::GetField),
+        SetField { .. } => LinkedOp::Declaration(RawOpCode::SetField),
+        F2I => LinkedOp::F2I,
+        I2F => LinkedOp::I2F,
+        C2I => LinkedOp::C2I,
+        PushInt(v) => LinkedOp::PushInt(*v),
+        PushFloat(v) => LinkedOp::PushFloat(*v),
+        PushBool(v) => LinkedOp::PushBool(*v),
+        PushChar(v) => LinkedOp::PushChar(*v),
+        Pop => LinkedOp::Pop,
+        Dup => LinkedOp::Dup,
+        PushLocal { index } => LinkedOp::PushLocal { index: *index },
+        SetLocal { index, .. } => LinkedOp::SetLocal { index: *index },
+        Jmp { offset, jmpType } => LinkedOp::Jmp {
+            offset: *offset,
+            jmpType: jmpType.tryMap(|t| vm.internType(t))?,
+        },
+        Call { encoded } => LinkedOp::Call {
+            index: function(vm, encoded),
+        },
+        TailCall { encoded } => LinkedOp::TailCall {
+            index: function(vm, encoded),
+        },
+        Return => LinkedOp::Return,
+        Add(t) => LinkedOp::Add(vm.internType(t)?),
+        Sub(t) => LinkedOp::Sub(vm.internType(t)?),
+        Div(t) => LinkedOp::Div(vm.internType(t)?),
+        Mul(t) => LinkedOp::Mul(vm.internType(t)?),
+        Equals(t) => LinkedOp::Equals(vm.internType(t)?),
+        Greater(t) => LinkedOp::Greater(vm.internType(t)?),
+        Less(t) => LinkedOp::Less(vm.internType(t)?),
+        Or => LinkedOp::Or,
+        And => LinkedOp::And,
+        Not => LinkedOp::Not,
+        ArrayNew(t) => LinkedOp::ArrayNew(vm.internType(t)?),
+        ArrayStore(_) => LinkedOp::ArrayStore,
+        ArrayLoad(_) => LinkedOp::ArrayLoad,
+        ArrayLength => LinkedOp::ArrayLength,
+        ArrayFill(t) => LinkedOp::ArrayFill(vm.internType(t)?),
+        ArrayPush(_) => LinkedOp::ArrayPush,
+        Inc { typ, index } => LinkedOp::Inc {
+            typ: vm.internType(typ)?,
+            index: narrow(*index, "locals")?,
+        },
+        Dec { typ, index } => LinkedOp::Dec {
+            typ: vm.internType(typ)?,
+            index: narrow(*index, "locals")?,
+        },
+        StrNew(s) => LinkedOp::StrNew {
+            index: vm.internString(s),
+        },
+        GetChar => LinkedOp::GetChar,
+        MapNew { key, value } => LinkedOp::MapNew {
+            key: vm.internType(key)?,
+            value: vm.internType(value)?,
+        },
+        MapStore(_) => LinkedOp::MapStore,
+        MapLoad(_) => LinkedOp::MapLoad,
+        MapContains => LinkedOp::MapContains,
+        MapRemove => LinkedOp::MapRemove,
+        MapKeys(t) => LinkedOp::MapKeys(vm.internType(t)?),
+        RangeNew => LinkedOp::RangeNew,
+        ArraySlice(t) => LinkedOp::ArraySlice(vm.internType(t)?),
+        StrSlice => LinkedOp::StrSlice,
+        GenNew(t) => LinkedOp::GenNew(vm.internType(t)?),
+        Yield => LinkedOp::Yield,
+        GenResume => LinkedOp::GenResume,
+        GenValue(_) => LinkedOp::GenValue,
+        Spawn { encoded, argCount } => LinkedOp::Spawn {
+            index: narrow(function(vm, encoded), "functions")?,
+            argCount: narrow(*argCount, "arguments")?,
+        },
+        Join => LinkedOp::Join,
+        ChanNew(t) => LinkedOp::ChanNew(vm.internType(t)?),
+        ChanSend => LinkedOp::ChanSend,
+        ChanRecv(_) => LinkedOp::ChanRecv,
+    })
+}
+
+pub fn evaluateBytecode(bytecode: Vec<OpCode>, locals: Vec<DataType>) -> VirtualMachine {
     let mut vals = vec![];
     for b in &locals {
         vals.push(b.toDefaultValue())
     }
     let mut vm = bootStrapVM();
-    for _ in &bytecode {
-        vm.opCodeCache.push(None);
-    }
-    run(
+    let mut code = vec![];
+    link(&mut vm, &bytecode, &mut code).unwrap_or_else(|e| panic!("{e}"));
+    let res = runTraced(
         &mut SeekableOpcodes {
             index: 0,
-            opCodes: &mut bytecode,
+            opCodes: &mut code,
         },
         &mut vm,
         &mut StackFrame::new(&mut vals),
     );
+    if let Err(e) = res {
+        panic!("{}", vm.describeError(&e))
+    }
 
     vm
 }
 
 pub fn evaluateBytecode2(
-    mut bytecode: Vec<OpCode>,
+    bytecode: Vec<OpCode>,
     locals: Vec<DataType>,
     vm: &mut VirtualMachine,
-) {
+) -> Result<(), VmError> {
+    let mut code = vec![];
+    link(vm, &bytecode, &mut code)?;
     let mut vals = vec![];
     for b in &locals {
         vals.push(b.toDefaultValue())
     }
-    for _ in &bytecode {
-        vm.opCodeCache.push(None);
-    }
-    run(
+    let mut frame = StackFrame::new(&mut vals);
+    runTraced(
         &mut SeekableOpcodes {
             index: 0,
-            opCodes: &mut bytecode,
+            opCodes: &mut code,
         },
         vm,
-        &mut StackFrame::new(&mut vals),
-    );
+        &mut frame,
+    )
 }
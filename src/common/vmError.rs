@@ -0,0 +1,54 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::limits::Limit;
+use crate::vm::Value;
+
+// error of running script, frames active when it was raised are recorded in vm.backtrace
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmError {
+    TypeError { expected: String, got: String },
+    IndexOutOfBounds { index: isize, len: usize },
+    NullReference,
+    StackUnderflow,
+    UnknownFunction(String),
+    DivisionByZero,
+    IntegerOverflow,
+    KeyNotFound(String),
+    NativeError(String),
+    InvalidOperation(String),
+    LimitExceeded(Limit),
+    Interrupted,
+}
+
+impl VmError {
+    pub fn typeError(expected: &str, got: &Value) -> VmError {
+        VmError::TypeError {
+            expected: expected.to_string(),
+            got: got.typeName(),
+        }
+    }
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmError::TypeError { expected, got } => write!(f, "expected {expected} got {got}"),
+            VmError::IndexOutOfBounds { index, len } => {
+                write!(f, "index {index} out of bounds for length {len}")
+            }
+            VmError::NullReference => write!(f, "null reference"),
+            VmError::StackUnderflow => write!(f, "stack underflow"),
+            VmError::UnknownFunction(name) => write!(f, "function {name} not found"),
+            VmError::DivisionByZero => write!(f, "division by zero"),
+            VmError::IntegerOverflow => write!(f, "integer overflow"),
+            VmError::KeyNotFound(key) => write!(f, "key {key} not found in map"),
+            VmError::NativeError(msg) => write!(f, "{msg}"),
+            VmError::InvalidOperation(msg) => write!(f, "{msg}"),
+            VmError::LimitExceeded(limit) => write!(f, "{limit} limit exceeded"),
+            VmError::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+impl Error for VmError {}
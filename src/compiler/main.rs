@@ -1,13 +1,14 @@
 extern crate rust_vm;
 
-use std::collections::HashMap;
 use std::error::Error;
+use std::path::Path;
 use std::rc::Rc;
 use std::time::Instant;
 
 use rust_vm::codegen::bytecodeGen2;
 use rust_vm::fs::setupFs;
 use rust_vm::lexer::tokenizeSource;
+use rust_vm::lints::{lintProgram, LintConfig};
 use rust_vm::parser::parseTokens;
 use rust_vm::std::bootStrapVM;
 use rust_vm::vm::evaluateBytecode2;
@@ -21,16 +22,12 @@ fn main() {
     let now = Instant::now();
     let sourceFile = std::env::args().nth(1).expect("expected source field");
 
-    let src = std::fs::read_to_string(sourceFile).expect("failed to read source");
+    let src = std::fs::read_to_string(&sourceFile).expect("failed to read source");
 
     let mut vm = bootStrapVM();
     // let mut localTypes = vec![];
     setupFs(&mut vm);
-    let mut functionReturns = HashMap::new();
-
-    for f in &vm.functions {
-        functionReturns.insert(f.0.clone(), f.1.returnType.clone());
-    }
+    let mut functionReturns = vm.functionReturns();
 
     let tokens = match tokenizeSource(&src) {
         Ok(v) => v,
@@ -56,14 +53,23 @@ fn main() {
         }
     };
 
-    // println!("{:?}", &vm.functions.keys());
-
-    let mut rets = HashMap::new();
+    let lintConfig = match LintConfig::forSource(Path::new(&sourceFile)) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("lints");
+            handleError(e);
+            return;
+        }
+    };
 
-    for f in &vm.functions {
-        rets.insert(f.0.clone(), f.1.returnType.clone());
+    for warning in lintProgram(&ast, &src, &lintConfig) {
+        eprintln!("{warning}");
     }
 
+    // println!("{:?}", &vm.functions.keys());
+
+    let mut rets = vm.functionReturns();
+
     let bs = match bytecodeGen2(ast, &mut rets) {
         Ok(v) => v,
         Err(e) => {
@@ -96,7 +102,13 @@ fn main() {
 
     let a = Instant::now();
 
-    evaluateBytecode2(bs.0, bs.1, &mut vm);
+    vm.lines = bs.2;
+    vm.lines.file = Some(sourceFile);
+
+    if let Err(e) = evaluateBytecode2(bs.0, bs.1, &mut vm) {
+        eprintln!("ERROR: {}", vm.describeError(&e));
+        return;
+    }
 
     let elapsed = a.elapsed();
     println!("finished in: {elapsed:.2?}");
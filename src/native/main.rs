@@ -1,12 +1,13 @@
 extern crate rust_vm;
 
-use std::collections::HashMap;
 use std::error::Error;
+use std::path::Path;
 use std::fs;
 
 use rust_vm::cGen::{bytecodeGen2, statementFi};
 use rust_vm::fs::setupFs;
 use rust_vm::lexer::tokenizeSource;
+use rust_vm::lints::{lintProgram, LintConfig};
 use rust_vm::parser::parseTokens;
 use rust_vm::std::bootStrapVM;
 
@@ -18,16 +19,12 @@ fn handleError(err: Box<dyn Error>) {
 fn main() {
     let sourceFile = std::env::args().nth(1).expect("expected source field");
 
-    let src = std::fs::read_to_string(sourceFile).expect("failed to read source");
+    let src = std::fs::read_to_string(&sourceFile).expect("failed to read source");
     // let src = "fn a() { print(1) }";
     let mut vm = bootStrapVM();
     // let mut localTypes = vec![];
     setupFs(&mut vm);
-    let mut functionReturns = HashMap::new();
-
-    for f in &vm.functions {
-        functionReturns.insert(f.0.clone(), f.1.returnType.clone());
-    }
+    let mut functionReturns = vm.functionReturns();
 
     let tokens = match tokenizeSource(&src) {
         Ok(v) => v,
@@ -53,15 +50,24 @@ fn main() {
         }
     };
 
+    let lintConfig = match LintConfig::forSource(Path::new(&sourceFile)) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("lints");
+            handleError(e);
+            return;
+        }
+    };
+
+    for warning in lintProgram(&ast, &src, &lintConfig) {
+        eprintln!("{warning}");
+    }
+
     // println!("{:?}", ast);
 
     // println!("{:?}", &vm.functions.keys());
 
-    let mut rets = HashMap::new();
-
-    for f in &vm.functions {
-        rets.insert(f.0.clone(), f.1.returnType.clone());
-    }
+    let mut rets = vm.functionReturns();
 
     // println!("{:?}", rets);
 
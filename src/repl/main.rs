@@ -12,7 +12,7 @@ use rust_vm::lexer::tokenizeSource;
 use rust_vm::parser::{parse, parseOne, parsingUnits, TokenProvider};
 use rust_vm::parser::ParsingUnitSearchType::{Ahead, Back};
 use rust_vm::std::bootStrapVM;
-use rust_vm::vm::{run, SeekableOpcodes, StackFrame};
+use rust_vm::vm::{link, runTraced, SeekableOpcodes, StackFrame};
 
 fn readInput() -> String {
     print!(">>> ");
@@ -37,19 +37,16 @@ fn main() {
     let mut vm = bootStrapVM();
     setupFs(&mut vm);
     let mut localTypes = vec![];
-    let mut functionReturns = HashMap::new();
+    let mut functionReturns = vm.functionReturns();
     let mut mainLocals = HashMap::new();
     let mut localValues = vec![];
     let mut lastLocalSize: usize = 0;
     let mut opcodeIndex: usize = 0;
     let mut opcodes = vec![];
+    let mut code = vec![];
     let parsingUnits = parsingUnits();
     let mut structs = HashMap::new();
 
-    for f in &vm.functions {
-        functionReturns.insert(f.0.clone(), f.1.returnType.clone());
-    }
-
     println!("VIPL-repl");
     println!("(vasuf insejn programing language)");
     println!("to exit type ^C or EXIT");
@@ -154,25 +151,37 @@ fn main() {
 
          */
 
-        for _ in 0..bs.len() {
-            vm.opCodeCache.push(None)
-        }
+        let start = opcodes.len();
+        opcodes.extend(bs.0);
 
-        opcodes.extend(bs);
+        if let Err(e) = link(&mut vm, &opcodes, &mut code) {
+            eprintln!("linker");
+            handleError(Box::new(e));
+            opcodes.truncate(start);
+            continue;
+        }
+        vm.lines.append(bs.1, start);
 
         let mut stack = StackFrame {
             localVariables: &mut localValues,
             name: None,
             objects: None,
+            pendingError: None,
         };
 
         let mut opCodes = SeekableOpcodes {
             index: opcodeIndex as isize,
-            opCodes: &mut opcodes,
+            opCodes: &mut code,
         };
 
-        run(&mut opCodes, &mut vm, &mut stack);
-        opcodeIndex = opCodes.index as usize - 1;
+        opcodeIndex = match runTraced(&mut opCodes, &mut vm, &mut stack) {
+            Ok(_) => opCodes.index as usize - 1,
+            Err(e) => {
+                eprintln!("ERROR: {}", vm.describeError(&e));
+                // rest of failed input is skipped
+                opCodes.opCodes.len()
+            }
+        };
 
         for val in &vm.stack {
             println!("{}", val.valueStr())